@@ -1,24 +1,156 @@
-t_trace daemon run >/dev/null 2>&1
+# The script may be sourced again in the same shell, e.g. by `source ~/.bashrc`. The functions are
+# simply redefined then, but the shell is announced, and the traps and PROMPT_COMMAND set up, only
+# the first time: a second EXIT trap would chain to itself.
+if [[ -z "$t_trace_initialized" ]]; then
+  t_trace daemon run >/dev/null 2>&1
+  t_trace daemon session-begin --timeout "${T_TRACE_HOOK_TIMEOUT:-50ms}" "$BASHPID" >/dev/null 2>&1
+fi
 
-# Define the hook function to run before a command executes.
+# Define the hook function to run before a command executes. Bash runs it before every simple
+# command, e.g. once per stage of a pipeline, but a command line is tracked as one unit: only the
+# first command run after the prompt reports it.
 t_trace_preexec() {
-  # Guard Clause: Do not track t_trace's own commands.
-  if [[ "$BASH_COMMAND" == t_trace* ]]; then
+  # Guard Clause: Do not track t_trace's own commands, nor those run from PROMPT_COMMAND.
+  if [[ "$BASH_COMMAND" == t_trace* || -z "$t_trace_at_prompt" ]]; then
     return
   fi
+  t_trace_at_prompt=
+
+  t_trace_report_jobs
+
+  # The whole command line is taken from the history. Lines the shell kept out of its history
+  # (e.g. with HISTCONTROL=ignorespace) are sent with a leading space, so the daemon can ignore
+  # them as well.
+  local command
+  if t_trace_kept_out_of_history; then
+    command=" $BASH_COMMAND"
+  else
+    command=$(HISTTIMEFORMAT= builtin history 1)
+    command="${command#*[0-9]  }"
+    if [[ "$command" == t_trace* ]]; then
+      return
+    fi
+  fi
 
   # This is extremely fast and guarantees the message command-start is sent before the command runs.
-  t_trace daemon command-begin "$BASHPID" "$BASH_COMMAND" >/dev/null 2>&1
+  # A daemon that does not take it within the deadline is given up on, so the shell never waits.
+  t_trace daemon command-begin --timeout "${T_TRACE_HOOK_TIMEOUT:-50ms}" --cwd "$PWD" \
+    "$BASHPID" "$command" >/dev/null 2>&1
+}
+
+# Succeeds if the command line being run was not added to the history.
+t_trace_kept_out_of_history() {
+  # HISTCMD only advances past the number it had at the prompt if the line was saved.
+  if (( HISTCMD >= ${t_trace_prompt_histcmd:-0} )); then
+    return 1
+  fi
+
+  # With ignoredups, a repeated command is not saved either. Only then is a subshell needed.
+  if [[ "$HISTCONTROL" == *ignoredups* || "$HISTCONTROL" == *ignoreboth* ]]; then
+    local previous
+    previous=$(HISTTIMEFORMAT= builtin history 1)
+    previous="${previous#*[0-9]  }"
+    [[ "$previous" != "$BASH_COMMAND"* ]]
+    return
+  fi
+  return 0
+}
+
+# Background jobs whose end is still to be reported, by their PID.
+if [[ -z "$t_trace_initialized" ]]; then
+  t_trace_jobs=()
+  t_trace_last_job=$!
+fi
+
+# Succeeds if the command line run last started a background job, and tells the daemon so. `$!`
+# changes whenever the shell starts a background job, but also for a process substitution such as
+# `diff <(ls a) <(ls b)`, whose process never enters the job table.
+t_trace_note_background() {
+  if [[ "$!" == "$t_trace_last_job" ]]; then
+    return 1
+  fi
+  t_trace_last_job=$!
+  if [[ " $(t_trace_job_pids) " != *" $! "* ]]; then
+    return 1
+  fi
+  t_trace_jobs+=("$!")
+  t_trace daemon command-background --timeout "${T_TRACE_HOOK_TIMEOUT:-50ms}" "$BASHPID" "$!" \
+    >/dev/null 2>&1
+}
+
+# Prints the PIDs of all processes in the shell's job table, e.g. of every stage of a background
+# pipeline. `jobs -l` puts each after the job number, or first on the lines of further stages.
+t_trace_job_pids() {
+  jobs -l | awk '{ print ($1 ~ /^\[/) ? $2 : $1 }' | tr '\n' ' '
 }
 
-# Define the hook function to run after a command has finished.
+# Reports the background jobs that ended. A job is only waited for once the shell announced its end
+# and dropped it from the job table, so that the shell's own "Done" message is not lost.
+t_trace_report_jobs() {
+  if (( ${#t_trace_jobs[@]} == 0 )); then
+    return
+  fi
+
+  local running=" $(t_trace_job_pids) " job status remaining=()
+  for job in "${t_trace_jobs[@]}"; do
+    if [[ "$running" == *" $job "* ]] || kill -0 "$job" 2>/dev/null; then
+      remaining+=("$job")
+      continue
+    fi
+    wait "$job" 2>/dev/null
+    status=$?
+    t_trace daemon command-end --timeout "${T_TRACE_HOOK_TIMEOUT:-50ms}" --job "$job" \
+      "$BASHPID" "$status" 2>/dev/null
+  done
+  t_trace_jobs=("${remaining[@]}")
+}
+
+# Define the hook function to run after a command has finished. It must run first in
+# PROMPT_COMMAND, while `$?` and `PIPESTATUS` still describe the command line.
 t_trace_precmd() {
-  local exit_code=$?
+  local exit_code=$? pipe_status=("${PIPESTATUS[@]}")
+  t_trace_at_prompt=
+
+  # Only a command line that ended with a pipeline has the exit codes of its stages sent.
+  local stages=() joined
+  if (( ${#pipe_status[@]} > 1 )); then
+    printf -v joined '%s,' "${pipe_status[@]}"
+    stages=(--pipe-status "${joined%,}")
+  fi
 
-  # This guarantees the command-end message is sent before the next prompt is drawn.
-  t_trace daemon command-end "$BASHPID" "$exit_code" >/dev/null 2>&1
+  # This guarantees the command-end message is sent before the next prompt is drawn. Its output is
+  # kept: for long-running commands it may be a bell or a notification escape sequence. A command
+  # line that started a background job is tracked as that job, and ends with it.
+  if ! t_trace_note_background; then
+    t_trace daemon command-end --timeout "${T_TRACE_HOOK_TIMEOUT:-50ms}" "${stages[@]}" \
+      "$BASHPID" "$exit_code" 2>/dev/null
+  fi
+  t_trace_report_jobs
+
+  t_trace_prompt_histcmd=$HISTCMD
+}
+
+# Runs last in PROMPT_COMMAND: the next command starts a new command line.
+t_trace_prompt_ready() {
+  t_trace_at_prompt=1
 }
 
-# Register the functions with Bash's execution hooks.
+# Tell the daemon the shell is exiting, then run whatever EXIT trap was set before this script.
+t_trace_session_end() {
+  t_trace daemon session-end --timeout "${T_TRACE_HOOK_TIMEOUT:-50ms}" "$BASHPID" >/dev/null 2>&1
+}
+if [[ -z "$t_trace_initialized" ]]; then
+  t_trace_exit_trap=$(trap -p EXIT)
+  if [[ -n "$t_trace_exit_trap" ]]; then
+    t_trace_exit_trap="${t_trace_exit_trap#trap -- }"
+    eval "t_trace_exit_trap=${t_trace_exit_trap% EXIT}"
+  fi
+  trap 't_trace_session_end; eval "$t_trace_exit_trap"' EXIT
+
+  # Newlines keep the user's PROMPT_COMMAND intact even if it ends with a `;`.
+  export PROMPT_COMMAND="t_trace_precmd"$'\n'"$PROMPT_COMMAND"$'\n'"t_trace_prompt_ready"
+  t_trace_initialized=1
+fi
+
+# Register the preexec function with Bash's execution hooks.
 trap 't_trace_preexec' DEBUG
-export PROMPT_COMMAND="t_trace_precmd; $PROMPT_COMMAND"
@@ -0,0 +1,84 @@
+//! Charts drawn with unicode block characters, for printing straight to the terminal.
+
+use std::time::Duration;
+
+/// Sparkline levels, lowest first.
+const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Partial blocks ending a bar, from one to seven eighths of a character.
+const EIGHTHS: [char; 7] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+/// One character per duration, from `▁` for the shortest to `█` for the longest.
+/// Durations that are all the same are drawn at mid-height.
+pub fn sparkline(durations: &[Duration]) -> String {
+    let (Some(min), Some(max)) = (durations.iter().min(), durations.iter().max()) else {
+        return String::new();
+    };
+    let range = (*max - *min).as_secs_f64();
+    let top = (LEVELS.len() - 1) as f64;
+
+    durations
+        .iter()
+        .map(|duration| {
+            if range == 0.0 {
+                return LEVELS[LEVELS.len() / 2 - 1];
+            }
+            let position = (*duration - *min).as_secs_f64() / range;
+            LEVELS[(position * top).round() as usize]
+        })
+        .collect()
+}
+
+/// A horizontal bar of `value / max` times `width` characters, to an eighth of a character.
+/// A non-zero value always gets at least a sliver.
+pub fn bar(value: u64, max: u64, width: usize) -> String {
+    if max == 0 || value == 0 {
+        return String::new();
+    }
+    let eighths = (u128::from(value) * width as u128 * 8 / u128::from(max)).max(1) as usize;
+    let mut bar = "█".repeat(eighths / 8);
+    let remainder = eighths % 8;
+    if remainder > 0 {
+        bar.push(EIGHTHS[remainder - 1]);
+    }
+    bar
+}
+
+/// A histogram bucket bound in its largest whole unit, e.g. `512ms`, `4s` or `2m`.
+pub fn format_bound(bound: Duration) -> String {
+    let millis = bound.as_millis();
+    match millis {
+        0..1_000 => format!("{}ms", millis),
+        1_000..60_000 => format!("{}s", millis / 1_000),
+        60_000..3_600_000 => format!("{}m", millis / 60_000),
+        _ => format!("{}h", millis / 3_600_000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparkline_spans_shortest_to_longest() {
+        let durations = [1, 5, 3, 9].map(Duration::from_secs);
+        assert_eq!(sparkline(&durations), "▁▅▃█");
+        assert_eq!(sparkline(&[Duration::from_secs(2); 3]), "▄▄▄");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn bars_are_scaled_to_eighths() {
+        assert_eq!(bar(10, 10, 4), "████");
+        assert_eq!(bar(5, 10, 3), "█▌");
+        assert_eq!(bar(1, 1_000, 4), "▏");
+        assert_eq!(bar(0, 10, 4), "");
+    }
+
+    #[test]
+    fn bounds_use_the_largest_whole_unit() {
+        assert_eq!(format_bound(Duration::from_millis(512)), "512ms");
+        assert_eq!(format_bound(Duration::from_millis(4_096)), "4s");
+        assert_eq!(format_bound(Duration::from_millis(131_072)), "2m");
+    }
+}
@@ -1,4 +1,11 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use crate::config::SortKey;
+use crate::daemon::StorageKind;
+use crate::export::ExportFormat;
+
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// A high-performance command-line statistics tracker.
 #[derive(Parser, Debug)]
@@ -7,6 +14,9 @@ use clap::{Parser, Subcommand, ValueEnum};
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+    /// Use this config file instead of `$XDG_CONFIG_HOME/t_trace/config.toml`.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -16,10 +26,121 @@ pub enum Commands {
     /// Manage the t_trace background daemon process.
     Daemon(DaemonArgs),
     /// Display aggregated command statistics.
-    Stats {
-        /// Optionally filter the output to show only commands containing this phrase (case-insensitive).
-        #[arg(short = 'g', long = "grep")]
-        filter: Option<String>,
+    Stats(StatsArgs),
+    /// Open a live dashboard of the statistics and the commands running right now.
+    Top {
+        /// How often to refresh from the daemon (e.g. 500ms, 5s).
+        #[arg(short, long, value_parser = parse_positive_duration, default_value = "2s")]
+        interval: Duration,
+    },
+    /// Show how the duration of a single command trended over its recent runs.
+    Show {
+        /// The exact command, as shown by `t_trace stats`.
+        command: String,
+        /// Number of recent runs drawn in the sparkline.
+        #[arg(short = 'n', long, default_value_t = 60)]
+        runs: usize,
+        /// Include runs interrupted with Ctrl-C (SIGINT) or SIGTERM in the sparkline and mean time.
+        #[arg(long)]
+        include_interrupted: bool,
+    },
+    /// List commands whose last run was much slower than their recent runs.
+    Regressions,
+    /// List shell sessions with their command counts and how long they were busy or idle.
+    Sessions,
+    /// Print commands starting and finishing, and other daemon events, as they happen.
+    Watch {
+        /// Print each event as a line of JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect the configuration file.
+    Config(ConfigArgs),
+    /// Delete the statistics of a single command, e.g. one containing a typo or a secret.
+    Forget {
+        /// The exact command, as shown by `t_trace stats`.
+        command: String,
+    },
+    /// Delete the statistics of stale, rarely used commands.
+    #[command(group(ArgGroup::new("criteria").required(true).multiple(true)))]
+    Prune {
+        /// Only delete commands last run longer ago than this (e.g. 90d, 12w).
+        #[arg(long, value_parser = parse_duration, group = "criteria")]
+        older_than: Option<Duration>,
+        /// Only delete commands run fewer times than this.
+        #[arg(long, group = "criteria")]
+        min_count: Option<u64>,
+    },
+    /// Write all tracked statistics to standard output.
+    Export {
+        /// Output format.
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+    },
+    /// Load statistics from a file produced by `export`.
+    Import {
+        /// File to import.
+        file: PathBuf,
+        /// Input format. Guessed from the file extension when omitted.
+        #[arg(short, long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Add the imported statistics to the existing ones (default).
+        #[arg(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Discard all existing statistics before importing.
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct StatsArgs {
+    #[command(subcommand)]
+    pub command: Option<StatsCommands>,
+    /// Optionally filter the output to show only commands containing this phrase (case-insensitive).
+    #[arg(short = 'g', long = "grep")]
+    pub filter: Option<String>,
+    /// Column to sort by; the largest values are shown last [default: from config, else total].
+    #[arg(short, long, value_enum)]
+    pub sort: Option<SortKey>,
+    /// Show only this many commands, those sorted last [default: from config, else all].
+    #[arg(short = 'n', long)]
+    pub limit: Option<usize>,
+    /// Show only failing commands with their fail rate, most common exit codes and last failure.
+    #[arg(long)]
+    pub failures: bool,
+    /// Count runs interrupted with Ctrl-C (SIGINT) or SIGTERM in the total and mean times.
+    #[arg(long)]
+    pub include_interrupted: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommands,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// Print the effective configuration, including defaults, as TOML.
+    Show,
+    /// Print the location of the configuration file.
+    Path,
+    /// Check that the configuration file is valid.
+    Validate,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StatsCommands {
+    /// Delete the statistics of all commands, or of the commands matching a pattern.
+    #[command(group(ArgGroup::new("scope").required(true)))]
+    Reset {
+        /// Delete the statistics of every command.
+        #[arg(long, group = "scope")]
+        all: bool,
+        /// Delete the statistics of commands containing this phrase (case-insensitive).
+        #[arg(long = "match", value_name = "PATTERN", group = "scope")]
+        pattern: Option<String>,
     },
 }
 
@@ -44,17 +165,56 @@ pub struct DaemonArgs {
 #[derive(Subcommand, Debug)]
 pub enum DaemonCommands {
     /// Start the daemon process in the background.
-    Run,
+    Run {
+        /// Time after which a command that never reported its end is recorded as abandoned (e.g. 30m, 12h, 7d) [default: 24h].
+        #[arg(long, value_parser = parse_positive_duration)]
+        in_flight_ttl: Option<Duration>,
+        /// How often the daemon saves changed stats in the background (e.g. 30s, 5m) [default: 1m].
+        #[arg(long, value_parser = parse_positive_duration)]
+        autosave_interval: Option<Duration>,
+        /// Backend used to persist the stats. Existing JSON stats are imported into SQLite on first start [default: sqlite].
+        #[arg(long, value_enum)]
+        storage: Option<StorageKind>,
+        /// Additional regular expression to redact from commands before they are stored. Can be repeated.
+        #[arg(long = "redact", value_name = "REGEX", value_parser = Regex::new)]
+        redact_patterns: Vec<Regex>,
+        /// Command to ignore when it matches exactly (e.g. `ls`, `clear`). Can be repeated.
+        #[arg(long = "ignore", value_name = "COMMAND")]
+        ignore_commands: Vec<String>,
+        /// Ignore commands starting with this prefix (e.g. `cd `). Can be repeated.
+        #[arg(long = "ignore-prefix", value_name = "PREFIX")]
+        ignore_prefixes: Vec<String>,
+        /// Ignore commands matching this regular expression. Can be repeated.
+        #[arg(long = "ignore-regex", value_name = "REGEX", value_parser = Regex::new)]
+        ignore_patterns: Vec<Regex>,
+        /// Only track commands matching this regular expression. Can be repeated.
+        #[arg(long = "only", value_name = "REGEX", value_parser = Regex::new)]
+        only_patterns: Vec<Regex>,
+        /// Also track commands the shell kept out of its history (e.g. starting with a space).
+        #[arg(long)]
+        track_space_prefixed: bool,
+        /// Do not record commands finishing faster than this (e.g. 500ms, 2s) [default: 0s].
+        #[arg(long, value_parser = parse_duration)]
+        min_duration: Option<Duration>,
+    },
     /// Stop the daemon process gracefully.
     Stop,
     /// Check if the daemon process is running and responsive.
     HealthCheck,
+    /// Show what the daemon is doing and any problems it recovered from.
+    Status,
     /// Notify the daemon process that a command is beginning.
     CommandBegin {
         #[arg()]
         pid: u32,
         #[arg()]
         command: String,
+        /// Working directory the command runs in, passed on to hooks.
+        #[arg(long)]
+        cwd: Option<PathBuf>,
+        /// Give up if the daemon has not taken the message within this, and note the drop.
+        #[arg(long, value_parser = parse_duration, default_value = "50ms")]
+        timeout: Duration,
     },
     /// Notify the daemon process that a command has ended.
     CommandEnd {
@@ -62,5 +222,169 @@ pub enum DaemonCommands {
         pid: u32,
         #[arg()]
         exit_code: i32,
+        /// The background job that ended, as passed to `command-background`.
+        #[arg(long)]
+        job: Option<u32>,
+        /// Exit codes of the stages of the pipeline the command line ended with (`PIPESTATUS`).
+        #[arg(long, value_delimiter = ',')]
+        pipe_status: Vec<i32>,
+        /// Give up if the daemon has not answered within this, and note the drop.
+        #[arg(long, value_parser = parse_duration, default_value = "50ms")]
+        timeout: Duration,
+    },
+    /// Notify the daemon process that the command begun last went to the background.
+    CommandBackground {
+        #[arg()]
+        pid: u32,
+        /// Identifies the job when it ends, e.g. its PID (`$!`).
+        #[arg()]
+        job: u32,
+        /// Give up if the daemon has not taken the message within this, and note the drop.
+        #[arg(long, value_parser = parse_duration, default_value = "50ms")]
+        timeout: Duration,
+    },
+    /// Notify the daemon process that a shell started.
+    SessionBegin {
+        #[arg()]
+        pid: u32,
+        /// Give up if the daemon has not taken the message within this, and note the drop.
+        #[arg(long, value_parser = parse_duration, default_value = "50ms")]
+        timeout: Duration,
+    },
+    /// Notify the daemon process that a shell is exiting.
+    SessionEnd {
+        #[arg()]
+        pid: u32,
+        /// Give up if the daemon has not taken the message within this, and note the drop.
+        #[arg(long, value_parser = parse_duration, default_value = "50ms")]
+        timeout: Duration,
     },
 }
+
+/// Parses a human-friendly duration such as `500ms`, `30s`, `15m`, `12h`, `90d` or `2w`.
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let split_at = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split_at);
+
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid duration: '{}'", input))?;
+    let seconds_per_unit = match unit {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Unknown duration unit '{}' in '{}'", unit, input)),
+    };
+
+    amount
+        .checked_mul(seconds_per_unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Duration is too large: '{}'", input))
+}
+
+/// Like [`parse_duration`], for intervals and time limits, which must not be zero.
+pub fn parse_positive_duration(input: &str) -> Result<Duration, String> {
+    match parse_duration(input)? {
+        Duration::ZERO => Err(format!("Duration must not be zero: '{}'", input.trim())),
+        duration => Ok(duration),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_supports_all_units() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(43_200)));
+        assert_eq!(parse_duration("90d"), Ok(Duration::from_secs(7_776_000)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(1_209_600)));
+    }
+
+    #[test]
+    fn removal_commands_require_a_scope() {
+        assert!(Cli::try_parse_from(["t_trace", "stats", "reset"]).is_err());
+        assert!(
+            Cli::try_parse_from(["t_trace", "stats", "reset", "--all", "--match", "x"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["t_trace", "stats", "reset", "--match", "git"]).is_ok());
+        assert!(Cli::try_parse_from(["t_trace", "prune"]).is_err());
+        assert!(
+            Cli::try_parse_from([
+                "t_trace",
+                "prune",
+                "--older-than",
+                "90d",
+                "--min-count",
+                "2"
+            ])
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn command_end_takes_the_exit_codes_of_pipeline_stages() {
+        let cli = Cli::try_parse_from([
+            "t_trace",
+            "daemon",
+            "command-end",
+            "--pipe-status",
+            "0,141,0",
+            "42",
+            "0",
+        ])
+        .unwrap();
+        let Commands::Daemon(DaemonArgs {
+            command: DaemonCommands::CommandEnd {
+                pid, pipe_status, ..
+            },
+        }) = cli.command
+        else {
+            panic!("not parsed as command-end");
+        };
+        assert_eq!((pid, pipe_status), (42, vec![0, 141, 0]));
+        assert!(
+            Cli::try_parse_from([
+                "t_trace",
+                "daemon",
+                "command-end",
+                "--pipe-status",
+                "0,x",
+                "42",
+                "0"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn intervals_and_time_limits_must_not_be_zero() {
+        assert_eq!(parse_positive_duration("1ms"), Ok(Duration::from_millis(1)));
+        assert!(parse_positive_duration("0").is_err());
+        assert!(parse_positive_duration("0ms").is_err());
+        for option in ["--autosave-interval", "--in-flight-ttl"] {
+            assert!(Cli::try_parse_from(["t_trace", "daemon", "run", option, "0s"]).is_err());
+        }
+        assert!(Cli::try_parse_from(["t_trace", "top", "--interval", "0"]).is_err());
+        assert!(Cli::try_parse_from(["t_trace", "daemon", "run", "--min-duration", "0s"]).is_ok());
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid_input() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("10y").is_err());
+        assert!(parse_duration("-5s").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use super::{ClientError, ClientOptions, ClientRequest};
+
+use crate::dto::DaemonEvent;
+use crate::protocol::Request;
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Lines, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Instant;
+
+/// The same as [`Client`](super::Client), for tools without an async runtime.
+#[derive(Debug, Clone, Default)]
+pub struct BlockingClient {
+    options: ClientOptions,
+}
+
+/// Events streamed by the daemon after subscribing, as an iterator that blocks for each event.
+pub struct BlockingSubscription {
+    lines: Lines<BufReader<UnixStream>>,
+}
+
+impl Iterator for BlockingSubscription {
+    type Item = Result<DaemonEvent, ClientError>;
+
+    /// Waits for the next event. Ends once the daemon closed the connection.
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.lines.next()?;
+        Some(
+            line.map_err(ClientError::from)
+                .and_then(|line| serde_json::from_str(&line).map_err(ClientError::from)),
+        )
+    }
+}
+
+impl BlockingClient {
+    /// A client of the daemon of the current user, with the default timeout.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: ClientOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn send<R: ClientRequest>(&self, request: R) -> Result<R::Response, ClientError> {
+        let deadline = Deadline::start(&self.options);
+        let mut stream = self.connect()?;
+        deadline.apply(&stream)?;
+        deadline.check(stream.write_all(format!("{}\n", request.into_request()).as_bytes()))?;
+        if !R::EXPECTS_RESPONSE {
+            return R::parse_response("");
+        }
+
+        let mut response = Vec::new();
+        let mut buffer = [0; 8 * 1024];
+        loop {
+            deadline.apply(&stream)?;
+            let read = deadline.check(stream.read(&mut buffer))?;
+            if read == 0 {
+                break;
+            }
+            response.extend_from_slice(&buffer[..read]);
+        }
+        let response = String::from_utf8(response)
+            .map_err(|e| ClientError::Io(io::Error::new(ErrorKind::InvalidData, e)))?;
+        R::parse_response(&response)
+    }
+
+    /// Turns a connection into a stream of events, e.g. commands starting and finishing.
+    pub fn subscribe(&self) -> Result<BlockingSubscription, ClientError> {
+        let deadline = Deadline::start(&self.options);
+        let mut stream = self.connect()?;
+        deadline.apply(&stream)?;
+        deadline.check(stream.write_all(format!("{}\n", Request::Subscribe).as_bytes()))?;
+        // Events can be far apart, so reads wait as long as it takes.
+        stream.set_read_timeout(None)?;
+        Ok(BlockingSubscription {
+            lines: BufReader::new(stream).lines(),
+        })
+    }
+
+    fn connect(&self) -> Result<UnixStream, ClientError> {
+        UnixStream::connect(&self.options.socket_path).map_err(|source| ClientError::Connect {
+            path: self.options.socket_path.clone(),
+            source,
+        })
+    }
+}
+
+/// When the request in progress has to be complete, if it has a timeout.
+struct Deadline {
+    timeout: Option<(std::time::Duration, Instant)>,
+}
+
+impl Deadline {
+    fn start(options: &ClientOptions) -> Self {
+        Self {
+            timeout: options
+                .timeout
+                .map(|timeout| (timeout, Instant::now() + timeout)),
+        }
+    }
+
+    /// Limits the next read or write on `stream` to the time left.
+    fn apply(&self, stream: &UnixStream) -> Result<(), ClientError> {
+        let Some((timeout, deadline)) = self.timeout else {
+            return Ok(());
+        };
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(ClientError::Timeout(timeout));
+        }
+        stream.set_read_timeout(Some(left))?;
+        stream.set_write_timeout(Some(left))?;
+        Ok(())
+    }
+
+    /// Reports a read or write that ran out of time as a timeout.
+    fn check<T>(&self, result: io::Result<T>) -> Result<T, ClientError> {
+        result.map_err(|e| match (e.kind(), self.timeout) {
+            (ErrorKind::WouldBlock | ErrorKind::TimedOut, Some((timeout, _deadline))) => {
+                ClientError::Timeout(timeout)
+            }
+            _ => ClientError::Io(e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{GetRegressions, HealthCheck, Stop};
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    /// Stands in for the daemon: answers the first request on `socket_path` with `response`
+    /// after `delay`, and returns the request.
+    fn fake_daemon(
+        socket_path: &Path,
+        response: &'static str,
+        delay: Duration,
+    ) -> JoinHandle<String> {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        thread::spawn(move || {
+            let (stream, _addr) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            thread::sleep(delay);
+            let _ = reader.get_mut().write_all(response.as_bytes());
+            request
+        })
+    }
+
+    #[test]
+    fn requests_are_answered_without_a_runtime() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("daemon.sock");
+        let client =
+            BlockingClient::with_options(ClientOptions::default().socket_path(&socket_path));
+
+        let daemon = fake_daemon(&socket_path, "[]", Duration::ZERO);
+        assert!(client.send(GetRegressions).unwrap().is_empty());
+        assert_eq!(daemon.join().unwrap(), "GET_REGRESSIONS\n");
+
+        std::fs::remove_file(&socket_path).unwrap();
+        let daemon = fake_daemon(&socket_path, "", Duration::ZERO);
+        client.send(Stop).unwrap();
+        assert_eq!(daemon.join().unwrap(), "STOP\n");
+    }
+
+    #[test]
+    fn slow_daemons_time_out_and_subscriptions_stream_events() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("daemon.sock");
+        let options = ClientOptions::default()
+            .socket_path(&socket_path)
+            .timeout(Duration::from_millis(50));
+        let client = BlockingClient::with_options(options);
+
+        let _daemon = fake_daemon(&socket_path, "Daemon alive\n", Duration::from_secs(2));
+        let started = Instant::now();
+        assert!(matches!(
+            client.send(HealthCheck).unwrap_err(),
+            ClientError::Timeout(_)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        std::fs::remove_file(&socket_path).unwrap();
+        let daemon = fake_daemon(
+            &socket_path,
+            "{\"event\":\"stats_saved\",\"commands\":2}\n{\"event\":\"shutting_down\"}\n",
+            Duration::from_millis(100),
+        );
+        let events: Vec<DaemonEvent> = client
+            .subscribe()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(daemon.join().unwrap(), "SUBSCRIBE\n");
+        assert_eq!(
+            events,
+            vec![
+                DaemonEvent::StatsSaved { commands: 2 },
+                DaemonEvent::ShuttingDown
+            ]
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why a request to the daemon failed.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ClientError {
+    /// Nothing accepted the connection, usually because the daemon is not running.
+    #[error("Failed to connect to daemon at {path:?}. Is it running?")]
+    Connect {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// The request did not complete within [`ClientOptions::timeout`](super::ClientOptions::timeout).
+    #[error("Daemon did not answer within {0:?}")]
+    Timeout(Duration),
+    /// The connection failed after it was established.
+    #[error("Lost the connection to the daemon")]
+    Io(#[from] io::Error),
+    /// The daemon answered with something that is not the expected JSON.
+    #[error("Failed to decode the response of the daemon")]
+    Decode(#[from] serde_json::Error),
+    /// The daemon answered with something else than expected.
+    #[error("Unexpected response from daemon: '{0}'")]
+    UnexpectedResponse(String),
+}
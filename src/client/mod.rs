@@ -0,0 +1,267 @@
+//! Client library for the t_trace daemon.
+//!
+//! Every request is a type of its own, sent with [`Client::send`] and answered with a typed
+//! response:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), t_trace::client::ClientError> {
+//! use t_trace::client::{Client, GetStats};
+//!
+//! let stats = Client::new().send(GetStats).await?;
+//! if let Some(make) = stats.get("make") {
+//!     println!("make ran {} times", make.success_count + make.fail_count);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`BlockingClient`] offers the same without an async runtime. The request and response types,
+//! [`ClientOptions`] and [`ClientError`] are the stable interface for tools embedding t_trace;
+//! the line protocol underneath may change between versions.
+
+mod blocking;
+mod error;
+mod requests;
+
+pub use blocking::{BlockingClient, BlockingSubscription};
+pub use error::ClientError;
+pub use requests::{
+    ClientRequest, CommandBackground, CommandBegin, CommandEnd, Export, Forget, GetHistory,
+    GetInFlight, GetRegressions, GetSessions, GetStats, GetStatus, HealthCheck, Import, Prune,
+    ResetStats, SessionBegin, SessionEnd, Stats, Stop,
+};
+
+use crate::dto::DaemonEvent;
+use crate::protocol::Request;
+use crate::socket;
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::UnixStream;
+
+/// Time a request may take by default, from connecting until the whole response is read.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the daemon is reached and how long a request may take.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientOptions {
+    socket_path: PathBuf,
+    timeout: Option<Duration>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            socket_path: socket::get_socket_path(),
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Connect to the daemon listening on `socket_path` instead of the one of the current user.
+    pub fn socket_path(mut self, socket_path: impl Into<PathBuf>) -> Self {
+        self.socket_path = socket_path.into();
+        self
+    }
+
+    /// Give up on requests that take longer than `timeout`, from connecting until the whole
+    /// response is read. Subscriptions only apply it to subscribing.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Wait for the daemon as long as it takes.
+    pub fn no_timeout(mut self) -> Self {
+        self.timeout = None;
+        self
+    }
+}
+
+/// Sends requests to the daemon, each over a connection of its own.
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    options: ClientOptions,
+}
+
+/// Events streamed by the daemon after subscribing.
+pub struct Subscription {
+    lines: Lines<BufReader<UnixStream>>,
+}
+
+impl Subscription {
+    /// Waits for the next event. Returns `None` once the daemon closed the connection.
+    pub async fn next_event(&mut self) -> Result<Option<DaemonEvent>, ClientError> {
+        match self.lines.next_line().await? {
+            Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Client {
+    /// A client of the daemon of the current user, with the default timeout.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: ClientOptions) -> Self {
+        Self { options }
+    }
+
+    pub async fn send<R: ClientRequest>(&self, request: R) -> Result<R::Response, ClientError> {
+        self.within_timeout(async {
+            let mut stream = self.connect().await?;
+            stream
+                .write_all(format!("{}\n", request.into_request()).as_bytes())
+                .await?;
+            if !R::EXPECTS_RESPONSE {
+                return R::parse_response("");
+            }
+            stream.flush().await?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await?;
+            R::parse_response(&response)
+        })
+        .await
+    }
+
+    /// Turns a connection into a stream of events, e.g. commands starting and finishing.
+    pub async fn subscribe(&self) -> Result<Subscription, ClientError> {
+        let stream = self
+            .within_timeout(async {
+                let mut stream = self.connect().await?;
+                stream
+                    .write_all(format!("{}\n", Request::Subscribe).as_bytes())
+                    .await?;
+                Ok(stream)
+            })
+            .await?;
+        Ok(Subscription {
+            lines: BufReader::new(stream).lines(),
+        })
+    }
+
+    async fn connect(&self) -> Result<UnixStream, ClientError> {
+        UnixStream::connect(&self.options.socket_path)
+            .await
+            .map_err(|source| ClientError::Connect {
+                path: self.options.socket_path.clone(),
+                source,
+            })
+    }
+
+    async fn within_timeout<T>(
+        &self,
+        exchange: impl Future<Output = Result<T, ClientError>>,
+    ) -> Result<T, ClientError> {
+        match self.options.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange)
+                .await
+                .map_err(|_| ClientError::Timeout(timeout))?,
+            None => exchange.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::{CommandStats, TerminalAlert};
+    use std::path::Path;
+    use tempfile::tempdir;
+    use tokio::net::UnixListener;
+
+    /// Stands in for the daemon: answers the first request on `socket_path` with `response`
+    /// after `delay`, and returns the request.
+    fn fake_daemon(
+        socket_path: &Path,
+        response: impl Into<String>,
+        delay: Duration,
+    ) -> tokio::task::JoinHandle<String> {
+        let response = response.into();
+        let listener = UnixListener::bind(socket_path).unwrap();
+        tokio::spawn(async move {
+            let (stream, _addr) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).await.unwrap();
+            tokio::time::sleep(delay).await;
+            let _ = reader.get_mut().write_all(response.as_bytes()).await;
+            request
+        })
+    }
+
+    #[tokio::test]
+    async fn typed_requests_get_typed_responses() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("daemon.sock");
+        let client = Client::with_options(ClientOptions::default().socket_path(&socket_path));
+
+        let stats = serde_json::to_string(&std::collections::HashMap::from([(
+            "make".to_string(),
+            CommandStats {
+                success_count: 3,
+                ..Default::default()
+            },
+        )]))
+        .unwrap();
+        let daemon = fake_daemon(&socket_path, stats, Duration::ZERO);
+        let stats = client.send(GetStats).await.unwrap();
+        assert_eq!(daemon.await.unwrap(), "GET_STATS\n");
+        assert_eq!(stats.get("make").unwrap().success_count, 3);
+
+        std::fs::remove_file(&socket_path).unwrap();
+        let daemon = fake_daemon(
+            &socket_path,
+            r#"{"alert":"bell","title":"Command finished","body":"make (31.0s)"}"#,
+            Duration::ZERO,
+        );
+        let notification = client
+            .send(CommandEnd {
+                pid: 42,
+                exit_code: 0,
+                job: None,
+                pipe_status: Vec::new(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(daemon.await.unwrap(), "COMMAND_END 42 0\n");
+        assert_eq!(notification.alert, TerminalAlert::Bell);
+
+        std::fs::remove_file(&socket_path).unwrap();
+        let daemon = fake_daemon(&socket_path, "two", Duration::ZERO);
+        let error = client
+            .send(Forget {
+                command: "make".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ClientError::UnexpectedResponse(response) if response == "two"));
+        daemon.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_and_slow_daemons_are_reported() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("daemon.sock");
+        let options = ClientOptions::default()
+            .socket_path(&socket_path)
+            .timeout(Duration::from_millis(50));
+        let client = Client::with_options(options);
+
+        let error = client.send(HealthCheck).await.unwrap_err();
+        assert!(matches!(error, ClientError::Connect { ref path, .. } if *path == socket_path));
+        assert!(error.to_string().contains("Is it running?"));
+
+        let _daemon = fake_daemon(&socket_path, "Daemon alive\n", Duration::from_secs(5));
+        let error = client.send(HealthCheck).await.unwrap_err();
+        assert!(
+            matches!(error, ClientError::Timeout(timeout) if timeout == Duration::from_millis(50))
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use super::ClientError;
+
+use crate::dto::{
+    CommandHistory, CommandStats, DaemonStatus, ImportMode, Regression, RunningCommand, Session,
+    TerminalNotification,
+};
+use crate::export::ExportFormat;
+use crate::protocol::Request;
+
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A request the daemon answers with a [`Response`](ClientRequest::Response).
+///
+/// Implemented by the request types of this module only, so that the protocol underneath can
+/// change without breaking callers.
+pub trait ClientRequest: sealed::Sealed {
+    type Response;
+
+    /// Whether the daemon answers at all. Requests it does not are sent without waiting.
+    #[doc(hidden)]
+    const EXPECTS_RESPONSE: bool = true;
+
+    #[doc(hidden)]
+    fn into_request(self) -> Request;
+
+    #[doc(hidden)]
+    fn parse_response(response: &str) -> Result<Self::Response, ClientError>;
+}
+
+/// The stats of every tracked command.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Stats keyed by the command line, as recorded after redaction.
+    pub commands: HashMap<String, CommandStats>,
+}
+
+impl Stats {
+    pub fn get(&self, command: &str) -> Option<&CommandStats> {
+        self.commands.get(command)
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+impl IntoIterator for Stats {
+    type Item = (String, CommandStats);
+    type IntoIter = std::collections::hash_map::IntoIter<String, CommandStats>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.commands.into_iter()
+    }
+}
+
+/// Reports that a shell started running a command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandBegin {
+    /// PID of the shell running the command.
+    pub pid: u32,
+    pub command: String,
+    pub cwd: Option<PathBuf>,
+}
+
+/// Reports that the command a shell was running finished. Answered with what the shell should
+/// print in the terminal, if the command is announced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandEnd {
+    pub pid: u32,
+    pub exit_code: i32,
+    /// The background job that ended, as reported with [`CommandBackground`], rather than the
+    /// command in the foreground.
+    pub job: Option<u32>,
+    /// Exit codes of the stages of the pipeline the command line ended with, from Bash's
+    /// `PIPESTATUS`. Left empty for a single command.
+    pub pipe_status: Vec<i32>,
+}
+
+/// Reports that the command a shell was running went to the background as `job`, e.g. the PID
+/// of the job. The shell can start other commands meanwhile; the job's end is reported with
+/// [`CommandEnd::job`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandBackground {
+    pub pid: u32,
+    pub job: u32,
+}
+
+/// Reports that a shell started, e.g. from its startup file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionBegin {
+    /// PID of the shell.
+    pub pid: u32,
+}
+
+/// Reports that a shell is exiting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionEnd {
+    pub pid: u32,
+}
+
+/// Shell sessions, running and recently ended, in the order they started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GetSessions;
+
+/// Asks the daemon to save its stats and stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stop;
+
+/// Checks that the daemon answers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthCheck;
+
+/// What the daemon is doing and the problems it recovered from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GetStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GetStats;
+
+/// Commands running right now.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GetInFlight;
+
+/// The stats and recent runs of one command, redacted the way it is recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetHistory {
+    pub command: String,
+}
+
+/// Commands whose last run was much slower than their recent runs, largest slowdown first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GetRegressions;
+
+/// All stats as a document in `format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Export {
+    pub format: ExportFormat,
+}
+
+/// Loads stats, as parsed by [`export::parse`](crate::export::parse). Answered with the number
+/// of commands imported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub stats: HashMap<String, CommandStats>,
+    pub mode: ImportMode,
+}
+
+/// Deletes the stats of the commands matching `pattern`, or of all commands. Answered with the
+/// number of commands deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResetStats {
+    pub pattern: Option<String>,
+}
+
+/// Deletes the stats of a single command. Answered with whether it had any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forget {
+    pub command: String,
+}
+
+/// Deletes the stats of commands last run before `older_than` ago, or run fewer than
+/// `min_count` times. Answered with the number of commands deleted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prune {
+    pub older_than: Option<Duration>,
+    pub min_count: Option<u64>,
+}
+
+fn decode<T: DeserializeOwned>(response: &str) -> Result<T, ClientError> {
+    Ok(serde_json::from_str(response)?)
+}
+
+/// Parses the number of commands a request affected.
+fn count(response: &str) -> Result<usize, ClientError> {
+    response
+        .trim()
+        .parse()
+        .map_err(|_| ClientError::UnexpectedResponse(response.trim().to_string()))
+}
+
+impl sealed::Sealed for CommandBegin {}
+impl ClientRequest for CommandBegin {
+    type Response = ();
+    const EXPECTS_RESPONSE: bool = false;
+
+    fn into_request(self) -> Request {
+        Request::CommandBegin {
+            pid: self.pid,
+            command: self.command,
+            cwd: self.cwd,
+        }
+    }
+
+    fn parse_response(_response: &str) -> Result<(), ClientError> {
+        Ok(())
+    }
+}
+
+impl sealed::Sealed for CommandEnd {}
+impl ClientRequest for CommandEnd {
+    type Response = Option<TerminalNotification>;
+
+    fn into_request(self) -> Request {
+        Request::CommandEnd {
+            pid: self.pid,
+            exit_code: self.exit_code,
+            job: self.job,
+            pipe_status: self.pipe_status,
+        }
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response, ClientError> {
+        if response.trim().is_empty() {
+            return Ok(None);
+        }
+        decode(response).map(Some)
+    }
+}
+
+impl sealed::Sealed for CommandBackground {}
+impl ClientRequest for CommandBackground {
+    type Response = ();
+    const EXPECTS_RESPONSE: bool = false;
+
+    fn into_request(self) -> Request {
+        Request::CommandBackground {
+            pid: self.pid,
+            job: self.job,
+        }
+    }
+
+    fn parse_response(_response: &str) -> Result<(), ClientError> {
+        Ok(())
+    }
+}
+
+impl sealed::Sealed for SessionBegin {}
+impl ClientRequest for SessionBegin {
+    type Response = ();
+    const EXPECTS_RESPONSE: bool = false;
+
+    fn into_request(self) -> Request {
+        Request::SessionBegin { pid: self.pid }
+    }
+
+    fn parse_response(_response: &str) -> Result<(), ClientError> {
+        Ok(())
+    }
+}
+
+impl sealed::Sealed for SessionEnd {}
+impl ClientRequest for SessionEnd {
+    type Response = ();
+    const EXPECTS_RESPONSE: bool = false;
+
+    fn into_request(self) -> Request {
+        Request::SessionEnd { pid: self.pid }
+    }
+
+    fn parse_response(_response: &str) -> Result<(), ClientError> {
+        Ok(())
+    }
+}
+
+impl sealed::Sealed for GetSessions {}
+impl ClientRequest for GetSessions {
+    type Response = Vec<Session>;
+
+    fn into_request(self) -> Request {
+        Request::GetSessions
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response, ClientError> {
+        decode(response)
+    }
+}
+
+impl sealed::Sealed for Stop {}
+impl ClientRequest for Stop {
+    type Response = ();
+    const EXPECTS_RESPONSE: bool = false;
+
+    fn into_request(self) -> Request {
+        Request::Stop
+    }
+
+    fn parse_response(_response: &str) -> Result<(), ClientError> {
+        Ok(())
+    }
+}
+
+impl sealed::Sealed for HealthCheck {}
+impl ClientRequest for HealthCheck {
+    type Response = ();
+
+    fn into_request(self) -> Request {
+        Request::HealthCheck
+    }
+
+    fn parse_response(response: &str) -> Result<(), ClientError> {
+        match response.trim() {
+            "Daemon alive" => Ok(()),
+            other => Err(ClientError::UnexpectedResponse(other.to_string())),
+        }
+    }
+}
+
+impl sealed::Sealed for GetStatus {}
+impl ClientRequest for GetStatus {
+    type Response = DaemonStatus;
+
+    fn into_request(self) -> Request {
+        Request::Status
+    }
+
+    fn parse_response(response: &str) -> Result<DaemonStatus, ClientError> {
+        decode(response)
+    }
+}
+
+impl sealed::Sealed for GetStats {}
+impl ClientRequest for GetStats {
+    type Response = Stats;
+
+    fn into_request(self) -> Request {
+        Request::GetStats
+    }
+
+    fn parse_response(response: &str) -> Result<Stats, ClientError> {
+        Ok(Stats {
+            commands: decode(response)?,
+        })
+    }
+}
+
+impl sealed::Sealed for GetInFlight {}
+impl ClientRequest for GetInFlight {
+    type Response = Vec<RunningCommand>;
+
+    fn into_request(self) -> Request {
+        Request::GetInFlight
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response, ClientError> {
+        decode(response)
+    }
+}
+
+impl sealed::Sealed for GetHistory {}
+impl ClientRequest for GetHistory {
+    type Response = CommandHistory;
+
+    fn into_request(self) -> Request {
+        Request::GetHistory {
+            command: self.command,
+        }
+    }
+
+    fn parse_response(response: &str) -> Result<CommandHistory, ClientError> {
+        decode(response)
+    }
+}
+
+impl sealed::Sealed for GetRegressions {}
+impl ClientRequest for GetRegressions {
+    type Response = Vec<Regression>;
+
+    fn into_request(self) -> Request {
+        Request::GetRegressions
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response, ClientError> {
+        decode(response)
+    }
+}
+
+impl sealed::Sealed for Export {}
+impl ClientRequest for Export {
+    type Response = String;
+
+    fn into_request(self) -> Request {
+        Request::Export {
+            format: self.format,
+        }
+    }
+
+    fn parse_response(response: &str) -> Result<String, ClientError> {
+        Ok(response.to_string())
+    }
+}
+
+impl sealed::Sealed for Import {}
+impl ClientRequest for Import {
+    type Response = usize;
+
+    fn into_request(self) -> Request {
+        Request::Import {
+            mode: self.mode,
+            stats: self.stats,
+        }
+    }
+
+    fn parse_response(response: &str) -> Result<usize, ClientError> {
+        count(response)
+    }
+}
+
+impl sealed::Sealed for ResetStats {}
+impl ClientRequest for ResetStats {
+    type Response = usize;
+
+    fn into_request(self) -> Request {
+        Request::ResetStats {
+            pattern: self.pattern,
+        }
+    }
+
+    fn parse_response(response: &str) -> Result<usize, ClientError> {
+        count(response)
+    }
+}
+
+impl sealed::Sealed for Forget {}
+impl ClientRequest for Forget {
+    type Response = bool;
+
+    fn into_request(self) -> Request {
+        Request::Forget {
+            command: self.command,
+        }
+    }
+
+    fn parse_response(response: &str) -> Result<bool, ClientError> {
+        Ok(count(response)? > 0)
+    }
+}
+
+impl sealed::Sealed for Prune {}
+impl ClientRequest for Prune {
+    type Response = usize;
+
+    fn into_request(self) -> Request {
+        Request::Prune {
+            older_than: self.older_than,
+            min_count: self.min_count,
+        }
+    }
+
+    fn parse_response(response: &str) -> Result<usize, ClientError> {
+        count(response)
+    }
+}
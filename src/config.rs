@@ -0,0 +1,527 @@
+use crate::cli::{parse_duration, parse_positive_duration};
+use crate::daemon::{HookSettings, NotificationRules, RegressionRules, StorageKind, TrackingRules};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Settings read from `config.toml`. Every section and field is optional.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub daemon: DaemonConfig,
+    pub storage: StorageConfig,
+    pub tracking: TrackingRules,
+    pub redaction: RedactionConfig,
+    pub regressions: RegressionRules,
+    pub notifications: NotificationRules,
+    pub hooks: HookSettings,
+    pub metrics: MetricsConfig,
+    pub otel: OtelConfig,
+    pub display: DisplayConfig,
+}
+
+/// `[daemon]`: how the daemon process behaves. Changes take effect on restart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Time after which a command that never reported its end is recorded as abandoned.
+    #[serde(with = "positive_duration_format")]
+    pub in_flight_ttl: Duration,
+    /// How often changed stats are saved in the background.
+    #[serde(with = "positive_duration_format")]
+    pub autosave_interval: Duration,
+    /// Log filter, e.g. `info` or `t_trace=debug`. `RUST_LOG` takes precedence.
+    pub log_level: String,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            in_flight_ttl: Duration::from_secs(24 * 60 * 60),
+            autosave_interval: Duration::from_secs(60),
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+/// `[storage]`: where the stats are kept. Changes take effect on restart.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageKind,
+    /// Directory holding the stats, the journal and the daemon log.
+    /// Defaults to `t_trace` in the local data directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+}
+
+impl StorageConfig {
+    pub fn data_dir(&self) -> Result<PathBuf> {
+        match &self.data_dir {
+            Some(data_dir) => Ok(data_dir.clone()),
+            None => Ok(dirs::data_local_dir()
+                .context("Could not find local data directory")?
+                .join("t_trace")),
+        }
+    }
+}
+
+/// `[metrics]`: the Prometheus endpoint, served if the daemon was built with the `metrics`
+/// feature. Changes take effect on restart.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Where `/metrics` is served. Nothing is served if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<ListenAddress>,
+}
+
+/// A loopback TCP address such as `127.0.0.1:9464`, or a Unix socket written as `unix:<path>`.
+///
+/// Other interfaces are refused: the metrics carry command lines.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => {
+                let address: SocketAddr = s.parse().with_context(|| {
+                    format!(
+                        "Invalid listen address, expected host:port or unix:<path>: {}",
+                        s
+                    )
+                })?;
+                if !address.ip().is_loopback() {
+                    bail!("Listen address must be on the loopback interface: {}", s);
+                }
+                Ok(ListenAddress::Tcp(address))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(address: ListenAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// `[otel]`: the OTLP span exporter, active if the daemon was built with the `otel` feature.
+/// Changes take effect on restart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtelConfig {
+    /// Collector receiving a span for every finished command. Nothing is exported if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<CollectorEndpoint>,
+    /// The `service.name` of the exported spans.
+    pub service_name: String,
+    /// Exports the collector has not answered within this are dropped.
+    #[serde(with = "duration_format")]
+    pub timeout: Duration,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            service_name: "t_trace".to_string(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// An OTLP/HTTP traces endpoint such as `http://127.0.0.1:4318/v1/traces`.
+///
+/// The port defaults to 4318 and the path to `/v1/traces`. Only plain `http` is supported; a
+/// remote collector is reached through a local agent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct CollectorEndpoint {
+    /// Host name or IP address, without the brackets of an IPv6 address.
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl FromStr for CollectorEndpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(rest) = s.strip_prefix("http://") else {
+            bail!("Collector endpoint must start with http://: {}", s);
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .with_context(|| format!("Invalid port in collector endpoint: {}", s))?,
+            ),
+            _ => (authority, 4318),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            bail!("Collector endpoint has no host: {}", s);
+        }
+        Ok(CollectorEndpoint {
+            host: host.to_string(),
+            port,
+            path: if path.is_empty() { "/v1/traces" } else { path }.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for CollectorEndpoint {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<CollectorEndpoint> for String {
+    fn from(endpoint: CollectorEndpoint) -> Self {
+        endpoint.to_string()
+    }
+}
+
+impl CollectorEndpoint {
+    /// `host:port`, as used in the `Host` header.
+    pub fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl fmt::Display for CollectorEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.path)
+    }
+}
+
+/// `[redaction]`: patterns replaced in commands on top of the built-in rules.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactionConfig {
+    #[serde(with = "regex_list_format")]
+    pub patterns: Vec<Regex>,
+}
+
+/// `[display]`: defaults of `t_trace stats`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    pub sort: SortKey,
+    /// Show only this many commands, those sorted last.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// Count interrupted runs in the total and mean times.
+    pub include_interrupted: bool,
+}
+
+/// Column the stats table is sorted by. The largest values end up at the bottom, next to the prompt.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortKey {
+    /// Total time spent in the command.
+    #[default]
+    Total,
+    /// Mean time of a run.
+    Mean,
+    /// Number of runs.
+    Count,
+    /// Duration of the last run.
+    Last,
+    /// The command itself, alphabetically.
+    Command,
+}
+
+impl Config {
+    /// Reads the config file. A missing file yields the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {:?}", path))?;
+        toml::from_str(&contents).with_context(|| format!("Invalid config file: {:?}", path))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+/// Location of the config file: `$XDG_CONFIG_HOME/t_trace/config.toml`, where
+/// `XDG_CONFIG_HOME` defaults to `~/.config`.
+pub fn path() -> Result<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => dirs::home_dir()
+            .context("Could not find home directory")?
+            .join(".config"),
+    };
+    Ok(config_home.join("t_trace").join("config.toml"))
+}
+
+/// Durations as human-friendly strings such as `"90d"` or `"500ms"`.
+pub(crate) mod duration_format {
+    use super::parse_duration;
+
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use std::time::Duration;
+
+    const UNITS: &[(&str, u64)] = &[
+        ("w", 7 * 24 * 60 * 60),
+        ("d", 24 * 60 * 60),
+        ("h", 60 * 60),
+        ("m", 60),
+        ("s", 1),
+    ];
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        let formatted = if duration.subsec_nanos() == 0 && duration.as_secs() > 0 {
+            let seconds = duration.as_secs();
+            let (unit, per_unit) = UNITS
+                .iter()
+                .find(|(_unit, per_unit)| seconds.is_multiple_of(*per_unit))
+                .expect("Every whole number of seconds is divisible by one");
+            format!("{}{}", seconds / per_unit, unit)
+        } else {
+            format!("{}ms", duration.as_millis())
+        };
+        serializer.serialize_str(&formatted)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        parse_duration(&input).map_err(D::Error::custom)
+    }
+}
+
+/// Intervals and time limits, written like other durations but never zero.
+pub(crate) mod positive_duration_format {
+    use super::parse_positive_duration;
+
+    use serde::{Deserialize, Deserializer, de::Error};
+    use std::time::Duration;
+
+    pub use super::duration_format::serialize;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        parse_positive_duration(&input).map_err(D::Error::custom)
+    }
+}
+
+/// Regular expressions as a list of pattern strings, validated when the config is read.
+pub(crate) mod regex_list_format {
+    use regex::Regex;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(patterns: &[Regex], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(patterns.iter().map(Regex::as_str))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Regex>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|pattern| Regex::new(pattern).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::HookTrigger;
+    use crate::dto::TerminalAlert;
+    use tempfile::tempdir;
+
+    #[test]
+    fn missing_file_yields_defaults() {
+        let dir = tempdir().unwrap();
+        let config = Config::load(&dir.path().join("config.toml")).unwrap();
+
+        assert_eq!(config.daemon, DaemonConfig::default());
+        assert_eq!(config.storage.backend, StorageKind::Sqlite);
+        assert!(config.tracking.ignore_commands.is_empty());
+    }
+
+    #[test]
+    fn typed_sections_are_parsed() {
+        let config: Config = toml::from_str(
+            r#"
+            [daemon]
+            in_flight_ttl = "12h"
+            log_level = "debug"
+
+            [storage]
+            backend = "json"
+            data_dir = "/var/lib/t_trace"
+
+            [tracking]
+            ignore = ["ls", "clear"]
+            ignore_prefixes = ["cd "]
+            only = ["^cargo"]
+            min_duration = "500ms"
+
+            [redaction]
+            patterns = ["acme-[0-9]+"]
+
+            [regressions]
+            factor = 1.5
+            baseline_runs = 50
+
+            [notifications]
+            enabled = true
+            min_duration = "1m"
+            ignore = ["^(vim|ssh) "]
+            terminal = "osc777"
+
+            [hooks]
+            timeout = "30s"
+
+            [[hooks.scripts]]
+            path = "/usr/local/bin/log-deploy"
+            only = ["^deploy"]
+            on = "failure"
+
+            [metrics]
+            listen = "127.0.0.1:9464"
+
+            [otel]
+            endpoint = "http://collector.local"
+
+            [display]
+            sort = "mean"
+            limit = 20
+            include_interrupted = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.daemon.in_flight_ttl,
+            Duration::from_secs(12 * 60 * 60)
+        );
+        assert_eq!(config.daemon.autosave_interval, Duration::from_secs(60));
+        assert_eq!(config.storage.backend, StorageKind::Json);
+        assert_eq!(
+            config.storage.data_dir().unwrap(),
+            PathBuf::from("/var/lib/t_trace")
+        );
+        assert_eq!(config.tracking.ignore_commands, vec!["ls", "clear"]);
+        assert_eq!(config.tracking.only_patterns[0].as_str(), "^cargo");
+        assert_eq!(config.tracking.min_duration, Duration::from_millis(500));
+        assert!(config.redaction.patterns[0].is_match("acme-42"));
+        assert_eq!(config.regressions.factor, 1.5);
+        assert_eq!(config.regressions.baseline_runs, 50);
+        assert_eq!(config.regressions.min_baseline_runs, 5);
+        assert!(config.notifications.enabled);
+        assert_eq!(config.notifications.min_duration, Duration::from_secs(60));
+        assert!(config.notifications.ignore_patterns[0].is_match("ssh host"));
+        assert_eq!(config.notifications.terminal, TerminalAlert::Osc777);
+        assert_eq!(config.hooks.timeout, Duration::from_secs(30));
+        assert_eq!(config.hooks.max_concurrent, 4);
+        assert_eq!(config.hooks.scripts[0].on, HookTrigger::Failure);
+        assert!(config.hooks.scripts[0].args.is_empty());
+        assert_eq!(
+            config.metrics.listen,
+            Some(ListenAddress::Tcp("127.0.0.1:9464".parse().unwrap()))
+        );
+        let endpoint = config.otel.endpoint.unwrap();
+        assert_eq!(
+            endpoint.to_string(),
+            "http://collector.local:4318/v1/traces"
+        );
+        assert_eq!(config.otel.service_name, "t_trace");
+        assert_eq!(config.display.sort, SortKey::Mean);
+        assert_eq!(config.display.limit, Some(20));
+        assert!(config.display.include_interrupted);
+    }
+
+    #[test]
+    fn invalid_values_and_unknown_keys_are_rejected() {
+        for contents in [
+            "[daemon]\nin_flight_ttl = \"soon\"",
+            "[daemon]\nautosave_interval = \"0s\"",
+            "[daemon]\nin_flight_ttl = \"0\"",
+            "[tracking]\nignore_patterns = [\"(unclosed\"]",
+            "[storage]\nbackend = \"postgres\"",
+            "[display]\nsrot = \"mean\"",
+            "[metrics]\nlisten = \"localhost\"",
+            "[metrics]\nlisten = \"0.0.0.0:9464\"",
+            "[otel]\nendpoint = \"https://collector.example.com\"",
+            "[otel]\nendpoint = \"http://collector:otlp\"",
+        ] {
+            assert!(toml::from_str::<Config>(contents).is_err(), "{}", contents);
+        }
+    }
+
+    #[test]
+    fn shown_config_can_be_read_back() {
+        let mut config = Config::default();
+        config.tracking.ignore_patterns = vec![Regex::new(r"^git (status|diff)\b").unwrap()];
+        config.tracking.min_duration = Duration::from_millis(1500);
+        config.daemon.in_flight_ttl = Duration::from_secs(2 * 24 * 60 * 60);
+        config.metrics.listen = Some(ListenAddress::Unix(PathBuf::from("/run/t_trace.sock")));
+        config.otel.endpoint = Some("http://[::1]:4318/otlp/traces".parse().unwrap());
+
+        let shown = config.to_toml().unwrap();
+        assert!(shown.contains("in_flight_ttl = \"2d\""));
+        assert!(shown.contains("min_duration = \"1500ms\""));
+
+        let read_back: Config = toml::from_str(&shown).unwrap();
+        assert_eq!(read_back.daemon, config.daemon);
+        assert_eq!(read_back.metrics, config.metrics);
+        assert_eq!(read_back.otel, config.otel);
+        assert_eq!(
+            read_back.tracking.ignore_patterns[0].as_str(),
+            config.tracking.ignore_patterns[0].as_str()
+        );
+        assert_eq!(
+            read_back.tracking.min_duration,
+            config.tracking.min_duration
+        );
+    }
+}
@@ -0,0 +1,391 @@
+use super::state::FinishedCommand;
+
+use crate::config::{duration_format, regex_list_format};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+/// Most hook runs waiting for a free slot. Further ones are dropped, so that a hook that keeps
+/// hanging cannot pile up work in the daemon.
+const MAX_QUEUED_RUNS: usize = 100;
+
+/// `[hooks]`: executables run after tracked commands finish.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HookSettings {
+    /// Most hooks running at the same time. Further ones wait for a free slot.
+    pub max_concurrent: usize,
+    /// Hooks still running after this are killed.
+    #[serde(with = "duration_format")]
+    pub timeout: Duration,
+    pub scripts: Vec<Hook>,
+}
+
+impl Default for HookSettings {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 4,
+            timeout: Duration::from_secs(10),
+            scripts: Vec::new(),
+        }
+    }
+}
+
+/// One `[[hooks.scripts]]` entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    /// Executable run with the event as a line of JSON on its standard input.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// If not empty, the hook only runs for commands matching one of these patterns.
+    #[serde(default, rename = "only", with = "regex_list_format")]
+    pub only_patterns: Vec<Regex>,
+    /// Which outcomes the hook runs for.
+    #[serde(default)]
+    pub on: HookTrigger,
+    /// The hook only runs for commands that took at least this long.
+    #[serde(default, with = "duration_format")]
+    pub min_duration: Duration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HookTrigger {
+    #[default]
+    Any,
+    /// Exit code 0.
+    Success,
+    /// Any other exit code.
+    Failure,
+}
+
+impl Hook {
+    fn matches(&self, finished: &FinishedCommand) -> bool {
+        let outcome = match self.on {
+            HookTrigger::Any => true,
+            HookTrigger::Success => finished.exit_code == 0,
+            HookTrigger::Failure => finished.exit_code != 0,
+        };
+        outcome
+            && finished.duration >= self.min_duration
+            && (self.only_patterns.is_empty()
+                || self
+                    .only_patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(&finished.command)))
+    }
+}
+
+/// What a hook receives on its standard input.
+#[derive(Serialize, Debug)]
+struct HookEvent<'a> {
+    event: &'static str,
+    command: &'a str,
+    exit_code: i32,
+    duration_ms: u128,
+    cwd: Option<&'a PathBuf>,
+    finished_at_unix_ms: u128,
+    shell_pid: u32,
+    #[serde(skip_serializing_if = "<[i32]>::is_empty")]
+    pipe_status: &'a [i32],
+}
+
+impl<'a> From<&'a FinishedCommand> for HookEvent<'a> {
+    fn from(finished: &'a FinishedCommand) -> Self {
+        Self {
+            event: "command_end",
+            command: &finished.command,
+            exit_code: finished.exit_code,
+            duration_ms: finished.duration.as_millis(),
+            cwd: finished.cwd.as_ref(),
+            finished_at_unix_ms: finished
+                .finished_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            shell_pid: finished.pid,
+            pipe_status: &finished.pipe_status,
+        }
+    }
+}
+
+/// How a single hook run ended.
+#[derive(Debug, PartialEq)]
+enum HookOutcome {
+    Succeeded,
+    Failed,
+    TimedOut,
+}
+
+/// Runs the configured hooks in the background, at most `max_concurrent` at a time.
+///
+/// Hooks never hold up or affect tracking: their failures are only logged.
+#[derive(Debug)]
+pub struct HookRunner {
+    settings: HookSettings,
+    slots: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl Default for HookRunner {
+    fn default() -> Self {
+        Self::new(HookSettings::default())
+    }
+}
+
+impl HookRunner {
+    pub fn new(settings: HookSettings) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(settings.max_concurrent.max(1))),
+            queued: Arc::new(AtomicUsize::new(0)),
+            settings,
+        }
+    }
+
+    /// Starts every hook matching `finished`. Must be called within a Tokio runtime.
+    pub fn run(&self, finished: &FinishedCommand) {
+        let hooks: Vec<&Hook> = self
+            .settings
+            .scripts
+            .iter()
+            .filter(|hook| hook.matches(finished))
+            .collect();
+        if hooks.is_empty() {
+            return;
+        }
+        let event = match serde_json::to_string(&HookEvent::from(finished)) {
+            Ok(event) => Arc::new(format!("{}\n", event)),
+            Err(e) => {
+                warn!("Failed to serialize hook event: {}", e);
+                return;
+            }
+        };
+
+        for hook in hooks {
+            if self.queued.fetch_add(1, Ordering::Relaxed) >= MAX_QUEUED_RUNS {
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+                warn!("Too many hooks waiting to run, skipping {:?}.", hook.path);
+                continue;
+            }
+            let hook = hook.clone();
+            let event = Arc::clone(&event);
+            let slots = Arc::clone(&self.slots);
+            let queued = Arc::clone(&self.queued);
+            let timeout = self.settings.timeout;
+            tokio::spawn(async move {
+                let Ok(_slot) = slots.acquire_owned().await else {
+                    return;
+                };
+                queued.fetch_sub(1, Ordering::Relaxed);
+                run_hook(&hook, &event, timeout).await;
+            });
+        }
+    }
+}
+
+async fn run_hook(hook: &Hook, event: &str, timeout: Duration) -> HookOutcome {
+    let child = Command::new(&hook.path)
+        .args(&hook.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run hook {:?}: {}", hook.path, e);
+            return HookOutcome::Failed;
+        }
+    };
+
+    let stdin = child.stdin.take();
+    let run = async move {
+        if let Some(mut stdin) = stdin {
+            // A hook that does not read its input is fine.
+            let _ = stdin.write_all(event.as_bytes()).await;
+        }
+        child.wait_with_output().await
+    };
+    match tokio::time::timeout(timeout, run).await {
+        Ok(Ok(output)) if output.status.success() => {
+            debug!("Hook {:?} finished.", hook.path);
+            HookOutcome::Succeeded
+        }
+        Ok(Ok(output)) => {
+            warn!(
+                "Hook {:?} failed with {}: {}",
+                hook.path,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            HookOutcome::Failed
+        }
+        Ok(Err(e)) => {
+            warn!("Failed to wait for hook {:?}: {}", hook.path, e);
+            HookOutcome::Failed
+        }
+        Err(_) => {
+            warn!(
+                "Hook {:?} did not finish within {:?} and was killed.",
+                hook.path, timeout
+            );
+            HookOutcome::TimedOut
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::time::SystemTime;
+    use tempfile::tempdir;
+
+    fn finished(command: &str, exit_code: i32) -> FinishedCommand {
+        FinishedCommand {
+            pid: 4242,
+            command: command.to_string(),
+            cwd: Some(PathBuf::from("/srv/app")),
+            duration: Duration::from_millis(1_500),
+            exit_code,
+            pipe_status: Vec::new(),
+            finished_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        }
+    }
+
+    fn script(dir: &Path, name: &str, body: &str) -> Hook {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        Hook {
+            path,
+            args: Vec::new(),
+            only_patterns: Vec::new(),
+            on: HookTrigger::Any,
+            min_duration: Duration::ZERO,
+        }
+    }
+
+    async fn wait_for(path: &Path) -> String {
+        for _ in 0..300 {
+            if let Ok(contents) = std::fs::read_to_string(path) {
+                return contents;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{:?} was never written", path);
+    }
+
+    #[test]
+    fn hooks_run_for_matching_commands_and_outcomes() {
+        let hook = Hook {
+            path: PathBuf::from("/bin/true"),
+            args: Vec::new(),
+            only_patterns: vec![Regex::new("^deploy").unwrap()],
+            on: HookTrigger::Failure,
+            min_duration: Duration::from_secs(1),
+        };
+
+        assert!(hook.matches(&finished("deploy prod", 1)));
+        assert!(!hook.matches(&finished("deploy prod", 0)));
+        assert!(!hook.matches(&finished("make", 1)));
+        let quick = FinishedCommand {
+            duration: Duration::from_millis(10),
+            ..finished("deploy prod", 1)
+        };
+        assert!(!hook.matches(&quick));
+    }
+
+    #[tokio::test]
+    async fn hooks_receive_the_event_as_json_on_stdin() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("event.json");
+        let hook = script(
+            dir.path(),
+            "hook.sh",
+            &format!("cat > {0:?}.tmp && mv {0:?}.tmp {0:?}", output),
+        );
+        let runner = HookRunner::new(HookSettings {
+            scripts: vec![hook],
+            ..Default::default()
+        });
+
+        runner.run(&finished("cargo build", 101));
+        let event: serde_json::Value = serde_json::from_str(&wait_for(&output).await).unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({
+                "event": "command_end",
+                "command": "cargo build",
+                "exit_code": 101,
+                "duration_ms": 1500,
+                "cwd": "/srv/app",
+                "finished_at_unix_ms": 1_700_000_000_000u64,
+                "shell_pid": 4242,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_and_failing_hooks_are_reported() {
+        let dir = tempdir().unwrap();
+        let slow = script(dir.path(), "slow.sh", "sleep 5");
+        let failing = script(dir.path(), "failing.sh", "echo broken >&2; exit 3");
+        let fine = script(dir.path(), "fine.sh", "cat > /dev/null");
+
+        let started = SystemTime::now();
+        assert_eq!(
+            run_hook(&slow, "{}\n", Duration::from_millis(100)).await,
+            HookOutcome::TimedOut
+        );
+        assert!(started.elapsed().unwrap() < Duration::from_secs(2));
+        assert_eq!(
+            run_hook(&failing, "{}\n", Duration::from_secs(5)).await,
+            HookOutcome::Failed
+        );
+        assert_eq!(
+            run_hook(&fine, "{}\n", Duration::from_secs(5)).await,
+            HookOutcome::Succeeded
+        );
+    }
+
+    #[tokio::test]
+    async fn hooks_beyond_the_concurrency_limit_wait_for_a_slot() {
+        let dir = tempdir().unwrap();
+        let lock = dir.path().join("lock");
+        let log = dir.path().join("log");
+        let body = format!(
+            "mkdir {lock:?} || echo overlap >> {log:?}\nsleep 0.1\nrmdir {lock:?}\necho done >> {log:?}"
+        );
+        let runner = HookRunner::new(HookSettings {
+            max_concurrent: 1,
+            scripts: vec![
+                script(dir.path(), "first.sh", &body),
+                script(dir.path(), "second.sh", &body),
+            ],
+            ..Default::default()
+        });
+
+        runner.run(&finished("make", 0));
+        for _ in 0..300 {
+            if std::fs::read_to_string(&log).is_ok_and(|log| log.lines().count() >= 2) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "done\ndone\n");
+    }
+}
@@ -0,0 +1,437 @@
+use crate::dto::{CommandStats, ImportMode};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+
+/// A single state change, written to the journal before it is applied in memory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum JournalEntry {
+    Completed {
+        command: String,
+        duration: Duration,
+        exit_code: i32,
+        #[serde(default)]
+        finished_at: Option<SystemTime>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pipe_status: Vec<i32>,
+    },
+    Abandoned {
+        command: String,
+    },
+    Imported {
+        stats: HashMap<String, CommandStats>,
+        mode: ImportMode,
+    },
+    Removed {
+        commands: Vec<String>,
+    },
+}
+
+/// A journal entry as written to disk, numbered so that a snapshot can tell which entries it
+/// already includes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct JournalRecord {
+    /// Zero for entries written before the journal was numbered.
+    #[serde(default)]
+    seq: u64,
+    #[serde(flatten)]
+    entry: JournalEntry,
+}
+
+/// The entries of a journal that are still to be applied on startup.
+#[derive(Debug, Default, PartialEq)]
+pub struct Replay {
+    pub entries: Vec<JournalEntry>,
+    /// The highest sequence number in use, in the journal or in the snapshot.
+    pub last_seq: u64,
+}
+
+/// Append-only, write-ahead log of the changes made since the last successful save.
+///
+/// Entries are written and synced to disk by a thread of its own, so that whatever happened
+/// after the last snapshot can be replayed on startup if the daemon crashes or the machine loses
+/// power, without making the caller wait for the disk. Entries carry increasing sequence numbers,
+/// and a snapshot records the last one it includes, so that entries which were saved but not yet
+/// discarded are not applied twice.
+#[derive(Debug)]
+pub struct Journal {
+    commands: mpsc::Sender<JournalCommand>,
+    last_seq: u64,
+}
+
+#[derive(Debug)]
+enum JournalCommand {
+    /// A serialized record, ending with a newline.
+    Append(String),
+    DiscardThrough(u64),
+    Flush(mpsc::Sender<()>),
+}
+
+impl Journal {
+    /// Opens the journal at `path`, numbering new entries from `last_seq + 1`.
+    pub fn open(path: &Path, last_seq: u64) -> Result<Self> {
+        let file = JournalFile::open(path)?;
+        let (commands, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("journal".to_string())
+            .spawn(move || write_journal(file, receiver))
+            .context("Failed to start the journal thread")?;
+        Ok(Self { commands, last_seq })
+    }
+
+    /// Queues `entry` to be written. It reaches the disk shortly after, together with whatever
+    /// else was queued while the previous entries were synced.
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<()> {
+        self.last_seq += 1;
+        let record = JournalRecord {
+            seq: self.last_seq,
+            entry: entry.clone(),
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        self.send(JournalCommand::Append(line))
+    }
+
+    /// Sequence number of the entry appended last, to be saved with a snapshot taken now.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Discards the entries up to `seq`, i.e. those covered by a saved snapshot, while keeping
+    /// anything appended after the snapshot was taken.
+    pub fn discard_through(&self, seq: u64) -> Result<()> {
+        self.send(JournalCommand::DiscardThrough(seq))
+    }
+
+    /// Waits until everything appended so far is on disk.
+    pub fn flush(&self) -> Result<()> {
+        let (done, flushed) = mpsc::channel();
+        self.send(JournalCommand::Flush(done))?;
+        flushed
+            .recv()
+            .map_err(|_| anyhow!("The journal thread has stopped"))
+    }
+
+    fn send(&self, command: JournalCommand) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| anyhow!("The journal thread has stopped"))
+    }
+
+    /// Reads back the entries of the journal at `path` that a snapshot including everything up
+    /// to `saved_seq` lacks. A torn last line, left behind by a crash in the middle of a write,
+    /// is skipped.
+    pub fn replay_after(path: &Path, saved_seq: u64) -> Result<Replay> {
+        let mut replay = Replay {
+            entries: Vec::new(),
+            last_seq: saved_seq,
+        };
+        if !path.exists() {
+            return Ok(replay);
+        }
+
+        for line in read_lines(path)? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<JournalRecord>(&line) {
+                Ok(record) if record.seq == 0 || record.seq > saved_seq => {
+                    replay.last_seq = replay.last_seq.max(record.seq);
+                    replay.entries.push(record.entry);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Skipping unreadable journal entry '{}': {}", line, e),
+            }
+        }
+
+        Ok(replay)
+    }
+}
+
+/// The lines of the journal at `path`. A line that is not valid UTF-8, e.g. a write torn in the
+/// middle of a non-ASCII command, is skipped.
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    let file = File::open(path).with_context(|| format!("Failed to open journal: {:?}", path))?;
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).split(b'\n') {
+        match String::from_utf8(line?) {
+            Ok(line) => lines.push(line),
+            Err(e) => tracing::warn!(
+                "Skipping unreadable journal entry '{}': {}",
+                String::from_utf8_lossy(e.as_bytes()),
+                e.utf8_error()
+            ),
+        }
+    }
+    Ok(lines)
+}
+
+/// Writes the records queued by a [`Journal`] until it is dropped. Whatever queued up while the
+/// previous batch was synced is written with a single sync.
+fn write_journal(mut file: JournalFile, receiver: mpsc::Receiver<JournalCommand>) {
+    while let Ok(command) = receiver.recv() {
+        let mut batch = String::new();
+        for command in std::iter::once(command).chain(receiver.try_iter()) {
+            match command {
+                JournalCommand::Append(line) => batch.push_str(&line),
+                JournalCommand::DiscardThrough(seq) => {
+                    file.write(&std::mem::take(&mut batch));
+                    if let Err(e) = file.discard_through(seq) {
+                        tracing::error!("Failed to discard saved journal entries: {:#}", e);
+                    }
+                }
+                JournalCommand::Flush(done) => {
+                    file.write(&std::mem::take(&mut batch));
+                    let _ = done.send(());
+                }
+            }
+        }
+        file.write(&batch);
+    }
+}
+
+#[derive(Debug)]
+struct JournalFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl JournalFile {
+    /// Opens the journal for appending. If a crash left a torn line at its end, a new line is
+    /// started, so that the next record does not join the fragment and get lost with it.
+    fn open(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open journal: {:?}", path))?;
+        let len = file.metadata()?.len();
+        let mut last_byte = [b'\n'];
+        if len > 0 {
+            file.read_exact_at(&mut last_byte, len - 1)?;
+        }
+        if last_byte != [b'\n'] {
+            file.write_all(b"\n")
+                .with_context(|| format!("Failed to repair journal: {:?}", path))?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /// Appends `lines` and syncs them. A failure is logged; the entries are still applied in
+    /// memory and saved with the next snapshot.
+    fn write(&mut self, lines: &str) {
+        if lines.is_empty() {
+            return;
+        }
+        let result = self
+            .file
+            .write_all(lines.as_bytes())
+            .and_then(|()| self.file.sync_data());
+        if let Err(e) = result {
+            tracing::error!("Failed to append to the journal: {}", e);
+        }
+    }
+
+    fn discard_through(&mut self, seq: u64) -> Result<()> {
+        let mut remainder = String::new();
+        for line in read_lines(&self.path)? {
+            if serde_json::from_str::<JournalRecord>(&line).is_ok_and(|record| record.seq > seq) {
+                remainder.push_str(&line);
+                remainder.push('\n');
+            }
+        }
+
+        let temp_path = self.path.with_extension("log.tmp");
+        let mut temp_file = File::create(&temp_path)
+            .with_context(|| format!("Failed to create temp journal: {:?}", &temp_path))?;
+        temp_file.write_all(remainder.as_bytes())?;
+        temp_file.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to replace journal: {:?}", &self.path))?;
+
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn completed(command: &str) -> JournalEntry {
+        JournalEntry::Completed {
+            command: command.to_string(),
+            duration: Duration::from_millis(42),
+            exit_code: 0,
+            finished_at: None,
+            pipe_status: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn appended_entries_are_replayed_in_order() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+
+        let mut journal = Journal::open(&path, 0).unwrap();
+        journal.append(&completed("ls")).unwrap();
+        journal
+            .append(&JournalEntry::Abandoned {
+                command: "vim".to_string(),
+            })
+            .unwrap();
+        journal.flush().unwrap();
+
+        let entries = Journal::replay_after(&path, 0).unwrap().entries;
+        assert_eq!(
+            entries,
+            vec![
+                completed("ls"),
+                JournalEntry::Abandoned {
+                    command: "vim".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_skips_entries_the_snapshot_includes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+        std::fs::write(&path, "{\"Abandoned\":{\"command\":\"vim\"}}\n").unwrap();
+
+        let mut journal = Journal::open(&path, 10).unwrap();
+        for command in ["ls", "pwd", "make"] {
+            journal.append(&completed(command)).unwrap();
+        }
+        assert_eq!(journal.last_seq(), 13);
+        journal.flush().unwrap();
+
+        let replay = Journal::replay_after(&path, 12).unwrap();
+        let abandoned = JournalEntry::Abandoned {
+            command: "vim".to_string(),
+        };
+        assert_eq!(replay.entries, vec![abandoned, completed("make")]);
+        assert_eq!(replay.last_seq, 13);
+        assert_eq!(Journal::replay_after(&path, 20).unwrap().last_seq, 20);
+    }
+
+    #[test]
+    fn replay_of_missing_journal_is_empty() {
+        let dir = tempdir().unwrap();
+        let entries = Journal::replay_after(&dir.path().join("missing.log"), 0)
+            .unwrap()
+            .entries;
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn replay_skips_torn_trailing_line() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+
+        let mut journal = Journal::open(&path, 0).unwrap();
+        journal.append(&completed("make")).unwrap();
+        journal.flush().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"Completed\":{\"comm").unwrap();
+
+        let entries = Journal::replay_after(&path, 0).unwrap().entries;
+        assert_eq!(entries, vec![completed("make")]);
+    }
+
+    #[test]
+    fn torn_non_ascii_line_is_skipped_on_replay_and_discard() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+        let mut torn = b"{\"seq\":1,\"Completed\":{\"command\":\"caf".to_vec();
+        torn.push("é".as_bytes()[0]);
+        torn.push(b'\n');
+        std::fs::write(&path, torn).unwrap();
+
+        let mut journal = Journal::open(&path, 1).unwrap();
+        journal.append(&completed("ls")).unwrap();
+        journal.append(&completed("pwd")).unwrap();
+        journal.flush().unwrap();
+        assert_eq!(
+            Journal::replay_after(&path, 0).unwrap().entries,
+            vec![completed("ls"), completed("pwd")]
+        );
+
+        journal.discard_through(2).unwrap();
+        journal.flush().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.contains("pwd"));
+    }
+
+    #[test]
+    fn record_after_a_torn_line_starts_a_line_of_its_own() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+        std::fs::write(&path, "{\"seq\":1,\"Completed\":{\"comm").unwrap();
+
+        let mut journal = Journal::open(&path, 1).unwrap();
+        journal.append(&completed("make")).unwrap();
+        journal.flush().unwrap();
+
+        let replay = Journal::replay_after(&path, 0).unwrap();
+        assert_eq!(replay.entries, vec![completed("make")]);
+        assert_eq!(replay.last_seq, 2);
+    }
+
+    #[test]
+    fn discard_through_keeps_entries_appended_after_snapshot() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+
+        let mut journal = Journal::open(&path, 0).unwrap();
+        journal.append(&completed("ls")).unwrap();
+        let snapshot_seq = journal.last_seq();
+        journal.append(&completed("pwd")).unwrap();
+
+        journal.discard_through(snapshot_seq).unwrap();
+        journal.append(&completed("make")).unwrap();
+        journal.flush().unwrap();
+
+        let replay = Journal::replay_after(&path, 0).unwrap();
+        assert_eq!(replay.entries, vec![completed("pwd"), completed("make")]);
+        assert_eq!(replay.last_seq, 3);
+    }
+
+    #[test]
+    fn entries_queued_while_syncing_are_written_in_order() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("journal.log");
+
+        let mut journal = Journal::open(&path, 0).unwrap();
+        let commands: Vec<String> = (0..500).map(|i| format!("cmd {}", i)).collect();
+        for command in &commands {
+            journal.append(&completed(command)).unwrap();
+        }
+        drop(journal);
+
+        // The thread writes everything still queued before it stops.
+        let entries = (0..100)
+            .map(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                Journal::replay_after(&path, 0).unwrap().entries
+            })
+            .find(|entries| entries.len() == commands.len())
+            .expect("queued entries were not written");
+        let expected: Vec<JournalEntry> = commands.iter().map(|c| completed(c)).collect();
+        assert_eq!(entries, expected);
+    }
+}
@@ -1,12 +1,14 @@
 use anyhow::Result;
+use std::path::Path;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{EnvFilter, fmt};
 
-pub fn setup_daemon_logging() -> Result<tracing_appender::non_blocking::WorkerGuard> {
-    let data_dir = dirs::data_local_dir()
-        .expect("Could not find local data directory")
-        .join("t_trace");
-    std::fs::create_dir_all(&data_dir)?;
+/// Logs to `daemon.log` in the data directory. `RUST_LOG` takes precedence over `log_level`.
+pub fn setup_daemon_logging(
+    data_dir: &Path,
+    log_level: &str,
+) -> Result<tracing_appender::non_blocking::WorkerGuard> {
+    std::fs::create_dir_all(data_dir)?;
 
     let log_file_path = data_dir.join("daemon.log");
     let file_appender = tracing_appender::rolling::never(data_dir, "daemon.log");
@@ -18,7 +20,7 @@ pub fn setup_daemon_logging() -> Result<tracing_appender::non_blocking::WorkerGu
                 .with_writer(non_blocking_appender)
                 .with_ansi(false),
         )
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")));
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(log_level)));
 
     tracing::subscriber::set_global_default(subscriber)
         .expect("Unable to set global default subscriber");
@@ -0,0 +1,348 @@
+//! The Prometheus endpoint: a minimal HTTP server answering `GET /metrics` in the text
+//! exposition format.
+
+use super::state::SharedDaemonState;
+
+use crate::config::ListenAddress;
+use crate::dto::{CommandStats, DurationHistogram};
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+use tracing::{debug, info, warn};
+
+/// Longest request head read before the request is rejected.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Time a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves `/metrics` on `address` until the daemon stops.
+pub async fn serve(address: ListenAddress, state: SharedDaemonState) -> Result<()> {
+    match &address {
+        ListenAddress::Tcp(socket_address) => {
+            let listener = TcpListener::bind(socket_address).await?;
+            info!("Serving metrics on http://{}/metrics.", socket_address);
+            loop {
+                let (stream, _peer) = listener.accept().await?;
+                tokio::spawn(handle_request(stream, state.clone()));
+            }
+        }
+        ListenAddress::Unix(path) => {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)?;
+            info!("Serving metrics on {}.", address);
+            loop {
+                let (stream, _peer) = listener.accept().await?;
+                tokio::spawn(handle_request(stream, state.clone()));
+            }
+        }
+    }
+}
+
+async fn handle_request<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    state: SharedDaemonState,
+) {
+    let request_line =
+        match tokio::time::timeout(REQUEST_TIMEOUT, read_request_line(&mut stream)).await {
+            Ok(Some(request_line)) => request_line,
+            Ok(None) | Err(_) => return,
+        };
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let path = target.split('?').next().unwrap_or_default();
+    let response = match (method, path) {
+        ("GET" | "HEAD", "/metrics") => {
+            let (stats, in_flight) = {
+                let state_guard = state.lock().await;
+                (
+                    state_guard.aggregated_stats.clone(),
+                    state_guard.in_flight_count(),
+                )
+            };
+            let body = render(&stats, in_flight);
+            response("200 OK", CONTENT_TYPE, &body, method == "HEAD")
+        }
+        ("GET" | "HEAD", _) => response("404 Not Found", "text/plain", "Not found\n", false),
+        _ => response(
+            "405 Method Not Allowed",
+            "text/plain",
+            "Only GET is supported\n",
+            false,
+        ),
+    };
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        debug!("Failed to write metrics response: {}", e);
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// Reads the request head and returns its first line, e.g. `GET /metrics HTTP/1.1`.
+async fn read_request_line<S: AsyncRead + Unpin>(stream: &mut S) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+        if head.len() > MAX_REQUEST_SIZE {
+            warn!("Rejecting a metrics request with an oversized head.");
+            return None;
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    head.lines().next().map(str::to_string)
+}
+
+fn response(status: &str, content_type: &str, body: &str, head_only: bool) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        if head_only { "" } else { body }
+    )
+}
+
+/// The stats of every command in the Prometheus text format.
+///
+/// Durations are in seconds. The histogram buckets are those of [`DurationHistogram`], whose
+/// bounds double from 1ms; interrupted runs are left out of it, as they are of the mean times.
+pub fn render(stats: &HashMap<String, CommandStats>, in_flight: usize) -> String {
+    let mut commands: Vec<(&String, &CommandStats)> = stats.iter().collect();
+    commands.sort_by_key(|(command, _stats)| *command);
+    let mut output = String::new();
+
+    header(
+        &mut output,
+        "t_trace_command_runs_total",
+        "counter",
+        "Finished runs of a command, by outcome.",
+    );
+    for (command, stats) in &commands {
+        let label = escape_label(command);
+        for (outcome, count) in [
+            ("success", stats.success_count),
+            ("failure", stats.fail_count),
+            ("signal", stats.signal_count),
+            ("interrupted", stats.interrupted_count),
+            ("abandoned", stats.abandoned_count),
+        ] {
+            let _ = writeln!(
+                output,
+                "t_trace_command_runs_total{{command=\"{}\",outcome=\"{}\"}} {}",
+                label, outcome, count
+            );
+        }
+    }
+
+    header(
+        &mut output,
+        "t_trace_command_duration_seconds",
+        "histogram",
+        "Duration of the completed runs of a command, interrupted runs excluded.",
+    );
+    for (command, stats) in &commands {
+        let label = escape_label(command);
+        let histogram = &stats.duration_histogram;
+        let mut cumulative = 0;
+        // The last bucket is unbounded, so it only shows up in `+Inf`.
+        for bucket in 0..DurationHistogram::BUCKETS - 1 {
+            cumulative += histogram
+                .buckets()
+                .find(|(stored, _count)| *stored == bucket)
+                .map_or(0, |(_bucket, count)| count);
+            let _ = writeln!(
+                output,
+                "t_trace_command_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                label,
+                DurationHistogram::upper_bound(bucket).as_secs_f64(),
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            output,
+            "t_trace_command_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+            label,
+            histogram.count()
+        );
+        let _ = writeln!(
+            output,
+            "t_trace_command_duration_seconds_sum{{command=\"{}\"}} {}",
+            label,
+            stats.total_duration.as_secs_f64()
+        );
+        let _ = writeln!(
+            output,
+            "t_trace_command_duration_seconds_count{{command=\"{}\"}} {}",
+            label,
+            histogram.count()
+        );
+    }
+
+    header(
+        &mut output,
+        "t_trace_command_interrupted_seconds_total",
+        "counter",
+        "Time spent in runs of a command that were interrupted.",
+    );
+    for (command, stats) in &commands {
+        let _ = writeln!(
+            output,
+            "t_trace_command_interrupted_seconds_total{{command=\"{}\"}} {}",
+            escape_label(command),
+            stats.interrupted_duration.as_secs_f64()
+        );
+    }
+
+    header(
+        &mut output,
+        "t_trace_command_last_run_timestamp_seconds",
+        "gauge",
+        "When a command last finished, as a Unix timestamp.",
+    );
+    for (command, stats) in &commands {
+        if let Some(last_run_at) = stats.last_run_at {
+            let _ = writeln!(
+                output,
+                "t_trace_command_last_run_timestamp_seconds{{command=\"{}\"}} {}",
+                escape_label(command),
+                last_run_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64()
+            );
+        }
+    }
+
+    header(
+        &mut output,
+        "t_trace_tracked_commands",
+        "gauge",
+        "Distinct commands with stats.",
+    );
+    let _ = writeln!(output, "t_trace_tracked_commands {}", commands.len());
+    header(
+        &mut output,
+        "t_trace_in_flight_commands",
+        "gauge",
+        "Commands running right now.",
+    );
+    let _ = writeln!(output, "t_trace_in_flight_commands {}", in_flight);
+
+    output
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::state::DaemonState;
+    use std::sync::Arc;
+    use tokio::io::duplex;
+    use tokio::sync::Mutex;
+
+    fn sample_stats() -> HashMap<String, CommandStats> {
+        let mut histogram = DurationHistogram::default();
+        for millis in [300, 400, 2_500] {
+            histogram.record(Duration::from_millis(millis));
+        }
+        let stats = CommandStats {
+            total_duration: Duration::from_millis(3_200),
+            success_count: 2,
+            fail_count: 1,
+            interrupted_count: 1,
+            duration_histogram: histogram,
+            last_run_at: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            ..Default::default()
+        };
+        HashMap::from([("echo \"hi\"".to_string(), stats)])
+    }
+
+    #[test]
+    fn stats_are_rendered_as_counters_and_cumulative_histograms() {
+        let output = render(&sample_stats(), 2);
+
+        assert!(output.contains("# TYPE t_trace_command_runs_total counter\n"));
+        assert!(output.contains(
+            "t_trace_command_runs_total{command=\"echo \\\"hi\\\"\",outcome=\"failure\"} 1\n"
+        ));
+        assert!(output.contains(
+            "t_trace_command_runs_total{command=\"echo \\\"hi\\\"\",outcome=\"interrupted\"} 1\n"
+        ));
+        for (le, count) in [("0.256", 0), ("0.512", 2), ("2.048", 2), ("4.096", 3)] {
+            assert!(
+                output.contains(&format!(
+                    "t_trace_command_duration_seconds_bucket{{command=\"echo \\\"hi\\\"\",le=\"{}\"}} {}\n",
+                    le, count
+                )),
+                "le={}",
+                le
+            );
+        }
+        assert!(output.contains("le=\"+Inf\"} 3\n"));
+        assert!(
+            output.contains(
+                "t_trace_command_duration_seconds_sum{command=\"echo \\\"hi\\\"\"} 3.2\n"
+            )
+        );
+        assert!(output.contains(
+            "t_trace_command_last_run_timestamp_seconds{command=\"echo \\\"hi\\\"\"} 1700000000\n"
+        ));
+        assert!(output.contains("t_trace_tracked_commands 1\n"));
+        assert!(output.contains("t_trace_in_flight_commands 2\n"));
+    }
+
+    async fn request(raw: &str) -> String {
+        let state = Arc::new(Mutex::new(DaemonState {
+            aggregated_stats: sample_stats(),
+            ..Default::default()
+        }));
+        let (mut client, server) = duplex(64 * 1024);
+        let serving = tokio::spawn(handle_request(server, state));
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        serving.await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_over_http() {
+        let response = request("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(body.contains("t_trace_tracked_commands 1\n"));
+
+        let response = request("GET /other HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = request("POST /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
@@ -1,6 +1,24 @@
+mod hooks;
+mod journal;
 mod logging;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod notifications;
+mod options;
+#[cfg(feature = "otel")]
+mod otel;
 mod process;
+mod redaction;
+mod regressions;
+mod rules;
+mod sessions;
 mod state;
 mod storage;
+mod writer;
 
-pub use process::run;
+pub use hooks::{Hook, HookSettings, HookTrigger};
+pub use notifications::NotificationRules;
+pub use options::{DaemonOptions, StorageKind};
+pub use process::{check_storage, run};
+pub use regressions::RegressionRules;
+pub use rules::TrackingRules;
@@ -0,0 +1,255 @@
+use crate::config::{duration_format, regex_list_format};
+use crate::dto::{TerminalAlert, TerminalNotification, exit_code_label, is_interrupted_exit};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::warn;
+
+/// Decides which finished commands are announced, and how.
+///
+/// Only tracked commands that ran for at least `min_duration` are announced. Runs interrupted
+/// with Ctrl-C are not: whoever interrupted them is already looking.
+///
+/// This is also the `[notifications]` section of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationRules {
+    pub enabled: bool,
+    /// Commands finishing faster than this are not announced.
+    #[serde(with = "duration_format")]
+    pub min_duration: Duration,
+    /// If not empty, only commands matching one of these patterns are announced.
+    #[serde(rename = "only", with = "regex_list_format")]
+    pub only_patterns: Vec<Regex>,
+    /// Commands matching one of these patterns are not announced, e.g. editors or `ssh`.
+    #[serde(rename = "ignore", with = "regex_list_format")]
+    pub ignore_patterns: Vec<Regex>,
+    /// What the shell hook prints in the terminal the command ran in.
+    pub terminal: TerminalAlert,
+    /// Show a desktop notification with `notify-send`.
+    pub desktop: bool,
+    /// Executable run for every announced command, with the details in `T_TRACE_*` variables.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<PathBuf>,
+}
+
+impl Default for NotificationRules {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_duration: Duration::from_secs(30),
+            only_patterns: Vec::new(),
+            ignore_patterns: Vec::new(),
+            terminal: TerminalAlert::Bell,
+            desktop: false,
+            script: None,
+        }
+    }
+}
+
+/// A finished command worth announcing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub command: String,
+    pub duration: Duration,
+    pub exit_code: i32,
+}
+
+impl Notification {
+    pub fn title(&self) -> String {
+        if self.exit_code == 0 {
+            "Command finished".to_string()
+        } else {
+            format!(
+                "Command failed with exit code {}",
+                exit_code_label(self.exit_code)
+            )
+        }
+    }
+
+    pub fn body(&self) -> String {
+        format!("{} ({:.1?})", self.command, self.duration)
+    }
+}
+
+impl NotificationRules {
+    pub fn notification(
+        &self,
+        command: &str,
+        duration: Duration,
+        exit_code: i32,
+    ) -> Option<Notification> {
+        let announced = self.enabled
+            && duration >= self.min_duration
+            && !is_interrupted_exit(exit_code)
+            && (self.only_patterns.is_empty()
+                || self
+                    .only_patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(command)))
+            && !self
+                .ignore_patterns
+                .iter()
+                .any(|pattern| pattern.is_match(command));
+
+        announced.then(|| Notification {
+            command: command.to_string(),
+            duration,
+            exit_code,
+        })
+    }
+
+    /// Starts the desktop notification and the script in the background, and returns what the
+    /// shell hook should print, if anything.
+    pub fn deliver(&self, notification: &Notification) -> Option<TerminalNotification> {
+        if self.desktop {
+            let mut command = Command::new("notify-send");
+            command
+                .arg("--app-name=t_trace")
+                .arg(if notification.exit_code == 0 {
+                    "--urgency=normal"
+                } else {
+                    "--urgency=critical"
+                })
+                .arg(notification.title())
+                .arg(notification.body());
+            spawn_detached(command);
+        }
+        if let Some(script) = &self.script {
+            let mut command = Command::new(script);
+            command
+                .env("T_TRACE_COMMAND", &notification.command)
+                .env(
+                    "T_TRACE_DURATION_MS",
+                    notification.duration.as_millis().to_string(),
+                )
+                .env("T_TRACE_EXIT_CODE", notification.exit_code.to_string())
+                .env("T_TRACE_TITLE", notification.title())
+                .env("T_TRACE_BODY", notification.body());
+            spawn_detached(command);
+        }
+
+        (self.terminal != TerminalAlert::None).then(|| TerminalNotification {
+            alert: self.terminal,
+            title: notification.title(),
+            body: notification.body(),
+        })
+    }
+}
+
+/// Runs `command` without waiting for it, reaping it once it exits.
+fn spawn_detached(mut command: Command) {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    match command.spawn() {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                let _ = child.wait().await;
+            });
+        }
+        Err(e) => warn!(
+            "Failed to run {:?} for a notification: {}",
+            command.as_std().get_program(),
+            e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn enabled() -> NotificationRules {
+        NotificationRules {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_long_runs_of_matching_commands_are_announced() {
+        let rules = NotificationRules {
+            only_patterns: vec![Regex::new("^(cargo|make)").unwrap()],
+            ignore_patterns: vec![Regex::new("^cargo watch").unwrap()],
+            ..enabled()
+        };
+        let long = Duration::from_secs(45);
+
+        let notification = rules.notification("cargo build", long, 101).unwrap();
+        assert_eq!(notification.title(), "Command failed with exit code 101");
+        assert_eq!(notification.body(), "cargo build (45.0s)");
+
+        assert!(rules.notification("make", long, 0).is_some());
+        assert!(
+            rules
+                .notification("make", Duration::from_secs(29), 0)
+                .is_none()
+        );
+        assert!(rules.notification("cargo watch", long, 0).is_none());
+        assert!(rules.notification("vim", long, 0).is_none());
+        assert!(rules.notification("make", long, 130).is_none());
+        assert!(
+            NotificationRules::default()
+                .notification("make", long, 0)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn signal_exits_are_named_in_the_title() {
+        let notification = enabled()
+            .notification("sleep 60", Duration::from_secs(60), 137)
+            .unwrap();
+        assert_eq!(
+            notification.title(),
+            "Command failed with exit code 137 (SIGKILL)"
+        );
+    }
+
+    #[tokio::test]
+    async fn script_receives_the_details_and_terminal_gets_its_alert() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("output");
+        let script = dir.path().join("notify.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho \"$T_TRACE_COMMAND|$T_TRACE_DURATION_MS|$T_TRACE_EXIT_CODE\" > {:?}.tmp\nmv {:?}.tmp {:?}\n",
+                output, output, output
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let rules = NotificationRules {
+            terminal: TerminalAlert::Osc9,
+            script: Some(script),
+            ..enabled()
+        };
+        let notification = rules
+            .notification("make -j8", Duration::from_millis(31_500), 0)
+            .unwrap();
+        let terminal = rules.deliver(&notification).unwrap();
+        assert_eq!(terminal.alert, TerminalAlert::Osc9);
+        assert_eq!(terminal.title, "Command finished");
+
+        for _ in 0..200 {
+            if output.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "make -j8|31500|0\n"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::Config;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Where the daemon persists the aggregated stats.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// A single, human-readable `stats.json`, rewritten on every save.
+    Json,
+    /// A `stats.db` SQLite database, updated incrementally.
+    #[default]
+    Sqlite,
+}
+
+impl fmt::Display for StorageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageKind::Json => write!(f, "json"),
+            StorageKind::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+/// Settings given on the `daemon run` command line.
+///
+/// They take precedence over the config file, which in turn overrides the defaults. Lists are
+/// added to the ones from the config file.
+#[derive(Debug, Clone, Default)]
+pub struct DaemonOptions {
+    /// Config file read on startup and re-read on `SIGHUP`.
+    pub config_path: PathBuf,
+    pub in_flight_ttl: Option<Duration>,
+    pub autosave_interval: Option<Duration>,
+    pub storage: Option<StorageKind>,
+    pub redact_patterns: Vec<Regex>,
+    pub ignore_commands: Vec<String>,
+    pub ignore_prefixes: Vec<String>,
+    pub ignore_patterns: Vec<Regex>,
+    pub only_patterns: Vec<Regex>,
+    pub track_space_prefixed: bool,
+    pub min_duration: Option<Duration>,
+}
+
+impl DaemonOptions {
+    /// Reads the config file and applies the command-line settings on top of it.
+    pub fn resolve(&self) -> Result<Config> {
+        let mut config = Config::load(&self.config_path)?;
+
+        if let Some(in_flight_ttl) = self.in_flight_ttl {
+            config.daemon.in_flight_ttl = in_flight_ttl;
+        }
+        if let Some(autosave_interval) = self.autosave_interval {
+            config.daemon.autosave_interval = autosave_interval;
+        }
+        if let Some(storage) = self.storage {
+            config.storage.backend = storage;
+        }
+        config
+            .redaction
+            .patterns
+            .extend(self.redact_patterns.iter().cloned());
+
+        let tracking = &mut config.tracking;
+        tracking
+            .ignore_commands
+            .extend(self.ignore_commands.iter().cloned());
+        tracking
+            .ignore_prefixes
+            .extend(self.ignore_prefixes.iter().cloned());
+        tracking
+            .ignore_patterns
+            .extend(self.ignore_patterns.iter().cloned());
+        tracking
+            .only_patterns
+            .extend(self.only_patterns.iter().cloned());
+        tracking.track_space_prefixed |= self.track_space_prefixed;
+        if let Some(min_duration) = self.min_duration {
+            tracking.min_duration = min_duration;
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn command_line_overrides_config_file_which_overrides_defaults() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            "[daemon]\nin_flight_ttl = \"12h\"\nautosave_interval = \"5m\"\n\n[tracking]\nignore = [\"ls\"]\n",
+        )
+        .unwrap();
+
+        let options = DaemonOptions {
+            config_path,
+            in_flight_ttl: Some(Duration::from_secs(60)),
+            ignore_commands: vec!["clear".to_string()],
+            ..Default::default()
+        };
+        let config = options.resolve().unwrap();
+
+        assert_eq!(config.daemon.in_flight_ttl, Duration::from_secs(60));
+        assert_eq!(config.daemon.autosave_interval, Duration::from_secs(300));
+        assert_eq!(config.storage.backend, StorageKind::Sqlite);
+        assert_eq!(config.tracking.ignore_commands, vec!["ls", "clear"]);
+    }
+}
@@ -0,0 +1,353 @@
+//! Exports every finished command as an OpenTelemetry span, over OTLP/HTTP in the JSON
+//! encoding.
+//!
+//! The exporter follows the daemon's event stream, so it never holds up the shell hooks: spans
+//! are batched and posted from a task of their own, and dropped if the collector cannot keep up.
+
+use crate::config::{CollectorEndpoint, OtelConfig};
+use crate::dto::{DaemonEvent, exit_code_label};
+
+use anyhow::{Result, bail};
+use serde_json::{Value, json};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, warn};
+
+/// Most spans sent in one request.
+const MAX_BATCH: usize = 512;
+
+/// `SPAN_KIND_INTERNAL`: the command is neither a client nor a server call.
+const SPAN_KIND_INTERNAL: u8 = 1;
+const STATUS_CODE_OK: u8 = 1;
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// A finished command, as it is exported.
+#[derive(Debug, Clone, PartialEq)]
+struct CommandSpan {
+    pid: u32,
+    command: String,
+    exit_code: i32,
+    cwd: Option<PathBuf>,
+    start_unix_nanos: u128,
+    end_unix_nanos: u128,
+}
+
+impl CommandSpan {
+    fn from_event(event: DaemonEvent) -> Option<Self> {
+        let DaemonEvent::CommandFinished {
+            pid,
+            command,
+            exit_code,
+            duration_ms,
+            cwd,
+            finished_at_unix_ms,
+            ..
+        } = event
+        else {
+            return None;
+        };
+        let end_unix_nanos = u128::from(finished_at_unix_ms) * 1_000_000;
+        Some(Self {
+            pid,
+            command,
+            exit_code,
+            cwd,
+            start_unix_nanos: end_unix_nanos.saturating_sub(u128::from(duration_ms) * 1_000_000),
+            end_unix_nanos,
+        })
+    }
+
+    /// The span in the OTLP JSON encoding. Every command is the root of a trace of its own.
+    fn to_otlp(&self) -> Value {
+        // Named after the program, so that spans of a command group well in a collector.
+        let name = self.command.split_whitespace().next().unwrap_or_default();
+        let mut attributes = vec![
+            string_attribute("process.command_line", &self.command),
+            int_attribute("process.exit.code", self.exit_code.into()),
+            int_attribute("process.parent_pid", self.pid.into()),
+        ];
+        if let Some(cwd) = &self.cwd {
+            attributes.push(string_attribute(
+                "process.working_directory",
+                &cwd.to_string_lossy(),
+            ));
+        }
+        let status = if self.exit_code == 0 {
+            json!({ "code": STATUS_CODE_OK })
+        } else {
+            json!({
+                "code": STATUS_CODE_ERROR,
+                "message": format!("exit code {}", exit_code_label(self.exit_code)),
+            })
+        };
+        json!({
+            "traceId": random_hex(16),
+            "spanId": random_hex(8),
+            "name": name,
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": self.start_unix_nanos.to_string(),
+            "endTimeUnixNano": self.end_unix_nanos.to_string(),
+            "attributes": attributes,
+            "status": status,
+        })
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP JSON carries 64-bit integers as strings.
+fn int_attribute(key: &str, value: i64) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+/// `bytes` random bytes as lowercase hex, as trace and span IDs are written.
+fn random_hex(bytes: usize) -> String {
+    let mut hex = String::with_capacity(bytes * 2 + 16);
+    while hex.len() < bytes * 2 {
+        // Every `RandomState` is seeded differently, which is random enough for IDs.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(hex.len());
+        hex.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    hex.truncate(bytes * 2);
+    hex
+}
+
+/// The body of an export request carrying `spans`.
+fn export_request(service_name: &str, spans: &[CommandSpan]) -> String {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [string_attribute("service.name", service_name)],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "t_trace", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans.iter().map(CommandSpan::to_otlp).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+    .to_string()
+}
+
+/// Starts exporting the commands finishing from now on. Stops when the daemon shuts down.
+pub fn spawn(
+    endpoint: CollectorEndpoint,
+    config: OtelConfig,
+    events: broadcast::Receiver<DaemonEvent>,
+) {
+    info!("Exporting command spans to {}.", endpoint);
+    tokio::spawn(export_spans(endpoint, config, events));
+}
+
+async fn export_spans(
+    endpoint: CollectorEndpoint,
+    config: OtelConfig,
+    mut events: broadcast::Receiver<DaemonEvent>,
+) {
+    let mut shutting_down = false;
+    while !shutting_down {
+        let mut batch = Vec::new();
+        let mut next = events.recv().await;
+        loop {
+            match next {
+                Ok(DaemonEvent::ShuttingDown) | Err(RecvError::Closed) => {
+                    shutting_down = true;
+                    break;
+                }
+                Ok(event) => batch.extend(CommandSpan::from_event(event)),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(
+                        "Dropped up to {} command spans the collector could not keep up with.",
+                        missed
+                    );
+                }
+            }
+            if batch.len() >= MAX_BATCH {
+                break;
+            }
+            next = match events.try_recv() {
+                Ok(event) => Ok(event),
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                    Err(RecvError::Lagged(missed))
+                }
+                Err(broadcast::error::TryRecvError::Closed) => Err(RecvError::Closed),
+                Err(broadcast::error::TryRecvError::Empty) => break,
+            };
+        }
+        if batch.is_empty() {
+            continue;
+        }
+
+        let body = export_request(&config.service_name, &batch);
+        match tokio::time::timeout(config.timeout, post(&endpoint, &body)).await {
+            Ok(Ok(())) => debug!("Exported {} command span(s).", batch.len()),
+            Ok(Err(e)) => warn!("Failed to export {} command span(s): {}", batch.len(), e),
+            Err(_) => warn!(
+                "Dropped {} command span(s): {} did not answer within {:?}.",
+                batch.len(),
+                endpoint,
+                config.timeout
+            ),
+        }
+    }
+}
+
+/// Sends `body` to the collector and checks that it was accepted.
+async fn post(endpoint: &CollectorEndpoint, body: &str) -> Result<()> {
+    let mut stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port)).await?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint.path,
+        endpoint.authority(),
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // Only the status line matters; the rest of the response is not read.
+    let mut response = Vec::new();
+    let mut buffer = [0; 512];
+    while !response.contains(&b'\n') {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            bail!("The collector closed the connection without answering");
+        }
+        response.extend_from_slice(&buffer[..read]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default().trim();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => bail!("The collector answered {:?}", status_line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    /// Stands in for a collector: accepts one export, answers with `status` and returns the
+    /// request it received.
+    async fn collector(
+        status: &'static str,
+    ) -> (CollectorEndpoint, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let received = tokio::spawn(async move {
+            let (mut stream, _peer) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (endpoint, received)
+    }
+
+    fn finished(exit_code: i32) -> DaemonEvent {
+        DaemonEvent::CommandFinished {
+            pid: 4242,
+            command: "cargo build --release".to_string(),
+            exit_code,
+            duration_ms: 1_500,
+            cwd: Some(PathBuf::from("/srv/app")),
+            finished_at_unix_ms: 1_700_000_000_000,
+            pipe_status: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn commands_become_spans_with_their_attributes_and_status() {
+        let span = CommandSpan::from_event(finished(101)).unwrap().to_otlp();
+
+        assert_eq!(span["name"], "cargo");
+        assert_eq!(span["startTimeUnixNano"], "1699999998500000000");
+        assert_eq!(span["endTimeUnixNano"], "1700000000000000000");
+        assert_eq!(span["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(span["spanId"].as_str().unwrap().len(), 16);
+        assert_eq!(
+            span["attributes"],
+            json!([
+                { "key": "process.command_line", "value": { "stringValue": "cargo build --release" } },
+                { "key": "process.exit.code", "value": { "intValue": "101" } },
+                { "key": "process.parent_pid", "value": { "intValue": "4242" } },
+                { "key": "process.working_directory", "value": { "stringValue": "/srv/app" } },
+            ])
+        );
+        assert_eq!(
+            span["status"],
+            json!({ "code": STATUS_CODE_ERROR, "message": "exit code 101" })
+        );
+        assert_eq!(
+            CommandSpan::from_event(finished(0)).unwrap().to_otlp()["status"],
+            json!({ "code": STATUS_CODE_OK })
+        );
+        assert!(CommandSpan::from_event(DaemonEvent::ShuttingDown).is_none());
+        assert_ne!(random_hex(8), random_hex(8));
+    }
+
+    #[tokio::test]
+    async fn finished_commands_are_posted_to_the_collector() {
+        let (endpoint, received) = collector("200 OK").await;
+        let (events, receiver) = broadcast::channel(16);
+        spawn(endpoint, OtelConfig::default(), receiver);
+
+        events
+            .send(DaemonEvent::StatsSaved { commands: 1 })
+            .unwrap();
+        events.send(finished(0)).unwrap();
+        let request = tokio::time::timeout(Duration::from_secs(5), received)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /v1/traces HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/json\r\n"));
+        let body: Value = serde_json::from_str(body).unwrap();
+        let resource_spans = &body["resourceSpans"][0];
+        assert_eq!(
+            resource_spans["resource"]["attributes"][0]["value"]["stringValue"],
+            "t_trace"
+        );
+        let spans = resource_spans["scopeSpans"][0]["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0]["name"], "cargo");
+    }
+
+    #[tokio::test]
+    async fn rejected_exports_are_reported() {
+        let (endpoint, received) = collector("500 Internal Server Error").await;
+        let body = export_request("t_trace", &[CommandSpan::from_event(finished(0)).unwrap()]);
+
+        let error = post(&endpoint, &body).await.unwrap_err();
+        assert!(error.to_string().contains("500"), "{}", error);
+        received.await.unwrap();
+    }
+}
@@ -1,80 +1,317 @@
+use super::hooks::HookRunner;
+use super::journal::Journal;
 use super::logging;
-use super::state::DaemonState;
+use super::options::{DaemonOptions, StorageKind};
+use super::redaction::Redactor;
+use super::state::{DaemonState, SharedDaemonState};
 use super::storage;
+use super::writer::StateWriter;
 
+use crate::config::{CollectorEndpoint, Config, ListenAddress, OtelConfig};
+use crate::dto::{CommandHistory, DaemonEvent, DaemonStatus};
+use crate::export;
 use crate::protocol::Request;
 use crate::socket::get_socket_path;
 
 use anyhow::Result;
+use nix::errno::Errno;
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
 use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tracing::{error, info, warn};
 
-type SharedDaemonState = Arc<Mutex<DaemonState>>;
+const REAPER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Events kept for subscribers that fall behind before they miss some.
+const EVENT_BUFFER: usize = 256;
+
+/// How long shutdown waits for subscribers to receive the last event.
+const SUBSCRIBER_DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Facts about the running daemon that do not change after startup.
+#[derive(Debug)]
+struct DaemonInfo {
+    started_at: Instant,
+    storage: StorageKind,
+    storage_problems: Vec<String>,
+}
+
+/// Everything a connection handler needs to serve a request.
+#[derive(Clone)]
+struct DaemonContext {
+    state: SharedDaemonState,
+    writer: StateWriter,
+    info: Arc<DaemonInfo>,
+    /// Fans events out to `SUBSCRIBE` connections.
+    events: broadcast::Sender<DaemonEvent>,
+}
+
+impl DaemonContext {
+    /// Sends an event to the subscribers, only building it if there are any.
+    fn publish(&self, event: impl FnOnce() -> DaemonEvent) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(event());
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 enum HandlerResult {
     Response(Option<String>),
+    /// Keep the connection open and stream events over it.
+    Subscribe,
     Shutdown,
 }
 
-pub async fn run() -> Result<()> {
-    let _guard = logging::setup_daemon_logging().expect("Daemon logging setup failed");
+/// Fails if the stats in the data directory were written by a newer version of t_trace, which
+/// [`run`] would refuse to open. Called before daemonizing, while errors still reach a terminal.
+pub fn check_storage(config: &Config) -> Result<()> {
+    storage::check_version(&config.storage.data_dir()?, config.storage.backend)
+}
+
+pub async fn run(options: DaemonOptions) -> Result<()> {
+    let mut config = options.resolve()?;
+    let data_dir = config.storage.data_dir()?;
+    let _guard = logging::setup_daemon_logging(&data_dir, &config.daemon.log_level)
+        .expect("Daemon logging setup failed");
+    info!("Using configuration from {:?}.", options.config_path);
 
-    let initial_stats = storage::load_state()?;
-    let shared_state = Arc::new(Mutex::new(DaemonState {
-        in_flight: Default::default(),
-        aggregated_stats: initial_stats,
-    }));
+    let opened_storage = storage::open(&data_dir, config.storage.backend)
+        .inspect_err(|e| error!("Failed to open the stored stats: {:#}", e))?;
+    let mut initial_state = DaemonState {
+        aggregated_stats: opened_storage.stats,
+        history: opened_storage.history,
+        rules: config.tracking.clone(),
+        redactor: Redactor::new(config.redaction.patterns.clone()),
+        regression_rules: config.regressions.clone(),
+        notification_rules: config.notifications.clone(),
+        hooks: HookRunner::new(config.hooks.clone()),
+        ..Default::default()
+    };
 
-    let socket_path = get_socket_path()?;
+    let journal_path = storage::get_journal_file_path(&data_dir);
+    let replay = Journal::replay_after(&journal_path, opened_storage.journal_seq)?;
+    if !replay.entries.is_empty() {
+        info!(
+            "Replaying {} journal entries not covered by the last save.",
+            replay.entries.len()
+        );
+    }
+    for entry in replay.entries {
+        initial_state.apply(entry);
+    }
+    initial_state.journal = Some(Journal::open(&journal_path, replay.last_seq)?);
+    let shared_state = Arc::new(Mutex::new(initial_state));
+    let (events, _) = broadcast::channel(EVENT_BUFFER);
+    let context = DaemonContext {
+        writer: StateWriter::spawn(
+            Arc::clone(&shared_state),
+            opened_storage.backend,
+            events.clone(),
+        ),
+        state: shared_state,
+        events,
+        info: Arc::new(DaemonInfo {
+            started_at: Instant::now(),
+            storage: config.storage.backend,
+            storage_problems: opened_storage.problems,
+        }),
+    };
+
+    let socket_path = get_socket_path();
     if socket_path.exists() {
         warn!("Removing existing socket at {:?}", &socket_path);
         let _ = std::fs::remove_file(&socket_path);
     }
     let listener = UnixListener::bind(&socket_path)?;
 
+    tokio::spawn(reap_abandoned_commands(
+        Arc::clone(&context.state),
+        config.daemon.in_flight_ttl,
+    ));
+    tokio::spawn(autosave(
+        context.writer.clone(),
+        config.daemon.autosave_interval,
+    ));
+    if let Some(address) = config.metrics.listen.clone() {
+        serve_metrics(address, Arc::clone(&context.state));
+    }
+    if let Some(endpoint) = config.otel.endpoint.clone() {
+        export_spans(endpoint, config.otel.clone(), &context.events);
+    }
+
     let mut sigint = signal(SignalKind::interrupt())?;
     let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
 
     info!("Daemon loop started. Awaiting connections or signals.");
 
     loop {
         tokio::select! {
             Ok((stream, _addr)) = listener.accept() => {
-                let state_clone = Arc::clone(&shared_state);
-                tokio::spawn(handle_connection(stream, state_clone));
+                tokio::spawn(handle_connection(stream, context.clone()));
             },
             _ = sigint.recv() => { info!("SIGINT received, breaking loop."); break; },
             _ = sigterm.recv() => { info!("SIGTERM received, breaking loop."); break; },
+            _ = sighup.recv() => {
+                info!("SIGHUP received, reloading configuration.");
+                reload_config(&options, &context, &mut config).await;
+            },
         }
     }
 
-    shutdown_gracefully(shared_state).await;
+    shutdown_gracefully(&context).await;
 
     Ok(())
 }
 
-async fn shutdown_gracefully(state: SharedDaemonState) {
-    info!("Shutting down gracefully. Saving final state...");
-    let final_state = state.lock().await;
+/// Serves the Prometheus metrics on `address` in the background.
+#[cfg(feature = "metrics")]
+fn serve_metrics(address: ListenAddress, state: SharedDaemonState) {
+    tokio::spawn(async move {
+        if let Err(e) = super::metrics::serve(address.clone(), state).await {
+            error!("Stopped serving metrics on {}: {}", address, e);
+        }
+    });
+}
 
-    if let Err(e) = storage::save_state(&final_state) {
-        error!("Failed to save state during shutdown: {}", e);
-    } else {
-        info!("State saved successfully.");
+#[cfg(not(feature = "metrics"))]
+fn serve_metrics(address: ListenAddress, _state: SharedDaemonState) {
+    warn!(
+        "Not serving metrics on {}: the daemon was built without the `metrics` feature.",
+        address
+    );
+}
+
+#[cfg(feature = "otel")]
+fn export_spans(
+    endpoint: CollectorEndpoint,
+    config: OtelConfig,
+    events: &broadcast::Sender<DaemonEvent>,
+) {
+    super::otel::spawn(endpoint, config, events.subscribe());
+}
+
+#[cfg(not(feature = "otel"))]
+fn export_spans(
+    endpoint: CollectorEndpoint,
+    _config: OtelConfig,
+    _events: &broadcast::Sender<DaemonEvent>,
+) {
+    warn!(
+        "Not exporting spans to {}: the daemon was built without the `otel` feature.",
+        endpoint
+    );
+}
+
+/// Re-reads the config file and applies the settings that can change while the daemon runs:
+/// tracking rules, redaction, regression detection, notifications and hooks. An invalid file
+/// leaves the current settings in place.
+async fn reload_config(options: &DaemonOptions, context: &DaemonContext, config: &mut Config) {
+    let reloaded = match options.resolve() {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            error!("Keeping the current configuration: {:#}", e);
+            return;
+        }
+    };
+    if reloaded.daemon != config.daemon
+        || reloaded.storage != config.storage
+        || reloaded.metrics != config.metrics
+        || reloaded.otel != config.otel
+    {
+        warn!(
+            "Changes to the [daemon], [storage], [metrics] and [otel] sections take effect after a restart."
+        );
     }
 
-    if let Ok(path) = get_socket_path() {
-        let _ = std::fs::remove_file(path);
+    {
+        let mut state_guard = context.state.lock().await;
+        state_guard.rules = reloaded.tracking.clone();
+        state_guard.redactor = Redactor::new(reloaded.redaction.patterns.clone());
+        state_guard.regression_rules = reloaded.regressions.clone();
+        state_guard.notification_rules = reloaded.notifications.clone();
+        state_guard.hooks = HookRunner::new(reloaded.hooks.clone());
     }
+    config.tracking = reloaded.tracking;
+    config.redaction = reloaded.redaction;
+    config.regressions = reloaded.regressions;
+    config.notifications = reloaded.notifications;
+    config.hooks = reloaded.hooks;
+    info!("Configuration reloaded.");
+}
+
+async fn reap_abandoned_commands(state: SharedDaemonState, ttl: Duration) {
+    let mut interval = tokio::time::interval(REAPER_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut state_guard = state.lock().await;
+        let reaped = state_guard.reap_abandoned(ttl, shell_is_alive);
+        if reaped > 0 {
+            info!("Reaped {} abandoned in-flight command(s).", reaped);
+        }
+        let ended = state_guard.sessions.reap(shell_is_alive, SystemTime::now());
+        if ended > 0 {
+            info!(
+                "Ended {} session(s) of shells that exited unannounced.",
+                ended
+            );
+        }
+    }
+}
+
+async fn autosave(writer: StateWriter, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        writer.request_save();
+    }
+}
+
+fn shell_is_alive(pid: u32) -> bool {
+    let Ok(raw_pid) = i32::try_from(pid) else {
+        return false;
+    };
+    if raw_pid <= 0 {
+        return false;
+    }
+
+    match kill(Pid::from_raw(raw_pid), None) {
+        Ok(()) | Err(Errno::EPERM) => true,
+        Err(_) => false,
+    }
+}
+
+async fn shutdown_gracefully(context: &DaemonContext) {
+    info!("Shutting down gracefully. Saving final state...");
+    context.writer.save_now().await;
+    // The journal is written by a thread of its own, which must finish before the process exits.
+    if let Some(journal) = &context.state.lock().await.journal
+        && let Err(e) = journal.flush()
+    {
+        error!("Failed to flush the journal: {}", e);
+    }
+
+    context.publish(|| DaemonEvent::ShuttingDown);
+    let drained = async {
+        while context.events.receiver_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    };
+    let _ = tokio::time::timeout(SUBSCRIBER_DRAIN_TIMEOUT, drained).await;
+
+    let _ = std::fs::remove_file(get_socket_path());
     info!("Daemon has shut down.");
 }
 
-async fn handle_connection(mut stream: UnixStream, state: SharedDaemonState) {
+async fn handle_connection(mut stream: UnixStream, context: DaemonContext) {
     let mut reader = BufReader::new(&mut stream);
     let mut line = String::new();
 
@@ -83,9 +320,9 @@ async fn handle_connection(mut stream: UnixStream, state: SharedDaemonState) {
             return;
         }
 
-        match process_request(&line, &state).await {
+        match process_request(&line, &context).await {
             HandlerResult::Shutdown => {
-                shutdown_gracefully(state).await;
+                shutdown_gracefully(&context).await;
                 std::process::exit(0);
             }
             HandlerResult::Response(Some(response)) => {
@@ -94,29 +331,201 @@ async fn handle_connection(mut stream: UnixStream, state: SharedDaemonState) {
                 }
             }
             HandlerResult::Response(None) => {}
+            HandlerResult::Subscribe => {
+                let events = context.events.subscribe();
+                drop(reader);
+                stream_events(stream, events).await;
+            }
         }
     }
 }
 
-async fn process_request(line: &str, state: &SharedDaemonState) -> HandlerResult {
-    match Request::from_str(line) {
+/// Writes every event to a `SUBSCRIBE` connection as a line of JSON, until the subscriber hangs
+/// up or the daemon shuts down.
+async fn stream_events(mut stream: UnixStream, mut events: broadcast::Receiver<DaemonEvent>) {
+    let (mut reader, mut writer) = stream.split();
+    let mut ignored = [0; 64];
+    loop {
+        let event = tokio::select! {
+            received = events.recv() => match received {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => DaemonEvent::Lagged { missed },
+                Err(RecvError::Closed) => return,
+            },
+            // Subscribers send nothing after their request; reading only notices them leaving.
+            read = reader.read(&mut ignored) => match read {
+                Ok(0) | Err(_) => return,
+                Ok(_) => continue,
+            },
+        };
+
+        let Ok(mut line) = serde_json::to_string(&event) else {
+            continue;
+        };
+        line.push('\n');
+        if writer.write_all(line.as_bytes()).await.is_err() || event == DaemonEvent::ShuttingDown {
+            return;
+        }
+    }
+}
+
+async fn process_request(line: &str, context: &DaemonContext) -> HandlerResult {
+    let state = &context.state;
+    match line.parse::<Request>() {
         Ok(Request::HealthCheck) => HandlerResult::Response(Some("Daemon alive\n".to_string())),
-        Ok(Request::CommandBegin { pid, command }) => {
-            state.lock().await.handle_start(pid, command);
+        Ok(Request::CommandBegin { pid, command, cwd }) => {
+            let mut state_guard = state.lock().await;
+            let command = state_guard.redactor.redact(&command).into_owned();
+            if state_guard.handle_start(pid, command.clone(), cwd.clone()) {
+                context.publish(|| DaemonEvent::CommandStarted {
+                    pid,
+                    command: command.trim_start().to_string(),
+                    cwd,
+                });
+            }
+            HandlerResult::Response(None)
+        }
+        Ok(Request::CommandBackground { pid, job }) => {
+            state.lock().await.handle_background(pid, job);
+            HandlerResult::Response(None)
+        }
+        Ok(Request::CommandEnd {
+            pid,
+            exit_code,
+            job,
+            pipe_status,
+        }) => {
+            let mut state_guard = state.lock().await;
+            let finished = match job {
+                Some(job) => state_guard.handle_job_end(pid, job, exit_code),
+                None => state_guard.handle_end(pid, exit_code, pipe_status),
+            };
+            let Some(finished) = finished else {
+                return HandlerResult::Response(None);
+            };
+            state_guard.hooks.run(&finished);
+            context.publish(|| DaemonEvent::CommandFinished {
+                pid,
+                command: finished.command.clone(),
+                exit_code,
+                duration_ms: finished.duration.as_millis() as u64,
+                cwd: finished.cwd.clone(),
+                finished_at_unix_ms: finished
+                    .finished_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+                pipe_status: finished.pipe_status.clone(),
+            });
+            let rules = &state_guard.notification_rules;
+            let terminal_notification = rules
+                .notification(&finished.command, finished.duration, exit_code)
+                .and_then(|notification| rules.deliver(&notification));
+            HandlerResult::Response(
+                terminal_notification
+                    .and_then(|notification| serde_json::to_string(&notification).ok()),
+            )
+        }
+        Ok(Request::SessionBegin { pid }) => {
+            state.lock().await.sessions.begin(pid, SystemTime::now());
             HandlerResult::Response(None)
         }
-        Ok(Request::CommandEnd { pid, exit_code }) => {
-            state.lock().await.handle_end(pid, exit_code);
+        Ok(Request::SessionEnd { pid }) => {
+            state.lock().await.sessions.end(pid, SystemTime::now());
             HandlerResult::Response(None)
         }
+        Ok(Request::GetSessions) => {
+            let sessions = state.lock().await.sessions.list();
+            HandlerResult::Response(serde_json::to_string(&sessions).ok())
+        }
         Ok(Request::GetStats) => {
-            let state_guard = state.lock().await;
-            if let Err(e) = storage::save_state(&state_guard) {
-                error!("Failed to save state on GET_STATS request: {}", e);
-            }
-            let response = serde_json::to_string(&state_guard.aggregated_stats).ok();
+            context.writer.request_save();
+            let snapshot = state.lock().await.aggregated_stats.clone();
+            let response = serde_json::to_string(&snapshot).ok();
             HandlerResult::Response(response)
         }
+        Ok(Request::GetInFlight) => {
+            let running = state.lock().await.running_commands();
+            HandlerResult::Response(serde_json::to_string(&running).ok())
+        }
+        Ok(Request::GetHistory { command }) => {
+            let history = {
+                let state_guard = state.lock().await;
+                let command = state_guard.redactor.redact(&command).into_owned();
+                CommandHistory {
+                    stats: state_guard.aggregated_stats.get(&command).cloned(),
+                    runs: state_guard
+                        .history
+                        .get(&command)
+                        .map(|runs| runs.iter().cloned().collect())
+                        .unwrap_or_default(),
+                    command,
+                }
+            };
+            HandlerResult::Response(serde_json::to_string(&history).ok())
+        }
+        Ok(Request::GetRegressions) => {
+            let regressions = state.lock().await.regressions();
+            HandlerResult::Response(serde_json::to_string(&regressions).ok())
+        }
+        Ok(Request::Status) => {
+            let status = {
+                let state_guard = state.lock().await;
+                DaemonStatus {
+                    pid: std::process::id(),
+                    uptime: context.info.started_at.elapsed(),
+                    storage: context.info.storage.to_string(),
+                    tracked_commands: state_guard.aggregated_stats.len(),
+                    in_flight_commands: state_guard.in_flight_count(),
+                    problems: context.info.storage_problems.clone(),
+                }
+            };
+            HandlerResult::Response(serde_json::to_string(&status).ok())
+        }
+        Ok(Request::Export { format }) => {
+            let snapshot = state.lock().await.aggregated_stats.clone();
+            match export::render(&snapshot, format) {
+                Ok(document) => HandlerResult::Response(Some(document)),
+                Err(e) => {
+                    error!("Failed to export stats as {}: {}", format, e);
+                    HandlerResult::Response(None)
+                }
+            }
+        }
+        Ok(Request::Import { mode, stats }) => {
+            let imported = state.lock().await.import(stats, mode);
+            info!("Imported stats of {} command(s) ({:?}).", imported, mode);
+            context.writer.request_save();
+            HandlerResult::Response(Some(format!("{}\n", imported)))
+        }
+        Ok(Request::ResetStats { pattern }) => {
+            let removed = state.lock().await.reset(pattern.as_deref());
+            info!("Reset stats of {} command(s).", removed);
+            context.writer.request_save();
+            HandlerResult::Response(Some(format!("{}\n", removed)))
+        }
+        Ok(Request::Forget { command }) => {
+            let forgotten = {
+                let mut state_guard = state.lock().await;
+                let command = state_guard.redactor.redact(&command).into_owned();
+                state_guard.forget(&command)
+            };
+            context.writer.request_save();
+            HandlerResult::Response(Some(format!("{}\n", u8::from(forgotten))))
+        }
+        Ok(Request::Prune {
+            older_than,
+            min_count,
+        }) => {
+            let pruned = state
+                .lock()
+                .await
+                .prune(older_than, min_count, SystemTime::now());
+            info!("Pruned stats of {} command(s).", pruned);
+            context.writer.request_save();
+            HandlerResult::Response(Some(format!("{}\n", pruned)))
+        }
+        Ok(Request::Subscribe) => HandlerResult::Subscribe,
         Ok(Request::Stop) => HandlerResult::Shutdown,
         Err(_) => {
             warn!("Failed to parse request: '{}'", line.trim());
@@ -128,18 +537,31 @@ async fn process_request(line: &str, state: &SharedDaemonState) -> HandlerResult
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::dto::CommandStats;
+    use crate::daemon::NotificationRules;
+    use crate::dto::{
+        CommandStats, Regression, RunRecord, RunningCommand, Session, TerminalAlert,
+        TerminalNotification,
+    };
     use std::collections::HashMap;
     use std::time::Duration;
 
-    fn setup_test_state() -> SharedDaemonState {
-        Arc::new(Mutex::new(DaemonState::default()))
+    fn setup_test_context() -> DaemonContext {
+        DaemonContext {
+            state: Arc::new(Mutex::new(DaemonState::default())),
+            writer: StateWriter::disconnected(),
+            info: Arc::new(DaemonInfo {
+                started_at: Instant::now(),
+                storage: StorageKind::Json,
+                storage_problems: vec!["stats.json was unreadable".to_string()],
+            }),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
     }
 
     #[tokio::test]
     async fn process_request_health_check() {
-        let state = setup_test_state();
-        let result = process_request("HEALTH_CHECK\n", &state).await;
+        let context = setup_test_context();
+        let result = process_request("HEALTH_CHECK\n", &context).await;
         assert_eq!(
             result,
             HandlerResult::Response(Some("Daemon alive\n".to_string()))
@@ -148,16 +570,17 @@ mod tests {
 
     #[tokio::test]
     async fn process_request_stop_signals_shutdown() {
-        let state = setup_test_state();
-        let result = process_request("STOP\n", &state).await;
+        let context = setup_test_context();
+        let result = process_request("STOP\n", &context).await;
         assert_eq!(result, HandlerResult::Shutdown);
     }
 
     #[tokio::test]
     async fn process_request_start_modifies_state() {
-        let state = setup_test_state();
+        let context = setup_test_context();
+        let state = &context.state;
         let request_line = "COMMAND_BEGIN 1234 ls -l";
-        let result = process_request(request_line, &state).await;
+        let result = process_request(request_line, &context).await;
 
         assert_eq!(result, HandlerResult::Response(None));
         let state_guard = state.lock().await;
@@ -166,12 +589,100 @@ mod tests {
         assert_eq!(cmd.command_text, "ls -l");
     }
 
+    #[tokio::test]
+    async fn process_request_start_keeps_the_working_directory() {
+        let context = setup_test_context();
+        process_request("COMMAND_BEGIN 7 make\x1fcwd=/srv/app", &context).await;
+
+        let state_guard = context.state.lock().await;
+        let command = &state_guard.in_flight[&7];
+        assert_eq!(command.command_text, "make");
+        assert_eq!(
+            command.cwd.as_deref(),
+            Some(std::path::Path::new("/srv/app"))
+        );
+    }
+
+    #[tokio::test]
+    async fn tracked_commands_are_published_to_subscribers() {
+        let context = setup_test_context();
+        context.state.lock().await.rules.ignore_commands = vec!["ls".to_string()];
+        let mut events = context.events.subscribe();
+
+        assert_eq!(
+            process_request("SUBSCRIBE", &context).await,
+            HandlerResult::Subscribe
+        );
+        process_request("COMMAND_BEGIN 1 ls", &context).await;
+        process_request("COMMAND_END 1 0", &context).await;
+        process_request("COMMAND_BEGIN 2 make\x1fcwd=/srv", &context).await;
+        process_request("COMMAND_END 2 2", &context).await;
+
+        assert_eq!(
+            events.recv().await.unwrap(),
+            DaemonEvent::CommandStarted {
+                pid: 2,
+                command: "make".to_string(),
+                cwd: Some("/srv".into()),
+            }
+        );
+        let DaemonEvent::CommandFinished {
+            pid,
+            command,
+            exit_code,
+            ..
+        } = events.recv().await.unwrap()
+        else {
+            panic!("Expected the end of make");
+        };
+        assert_eq!((pid, command.as_str(), exit_code), (2, "make", 2));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn events_are_streamed_as_ndjson_until_shutdown() {
+        let (server, client) = UnixStream::pair().unwrap();
+        let (events, receiver) = broadcast::channel(EVENT_BUFFER);
+        let streaming = tokio::spawn(stream_events(server, receiver));
+
+        events
+            .send(DaemonEvent::StatsSaved { commands: 3 })
+            .unwrap();
+        events.send(DaemonEvent::ShuttingDown).unwrap();
+        streaming.await.unwrap();
+
+        let mut output = String::new();
+        BufReader::new(client)
+            .read_to_string(&mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            output,
+            "{\"event\":\"stats_saved\",\"commands\":3}\n{\"event\":\"shutting_down\"}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn streaming_stops_when_the_subscriber_hangs_up() {
+        let (server, client) = UnixStream::pair().unwrap();
+        let (events, receiver) = broadcast::channel(EVENT_BUFFER);
+        let streaming = tokio::spawn(stream_events(server, receiver));
+
+        drop(client);
+        streaming.await.unwrap();
+        assert_eq!(events.receiver_count(), 0);
+    }
+
     #[tokio::test]
     async fn process_request_end_moves_command_to_aggregated() {
-        let state = setup_test_state();
+        let context = setup_test_context();
+        let state = &context.state;
         let cmd_text = "git status".to_string();
-        state.lock().await.handle_start(5678, cmd_text.clone());
-        let result = process_request("COMMAND_END 5678 0", &state).await;
+        state
+            .lock()
+            .await
+            .handle_start(5678, cmd_text.clone(), None);
+        let result = process_request("COMMAND_END 5678 0", &context).await;
 
         assert_eq!(result, HandlerResult::Response(None));
         let state_guard = state.lock().await;
@@ -180,9 +691,37 @@ mod tests {
         assert!(state_guard.aggregated_stats.contains_key(&cmd_text));
     }
 
+    #[tokio::test]
+    async fn process_request_end_replies_with_a_notification_for_long_commands() {
+        let context = setup_test_context();
+        context.state.lock().await.notification_rules = NotificationRules {
+            enabled: true,
+            min_duration: Duration::ZERO,
+            ignore_patterns: vec![regex::Regex::new("^vim").unwrap()],
+            terminal: TerminalAlert::Osc9,
+            ..Default::default()
+        };
+
+        process_request("COMMAND_BEGIN 1 make test", &context).await;
+        let HandlerResult::Response(Some(json)) =
+            process_request("COMMAND_END 1 2", &context).await
+        else {
+            panic!("Expected a notification");
+        };
+        let notification: TerminalNotification = serde_json::from_str(&json).unwrap();
+        assert_eq!(notification.alert, TerminalAlert::Osc9);
+        assert_eq!(notification.title, "Command failed with exit code 2");
+        assert!(notification.body.starts_with("make test ("));
+
+        process_request("COMMAND_BEGIN 1 vim notes", &context).await;
+        let result = process_request("COMMAND_END 1 0", &context).await;
+        assert_eq!(result, HandlerResult::Response(None));
+    }
+
     #[tokio::test]
     async fn process_request_get_stats_returns_json_response() {
-        let state = setup_test_state();
+        let context = setup_test_context();
+        let state = &context.state;
         {
             let mut state_guard = state.lock().await;
             state_guard.aggregated_stats.insert(
@@ -192,11 +731,12 @@ mod tests {
                     last_run_duration: Duration::from_secs(2),
                     success_count: 5,
                     fail_count: 0,
+                    ..Default::default()
                 },
             );
         }
 
-        let result = process_request("GET_STATS", &state).await;
+        let result = process_request("GET_STATS", &context).await;
 
         match result {
             HandlerResult::Response(Some(json)) => {
@@ -211,8 +751,8 @@ mod tests {
 
     #[tokio::test]
     async fn process_request_get_stats_on_empty_state_is_ok() {
-        let state = setup_test_state();
-        let result = process_request("GET_STATS", &state).await;
+        let context = setup_test_context();
+        let result = process_request("GET_STATS", &context).await;
 
         match result {
             HandlerResult::Response(Some(json)) => {
@@ -223,10 +763,274 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn process_request_get_in_flight_lists_running_commands() {
+        let context = setup_test_context();
+        context
+            .state
+            .lock()
+            .await
+            .handle_start(42, "cargo test".to_string(), None);
+
+        let HandlerResult::Response(Some(json)) = process_request("GET_IN_FLIGHT", &context).await
+        else {
+            panic!("Expected a response with JSON data");
+        };
+        let running: Vec<RunningCommand> = serde_json::from_str(&json).unwrap();
+        assert_eq!(running.len(), 1);
+        assert_eq!(running[0].pid, 42);
+        assert_eq!(running[0].command, "cargo test");
+    }
+
+    #[tokio::test]
+    async fn process_request_pairs_overlapping_commands_of_one_shell() {
+        let context = setup_test_context();
+        // `sleep 30 & make; cargo build &`, with `make` failing and the jobs ending at the prompt.
+        process_request("COMMAND_BEGIN 1 sleep 30", &context).await;
+        process_request("COMMAND_BACKGROUND 1 101", &context).await;
+        process_request("COMMAND_BEGIN 1 make", &context).await;
+        process_request("COMMAND_END 1 2", &context).await;
+        process_request("COMMAND_BEGIN 1 cargo build", &context).await;
+        process_request("COMMAND_BACKGROUND 1 102", &context).await;
+
+        let running = context.state.lock().await.running_commands();
+        let mut jobs: Vec<(Option<u32>, &str)> = running
+            .iter()
+            .map(|running| (running.job, running.command.as_str()))
+            .collect();
+        jobs.sort();
+        assert_eq!(jobs, [(Some(101), "sleep 30"), (Some(102), "cargo build")]);
+
+        process_request("COMMAND_END 1 0\x1fjob=102", &context).await;
+        process_request("COMMAND_END 1 143\x1fjob=101", &context).await;
+        process_request("COMMAND_END 1 0\x1fjob=101", &context).await;
+
+        let state_guard = context.state.lock().await;
+        assert_eq!(state_guard.in_flight_count(), 0);
+        let stats = &state_guard.aggregated_stats;
+        assert_eq!(stats["make"].fail_count, 1);
+        assert_eq!(stats["cargo build"].success_count, 1);
+        assert_eq!(stats["sleep 30"].interrupted_count, 1);
+        assert_eq!(stats["sleep 30"].success_count, 0);
+        assert!(stats.values().all(|stats| stats.abandoned_count == 0));
+    }
+
+    #[tokio::test]
+    async fn process_request_sessions_count_the_commands_of_each_shell() {
+        let context = setup_test_context();
+        process_request("SESSION_BEGIN 10", &context).await;
+        process_request("SESSION_BEGIN 20", &context).await;
+        for pid in [10, 10, 20] {
+            process_request(&format!("COMMAND_BEGIN {} make", pid), &context).await;
+            process_request(&format!("COMMAND_END {} 0", pid), &context).await;
+        }
+        let result = process_request("SESSION_END 20", &context).await;
+        assert_eq!(result, HandlerResult::Response(None));
+
+        let HandlerResult::Response(Some(json)) = process_request("GET_SESSIONS", &context).await
+        else {
+            panic!("Expected a response with JSON data");
+        };
+        let sessions: Vec<Session> = serde_json::from_str(&json).unwrap();
+        let mut counts: Vec<(u32, u64, bool)> = sessions
+            .iter()
+            .map(|session| {
+                (
+                    session.pid,
+                    session.command_count,
+                    session.ended_at.is_some(),
+                )
+            })
+            .collect();
+        counts.sort();
+        assert_eq!(counts, [(10, 2, false), (20, 1, true)]);
+    }
+
+    #[tokio::test]
+    async fn process_request_get_history_returns_runs_of_the_redacted_command() {
+        let context = setup_test_context();
+        for (pid, exit_code) in [(1, "0"), (2, "1")] {
+            process_request(
+                &format!("COMMAND_BEGIN {} deploy --token abc", pid),
+                &context,
+            )
+            .await;
+            process_request(&format!("COMMAND_END {} {}", pid, exit_code), &context).await;
+        }
+
+        let HandlerResult::Response(Some(json)) =
+            process_request("GET_HISTORY deploy --token xyz", &context).await
+        else {
+            panic!("Expected a response with JSON data");
+        };
+        let history: CommandHistory = serde_json::from_str(&json).unwrap();
+        assert_eq!(history.command, "deploy --token <redacted>");
+        assert_eq!(history.stats.unwrap().fail_count, 1);
+        assert_eq!(history.runs.len(), 2);
+        assert_eq!(history.runs[1].exit_code, 1);
+
+        let HandlerResult::Response(Some(json)) = process_request("GET_HISTORY ls", &context).await
+        else {
+            panic!("Expected a response with JSON data");
+        };
+        let history: CommandHistory = serde_json::from_str(&json).unwrap();
+        assert!(history.stats.is_none() && history.runs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn process_request_get_regressions_lists_slowed_down_commands() {
+        let context = setup_test_context();
+        let runs = [1, 1, 1, 1, 1, 5].map(|secs| RunRecord {
+            finished_at: SystemTime::now(),
+            duration: Duration::from_secs(secs),
+            exit_code: 0,
+            pipe_status: Vec::new(),
+        });
+        context
+            .state
+            .lock()
+            .await
+            .history
+            .insert("cargo build".to_string(), runs.into_iter().collect());
+
+        let HandlerResult::Response(Some(json)) =
+            process_request("GET_REGRESSIONS", &context).await
+        else {
+            panic!("Expected a response with JSON data");
+        };
+        let regressions: Vec<Regression> = serde_json::from_str(&json).unwrap();
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].command, "cargo build");
+        assert_eq!(regressions[0].baseline, Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn process_request_status_reports_counts_and_problems() {
+        let context = setup_test_context();
+        context
+            .state
+            .lock()
+            .await
+            .handle_start(42, "sleep 10".to_string(), None);
+
+        let result = process_request("STATUS", &context).await;
+
+        match result {
+            HandlerResult::Response(Some(json)) => {
+                let status: DaemonStatus = serde_json::from_str(&json).unwrap();
+                assert_eq!(status.pid, std::process::id());
+                assert_eq!(status.storage, "json");
+                assert_eq!(status.in_flight_commands, 1);
+                assert_eq!(status.tracked_commands, 0);
+                assert_eq!(status.problems, vec!["stats.json was unreadable"]);
+            }
+            _ => panic!("Expected a response with the daemon status"),
+        }
+    }
+
+    #[test]
+    fn shell_is_alive_detects_running_and_invalid_pids() {
+        assert!(shell_is_alive(std::process::id()));
+        assert!(!shell_is_alive(0));
+        assert!(!shell_is_alive(u32::MAX));
+    }
+
+    #[tokio::test]
+    async fn process_request_import_then_export_round_trips() {
+        let context = setup_test_context();
+        let mut stats = HashMap::new();
+        stats.insert(
+            "cargo test".to_string(),
+            CommandStats {
+                success_count: 3,
+                ..Default::default()
+            },
+        );
+        let import = Request::Import {
+            mode: crate::dto::ImportMode::Merge,
+            stats: stats.clone(),
+        };
+
+        let result = process_request(&import.to_string(), &context).await;
+        assert_eq!(result, HandlerResult::Response(Some("1\n".to_string())));
+
+        match process_request("EXPORT csv", &context).await {
+            HandlerResult::Response(Some(csv)) => {
+                let exported = export::parse(&csv, export::ExportFormat::Csv).unwrap();
+                assert_eq!(exported, stats);
+            }
+            _ => panic!("Expected a CSV export"),
+        }
+    }
+
+    #[tokio::test]
+    async fn process_request_forget_removes_command() {
+        let context = setup_test_context();
+        process_request("COMMAND_BEGIN 1 git psuh", &context).await;
+        process_request("COMMAND_END 1 1", &context).await;
+
+        let result = process_request("FORGET git psuh", &context).await;
+        assert_eq!(result, HandlerResult::Response(Some("1\n".to_string())));
+        assert!(context.state.lock().await.aggregated_stats.is_empty());
+
+        let result = process_request("FORGET git psuh", &context).await;
+        assert_eq!(result, HandlerResult::Response(Some("0\n".to_string())));
+    }
+
+    #[tokio::test]
+    async fn process_request_redacts_commands_before_tracking() {
+        let context = setup_test_context();
+        for (pid, token) in [(1, "abc"), (2, "def")] {
+            let begin = format!(
+                "COMMAND_BEGIN {} curl -H 'Authorization: Bearer {}' api",
+                pid, token
+            );
+            process_request(&begin, &context).await;
+            process_request(&format!("COMMAND_END {} 0", pid), &context).await;
+        }
+
+        let state = context.state.lock().await;
+        assert_eq!(state.aggregated_stats.len(), 1);
+        let stats = state
+            .aggregated_stats
+            .get("curl -H 'Authorization: Bearer <redacted>' api")
+            .unwrap();
+        assert_eq!(stats.success_count, 2);
+    }
+
+    #[tokio::test]
+    async fn reload_config_applies_tracking_rules_and_keeps_them_on_error() {
+        let context = setup_test_context();
+        let dir = tempfile::tempdir().unwrap();
+        let options = DaemonOptions {
+            config_path: dir.path().join("config.toml"),
+            ..Default::default()
+        };
+        let mut config = options.resolve().unwrap();
+
+        std::fs::write(
+            &options.config_path,
+            "[tracking]\nignore = [\"ls\"]\n[redaction]\npatterns = [\"acme-[0-9]+\"]\n",
+        )
+        .unwrap();
+        reload_config(&options, &context, &mut config).await;
+        {
+            let state = context.state.lock().await;
+            assert!(!state.rules.should_track("ls"));
+            assert_eq!(state.redactor.redact("deploy acme-42"), "deploy <redacted>");
+        }
+
+        std::fs::write(&options.config_path, "[tracking]\nignore = 42\n").unwrap();
+        reload_config(&options, &context, &mut config).await;
+        assert!(!context.state.lock().await.rules.should_track("ls"));
+        assert_eq!(config.tracking.ignore_commands, vec!["ls"]);
+    }
+
     #[tokio::test]
     async fn process_request_invalid_input_is_handled_gracefully() {
-        let state = setup_test_state();
-        let result = process_request("GARBAGE_COMMAND_DOES_NOT_EXIST", &state).await;
+        let context = setup_test_context();
+        let state = &context.state;
+        let result = process_request("GARBAGE_COMMAND_DOES_NOT_EXIST", &context).await;
 
         assert_eq!(result, HandlerResult::Response(None));
         let state_guard = state.lock().await;
@@ -0,0 +1,159 @@
+use crate::config::duration_format;
+use crate::dto::{Regression, RunRecord, is_interrupted_exit};
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Decides when the last run of a command counts as a regression.
+///
+/// The last completed run is compared with the median (p50) of the completed runs before it.
+/// Interrupted runs are left out, as their duration says nothing about the command.
+///
+/// This is also the `[regressions]` section of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegressionRules {
+    /// How many times slower than the baseline the last run has to be.
+    pub factor: f64,
+    /// Number of runs before the last one that make up the baseline.
+    pub baseline_runs: usize,
+    /// Commands with fewer runs in their baseline are never flagged.
+    pub min_baseline_runs: usize,
+    /// Last runs shorter than this are never flagged, however fast the command used to be.
+    #[serde(with = "duration_format")]
+    pub min_duration: Duration,
+}
+
+impl Default for RegressionRules {
+    fn default() -> Self {
+        Self {
+            factor: 2.0,
+            baseline_runs: 20,
+            min_baseline_runs: 5,
+            min_duration: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RegressionRules {
+    /// Checks the last of `runs`, given oldest first.
+    pub fn detect(&self, command: &str, runs: &VecDeque<RunRecord>) -> Option<Regression> {
+        let mut completed = runs
+            .iter()
+            .rev()
+            .filter(|run| !is_interrupted_exit(run.exit_code));
+        let last_run = completed.next()?.clone();
+        if last_run.duration < self.min_duration {
+            return None;
+        }
+
+        let mut baseline: Vec<Duration> = completed
+            .take(self.baseline_runs)
+            .map(|run| run.duration)
+            .collect();
+        if baseline.is_empty() || baseline.len() < self.min_baseline_runs {
+            return None;
+        }
+        baseline.sort();
+        let median = baseline[(baseline.len() - 1) / 2];
+
+        (last_run.duration.as_secs_f64() > median.as_secs_f64() * self.factor).then(|| Regression {
+            command: command.to_string(),
+            last_run,
+            baseline: median,
+            baseline_runs: baseline.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn runs(runs: &[(u64, i32)]) -> VecDeque<RunRecord> {
+        runs.iter()
+            .map(|(secs, exit_code)| RunRecord {
+                finished_at: SystemTime::UNIX_EPOCH,
+                duration: Duration::from_secs(*secs),
+                exit_code: *exit_code,
+                pipe_status: Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn last_run_slower_than_the_baseline_median_is_a_regression() {
+        let rules = RegressionRules::default();
+        let regression = rules
+            .detect(
+                "cargo build",
+                &runs(&[(10, 0), (12, 0), (11, 0), (30, 0), (9, 0), (25, 0)]),
+            )
+            .unwrap();
+
+        assert_eq!(regression.command, "cargo build");
+        assert_eq!(regression.baseline, Duration::from_secs(11));
+        assert_eq!(regression.baseline_runs, 5);
+        assert_eq!(regression.last_run.duration, Duration::from_secs(25));
+        assert!((regression.slowdown() - 25.0 / 11.0).abs() < 1e-9);
+
+        let steady = runs(&[(10, 0), (12, 0), (11, 0), (30, 0), (9, 0), (21, 0)]);
+        assert!(rules.detect("cargo build", &steady).is_none());
+    }
+
+    #[test]
+    fn interrupted_runs_are_neither_the_last_run_nor_part_of_the_baseline() {
+        let rules = RegressionRules::default();
+        let history = runs(&[
+            (10, 0),
+            (1, 130),
+            (10, 0),
+            (10, 0),
+            (10, 0),
+            (10, 1),
+            (25, 0),
+        ]);
+        assert_eq!(rules.detect("make", &history).unwrap().baseline_runs, 5);
+
+        let interrupted_last = runs(&[(10, 0), (10, 0), (10, 0), (10, 0), (10, 0), (60, 143)]);
+        assert!(rules.detect("make", &interrupted_last).is_none());
+    }
+
+    #[test]
+    fn short_histories_and_short_runs_are_not_flagged() {
+        let rules = RegressionRules::default();
+        assert!(
+            rules
+                .detect("make", &runs(&[(1, 0), (1, 0), (9, 0)]))
+                .is_none()
+        );
+        assert!(rules.detect("make", &runs(&[])).is_none());
+
+        let fast = RegressionRules {
+            min_duration: Duration::from_secs(10),
+            ..Default::default()
+        };
+        assert!(
+            fast.detect(
+                "ls",
+                &runs(&[(1, 0), (1, 0), (1, 0), (1, 0), (1, 0), (5, 0)])
+            )
+            .is_none()
+        );
+
+        let windowed = RegressionRules {
+            baseline_runs: 2,
+            min_baseline_runs: 2,
+            ..Default::default()
+        };
+        let history = runs(&[(1, 0), (1, 0), (1, 0), (10, 0), (10, 0), (15, 0)]);
+        assert!(windowed.detect("make", &history).is_none());
+        assert!(
+            RegressionRules::default()
+                .detect("make", &history)
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use crate::config::{duration_format, regex_list_format};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Decides which commands are tracked at all.
+///
+/// Commands are matched after redaction. A command starting with whitespace is one the shell
+/// kept out of its history (e.g. with `HISTCONTROL=ignorespace`); it is ignored unless
+/// `track_space_prefixed` is set.
+///
+/// This is also the `[tracking]` section of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackingRules {
+    /// Commands ignored when they match exactly, e.g. `ls` or `clear`.
+    #[serde(rename = "ignore")]
+    pub ignore_commands: Vec<String>,
+    /// Commands ignored when they start with one of these, e.g. `cd `.
+    pub ignore_prefixes: Vec<String>,
+    /// Commands ignored when they match one of these patterns.
+    #[serde(with = "regex_list_format")]
+    pub ignore_patterns: Vec<Regex>,
+    /// If not empty, only commands matching one of these patterns are tracked.
+    #[serde(rename = "only", with = "regex_list_format")]
+    pub only_patterns: Vec<Regex>,
+    /// Track commands starting with whitespace instead of ignoring them.
+    pub track_space_prefixed: bool,
+    /// Commands finishing faster than this are not recorded.
+    #[serde(with = "duration_format")]
+    pub min_duration: Duration,
+}
+
+impl TrackingRules {
+    pub fn should_track(&self, command: &str) -> bool {
+        if command.starts_with(char::is_whitespace) && !self.track_space_prefixed {
+            return false;
+        }
+
+        let command = command.trim_start();
+        let ignored = self
+            .ignore_commands
+            .iter()
+            .any(|ignored| command == ignored)
+            || self
+                .ignore_prefixes
+                .iter()
+                .any(|prefix| command.starts_with(prefix.as_str()))
+            || self
+                .ignore_patterns
+                .iter()
+                .any(|pattern| pattern.is_match(command));
+        if ignored {
+            return false;
+        }
+
+        self.only_patterns.is_empty()
+            || self
+                .only_patterns
+                .iter()
+                .any(|pattern| pattern.is_match(command))
+    }
+
+    pub fn should_record(&self, duration: Duration) -> bool {
+        duration >= self.min_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_is_tracked_by_default() {
+        let rules = TrackingRules::default();
+        assert!(rules.should_track("ls"));
+        assert!(rules.should_track("cargo build --release"));
+        assert!(rules.should_record(Duration::ZERO));
+    }
+
+    #[test]
+    fn ignore_rules_match_exact_commands_prefixes_and_patterns() {
+        let rules = TrackingRules {
+            ignore_commands: vec!["ls".to_string(), "clear".to_string()],
+            ignore_prefixes: vec!["cd ".to_string()],
+            ignore_patterns: vec![Regex::new(r"^git (status|diff)\b").unwrap()],
+            ..Default::default()
+        };
+
+        assert!(!rules.should_track("ls"));
+        assert!(rules.should_track("ls -la"));
+        assert!(!rules.should_track("clear"));
+        assert!(!rules.should_track("cd /tmp"));
+        assert!(!rules.should_track("git status --short"));
+        assert!(rules.should_track("git push"));
+    }
+
+    #[test]
+    fn space_prefixed_commands_are_ignored_unless_enabled() {
+        let mut rules = TrackingRules::default();
+        assert!(!rules.should_track(" export TOKEN=x"));
+
+        rules.track_space_prefixed = true;
+        rules.ignore_commands = vec!["ls".to_string()];
+        assert!(rules.should_track(" make"));
+        assert!(!rules.should_track(" ls"));
+    }
+
+    #[test]
+    fn allowlist_limits_tracking_and_ignore_rules_still_apply() {
+        let rules = TrackingRules {
+            only_patterns: vec![Regex::new(r"^(cargo|make)\b").unwrap()],
+            ignore_prefixes: vec!["cargo fmt".to_string()],
+            ..Default::default()
+        };
+
+        assert!(rules.should_track("cargo build"));
+        assert!(rules.should_track("make test"));
+        assert!(!rules.should_track("cargo fmt"));
+        assert!(!rules.should_track("vim Cargo.toml"));
+    }
+
+    #[test]
+    fn short_commands_are_not_recorded() {
+        let rules = TrackingRules {
+            min_duration: Duration::from_millis(500),
+            ..Default::default()
+        };
+        assert!(!rules.should_record(Duration::from_millis(499)));
+        assert!(rules.should_record(Duration::from_millis(500)));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::dto::Session;
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// Number of ended sessions kept for `t_trace sessions`.
+pub const ENDED_SESSIONS_KEPT: usize = 50;
+
+/// The shells the daemon knows about, keyed by their PID while they run.
+///
+/// Shells announce themselves when they start and leave when they exit. Shells that started
+/// before the daemon, or whose messages were dropped, are picked up by their first command.
+/// Sessions only live in memory, like the commands in flight.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    active: HashMap<u32, Session>,
+    /// Most recently ended last.
+    ended: VecDeque<Session>,
+}
+
+impl Sessions {
+    /// Starts a session for the shell `pid`. A session the PID already had is ended first, e.g.
+    /// after `exec bash`.
+    pub fn begin(&mut self, pid: u32, now: SystemTime) {
+        self.end(pid, now);
+        self.active.insert(pid, new_session(pid, now));
+    }
+
+    /// Ends the session of the shell `pid`. Returns whether it had one.
+    pub fn end(&mut self, pid: u32, now: SystemTime) -> bool {
+        let Some(mut session) = self.active.remove(&pid) else {
+            return false;
+        };
+        session.ended_at = Some(now);
+        self.ended.push_back(session);
+        if self.ended.len() > ENDED_SESSIONS_KEPT {
+            self.ended.pop_front();
+        }
+        true
+    }
+
+    /// Counts a tracked command that ran for `duration` until `now` in the shell `pid`.
+    pub fn record_command(&mut self, pid: u32, duration: Duration, now: SystemTime) {
+        let session = self
+            .active
+            .entry(pid)
+            .or_insert_with(|| new_session(pid, now.checked_sub(duration).unwrap_or(now)));
+        session.command_count += 1;
+        session.busy += duration;
+    }
+
+    /// Ends the sessions of shells that exited without saying so, e.g. because they were
+    /// killed. Returns the number of sessions ended.
+    pub fn reap(&mut self, is_shell_alive: impl Fn(u32) -> bool, now: SystemTime) -> usize {
+        let gone: Vec<u32> = self
+            .active
+            .keys()
+            .copied()
+            .filter(|pid| !is_shell_alive(*pid))
+            .collect();
+        for pid in &gone {
+            self.end(*pid, now);
+        }
+        gone.len()
+    }
+
+    /// Ended and active sessions, in the order they started.
+    pub fn list(&self) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self
+            .ended
+            .iter()
+            .chain(self.active.values())
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|session| session.started_at);
+        sessions
+    }
+}
+
+fn new_session(pid: u32, started_at: SystemTime) -> Session {
+    Session {
+        pid,
+        started_at,
+        ended_at: None,
+        command_count: 0,
+        busy: Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    #[test]
+    fn commands_add_up_per_shell() {
+        let mut sessions = Sessions::default();
+        sessions.begin(100, at(0));
+        sessions.begin(200, at(10));
+        sessions.record_command(100, Duration::from_secs(5), at(20));
+        sessions.record_command(100, Duration::from_secs(15), at(60));
+        sessions.record_command(200, Duration::from_secs(1), at(30));
+        assert!(sessions.end(100, at(100)));
+        assert!(!sessions.end(100, at(110)));
+
+        let listed = sessions.list();
+        assert_eq!(listed.len(), 2);
+        let (first, second) = (&listed[0], &listed[1]);
+        assert_eq!((first.pid, first.command_count), (100, 2));
+        assert_eq!(first.busy, Duration::from_secs(20));
+        assert_eq!(first.duration(at(1000)), Duration::from_secs(100));
+        assert_eq!(first.idle(at(1000)), Duration::from_secs(80));
+        assert_eq!((second.pid, second.ended_at), (200, None));
+        assert_eq!(second.idle(at(40)), Duration::from_secs(29));
+    }
+
+    #[test]
+    fn unannounced_shells_start_with_their_first_command() {
+        let mut sessions = Sessions::default();
+        sessions.record_command(300, Duration::from_secs(30), at(100));
+        sessions.begin(400, at(0));
+        sessions.begin(400, at(50));
+
+        let listed = sessions.list();
+        let pids: Vec<u32> = listed.iter().map(|session| session.pid).collect();
+        assert_eq!(pids, [400, 400, 300]);
+        assert_eq!(listed[0].ended_at, Some(at(50)));
+        assert_eq!(listed[2].started_at, at(70));
+
+        assert_eq!(sessions.reap(|pid| pid == 300, at(200)), 1);
+        assert!(
+            sessions
+                .list()
+                .iter()
+                .all(|session| session.pid == 300 || session.ended_at.is_some())
+        );
+    }
+
+    #[test]
+    fn only_the_latest_ended_sessions_are_kept() {
+        let mut sessions = Sessions::default();
+        for pid in 0..ENDED_SESSIONS_KEPT as u32 + 5 {
+            sessions.begin(pid, at(pid as u64));
+            sessions.end(pid, at(pid as u64 + 1));
+        }
+        let listed = sessions.list();
+        assert_eq!(listed.len(), ENDED_SESSIONS_KEPT);
+        assert_eq!(listed[0].pid, 5);
+    }
+}
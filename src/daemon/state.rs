@@ -1,58 +1,471 @@
-use crate::dto::CommandStats;
+use super::hooks::HookRunner;
+use super::journal::{Journal, JournalEntry};
+use super::notifications::NotificationRules;
+use super::redaction::Redactor;
+use super::regressions::RegressionRules;
+use super::rules::TrackingRules;
+use super::sessions::Sessions;
+use super::storage::StatsChanges;
 
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use crate::dto::{
+    CommandStats, ImportMode, Regression, RunRecord, RunningCommand, is_interrupted_exit,
+    is_signal_exit,
+};
+
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+
+pub type SharedDaemonState = Arc<Mutex<DaemonState>>;
+
+/// Number of recent runs kept per command, e.g. for the sparkline of `t_trace show`.
+pub const HISTORY_LENGTH: usize = 200;
 
 #[derive(Debug)]
 pub struct InFlightCommand {
     pub start_time: Instant,
     pub command_text: String,
+    /// Working directory the command was started in, if the shell reported it.
+    pub cwd: Option<PathBuf>,
+}
+
+/// A tracked command that finished and was recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FinishedCommand {
+    pub pid: u32,
+    pub command: String,
+    pub cwd: Option<PathBuf>,
+    pub duration: Duration,
+    pub exit_code: i32,
+    /// Exit codes of the pipeline stages, as reported by the shell.
+    pub pipe_status: Vec<i32>,
+    pub finished_at: SystemTime,
 }
 
 #[derive(Default, Debug)]
 pub struct DaemonState {
     pub in_flight: HashMap<u32, InFlightCommand>,
+    /// Commands sent to the background, by the PID of their shell and then by the PID of the job.
+    pub background_jobs: HashMap<u32, HashMap<u32, InFlightCommand>>,
+    /// The shells commands are tracked in.
+    pub sessions: Sessions,
     pub aggregated_stats: HashMap<String, CommandStats>,
+    /// The most recent runs of each command, oldest first.
+    pub history: HashMap<String, VecDeque<RunRecord>>,
+    /// Commands whose stats or history changed since they were last persisted.
+    pub dirty_commands: HashSet<String>,
+    /// Write-ahead log of changes not yet covered by a saved snapshot.
+    pub journal: Option<Journal>,
+    /// Which commands are tracked and recorded.
+    pub rules: TrackingRules,
+    /// Removes secrets from commands before they are tracked.
+    pub redactor: Redactor,
+    /// When a run counts as a regression.
+    pub regression_rules: RegressionRules,
+    /// Which finished commands are announced.
+    pub notification_rules: NotificationRules,
+    /// Runs the configured hooks after commands finish.
+    pub hooks: HookRunner,
 }
 
 impl DaemonState {
-    pub fn handle_start(&mut self, pid: u32, command_text: String) {
+    /// Starts tracking a command of the shell `pid`. Returns whether it is tracked at all.
+    pub fn handle_start(&mut self, pid: u32, command_text: String, cwd: Option<PathBuf>) -> bool {
+        if !self.rules.should_track(&command_text) {
+            tracing::debug!("Ignoring command of PID {} due to tracking rules.", pid);
+            return false;
+        }
+
         let command = InFlightCommand {
             start_time: Instant::now(),
-            command_text,
+            command_text: command_text.trim_start().to_string(),
+            cwd,
         };
-        self.in_flight.insert(pid, command);
-    }
-
-    pub fn handle_end(&mut self, pid: u32, exit_code: i32) -> Option<Duration> {
-        if let Some(in_flight_command) = self.in_flight.remove(&pid) {
-            let duration = in_flight_command.start_time.elapsed();
-            let is_success = exit_code == 0;
-
-            let stats = self
-                .aggregated_stats
-                .entry(in_flight_command.command_text)
-                .or_insert(CommandStats {
-                    total_duration: Duration::from_secs(0),
-                    last_run_duration: Duration::from_secs(0),
-                    success_count: 0,
-                    fail_count: 0,
-                });
-
-            stats.total_duration += duration;
-            stats.last_run_duration = duration;
-
-            if is_success {
-                stats.success_count += 1;
-            } else {
-                stats.fail_count += 1;
+        if let Some(previous) = self.in_flight.insert(pid, command) {
+            tracing::warn!(
+                "PID {} began a new command before '{}' ended. Recording it as abandoned.",
+                pid,
+                previous.command_text
+            );
+            self.record(JournalEntry::Abandoned {
+                command: previous.command_text,
+            });
+        }
+        true
+    }
+
+    /// Moves the command in flight for `pid` to the background as `job`, where it runs alongside
+    /// the commands the shell starts next. Returns whether the shell had a command in flight.
+    pub fn handle_background(&mut self, pid: u32, job: u32) -> bool {
+        let Some(command) = self.in_flight.remove(&pid) else {
+            return false;
+        };
+        let jobs = self.background_jobs.entry(pid).or_default();
+        if let Some(previous) = jobs.insert(job, command) {
+            tracing::warn!(
+                "PID {} reused job {} before '{}' ended. Recording it as abandoned.",
+                pid,
+                job,
+                previous.command_text
+            );
+            self.record(JournalEntry::Abandoned {
+                command: previous.command_text,
+            });
+        }
+        true
+    }
+
+    /// Records the end of the command in flight for `pid`, along with the exit codes of the
+    /// stages if its command line ended with a pipeline. Returns the finished command if it was
+    /// recorded, i.e. if it was tracked and took at least the minimum duration.
+    pub fn handle_end(
+        &mut self,
+        pid: u32,
+        exit_code: i32,
+        pipe_status: Vec<i32>,
+    ) -> Option<FinishedCommand> {
+        let in_flight_command = self.in_flight.remove(&pid)?;
+        let duration = in_flight_command.start_time.elapsed();
+        let finished_at = SystemTime::now();
+        self.sessions.record_command(pid, duration, finished_at);
+        self.finish(
+            pid,
+            in_flight_command,
+            duration,
+            exit_code,
+            pipe_status,
+            finished_at,
+        )
+    }
+
+    /// Records the end of the background `job` of `pid`, like [`Self::handle_end`].
+    pub fn handle_job_end(
+        &mut self,
+        pid: u32,
+        job: u32,
+        exit_code: i32,
+    ) -> Option<FinishedCommand> {
+        let jobs = self.background_jobs.get_mut(&pid)?;
+        let in_flight_command = jobs.remove(&job)?;
+        if jobs.is_empty() {
+            self.background_jobs.remove(&pid);
+        }
+        let duration = in_flight_command.start_time.elapsed();
+        let finished_at = SystemTime::now();
+        // The shell was free for other commands while the job ran.
+        self.sessions
+            .record_command(pid, Duration::ZERO, finished_at);
+        self.finish(
+            pid,
+            in_flight_command,
+            duration,
+            exit_code,
+            Vec::new(),
+            finished_at,
+        )
+    }
+
+    fn finish(
+        &mut self,
+        pid: u32,
+        in_flight_command: InFlightCommand,
+        duration: Duration,
+        exit_code: i32,
+        pipe_status: Vec<i32>,
+        finished_at: SystemTime,
+    ) -> Option<FinishedCommand> {
+        if !self.rules.should_record(duration) {
+            return None;
+        }
+        let command = in_flight_command.command_text;
+        self.record(JournalEntry::Completed {
+            command: command.clone(),
+            duration,
+            exit_code,
+            finished_at: Some(finished_at),
+            pipe_status: pipe_status.clone(),
+        });
+        if let Some(regression) = self
+            .history
+            .get(&command)
+            .and_then(|runs| self.regression_rules.detect(&command, runs))
+        {
+            tracing::info!(
+                "'{}' took {:.1?}, {:.1}x its median of {:.1?}.",
+                command,
+                duration,
+                regression.slowdown(),
+                regression.baseline
+            );
+        }
+        Some(FinishedCommand {
+            pid,
+            command,
+            cwd: in_flight_command.cwd,
+            duration,
+            exit_code,
+            pipe_status,
+            finished_at,
+        })
+    }
+
+    /// Drops in-flight commands and background jobs whose shell is gone or which exceeded the
+    /// TTL, recording each of them as abandoned. Returns the number of reaped commands.
+    pub fn reap_abandoned(&mut self, ttl: Duration, is_shell_alive: impl Fn(u32) -> bool) -> usize {
+        let orphaned_pids: Vec<u32> = self
+            .in_flight
+            .iter()
+            .filter(|(pid, command)| command.start_time.elapsed() > ttl || !is_shell_alive(**pid))
+            .map(|(pid, _command)| *pid)
+            .collect();
+        let mut abandoned: Vec<String> = orphaned_pids
+            .iter()
+            .filter_map(|pid| self.in_flight.remove(pid))
+            .map(|command| command.command_text)
+            .collect();
+
+        self.background_jobs.retain(|pid, jobs| {
+            let shell_is_alive = is_shell_alive(*pid);
+            jobs.retain(|_job, command| {
+                let keep = shell_is_alive && command.start_time.elapsed() <= ttl;
+                if !keep {
+                    abandoned.push(std::mem::take(&mut command.command_text));
+                }
+                keep
+            });
+            !jobs.is_empty()
+        });
+
+        let reaped = abandoned.len();
+        for command in abandoned {
+            self.record(JournalEntry::Abandoned { command });
+        }
+        reaped
+    }
+
+    /// Number of commands in flight, in the foreground or in the background.
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.len()
+            + self
+                .background_jobs
+                .values()
+                .map(HashMap::len)
+                .sum::<usize>()
+    }
+
+    /// The commands in flight, longest running first.
+    pub fn running_commands(&self) -> Vec<RunningCommand> {
+        let mut running: Vec<RunningCommand> = self
+            .in_flight
+            .iter()
+            .map(|(pid, command)| (*pid, None, command))
+            .chain(self.background_jobs.iter().flat_map(|(pid, jobs)| {
+                jobs.iter()
+                    .map(move |(job, command)| (*pid, Some(*job), command))
+            }))
+            .map(|(pid, job, command)| RunningCommand {
+                pid,
+                job,
+                command: command.command_text.clone(),
+                elapsed: command.start_time.elapsed(),
+            })
+            .collect();
+        running.sort_by_key(|running| Reverse(running.elapsed));
+        running
+    }
+
+    /// Commands whose last run was a regression, largest slowdown first.
+    pub fn regressions(&self) -> Vec<Regression> {
+        let mut regressions: Vec<Regression> = self
+            .history
+            .iter()
+            .filter_map(|(command, runs)| self.regression_rules.detect(command, runs))
+            .collect();
+        regressions.sort_by(|first, second| second.slowdown().total_cmp(&first.slowdown()));
+        regressions
+    }
+
+    /// Merges stats exported elsewhere into the current ones, or replaces them altogether.
+    /// Returns the number of imported commands.
+    pub fn import(&mut self, stats: HashMap<String, CommandStats>, mode: ImportMode) -> usize {
+        let imported = stats.len();
+        self.record(JournalEntry::Imported { stats, mode });
+        imported
+    }
+
+    /// Drops the stats of every command, or only of those containing `pattern` (case-insensitive).
+    /// Returns the number of removed commands.
+    pub fn reset(&mut self, pattern: Option<&str>) -> usize {
+        let pattern = pattern.map(str::to_lowercase);
+        self.remove_where(|command, _stats| match &pattern {
+            Some(pattern) => command.to_lowercase().contains(pattern),
+            None => true,
+        })
+    }
+
+    /// Drops the stats of exactly this command. Returns whether it was tracked.
+    pub fn forget(&mut self, command: &str) -> bool {
+        self.remove_where(|tracked, _stats| tracked == command) > 0
+    }
+
+    /// Drops commands that were last run before `now - older_than` and that ran fewer than
+    /// `min_count` times. Only the given criteria apply; commands without a recorded last run
+    /// are never considered old. Returns the number of removed commands.
+    pub fn prune(
+        &mut self,
+        older_than: Option<Duration>,
+        min_count: Option<u64>,
+        now: SystemTime,
+    ) -> usize {
+        let cutoff = older_than.and_then(|age| now.checked_sub(age));
+        self.remove_where(|_command, stats| {
+            let is_old = match older_than {
+                Some(_) => matches!((stats.last_run_at, cutoff), (Some(last), Some(cutoff)) if last < cutoff),
+                None => true,
+            };
+            let is_rare = min_count.is_none_or(|min_count| stats.run_count() < min_count);
+            is_old && is_rare
+        })
+    }
+
+    fn remove_where(&mut self, predicate: impl Fn(&str, &CommandStats) -> bool) -> usize {
+        let commands: Vec<String> = self
+            .aggregated_stats
+            .iter()
+            .filter(|(command, stats)| predicate(command, stats))
+            .map(|(command, _stats)| command.clone())
+            .collect();
+
+        let removed = commands.len();
+        if removed > 0 {
+            self.record(JournalEntry::Removed { commands });
+        }
+        removed
+    }
+
+    /// Applies a change to the aggregated stats without journaling it, e.g. during replay.
+    pub fn apply(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::Completed {
+                command,
+                duration,
+                exit_code,
+                finished_at,
+                pipe_status,
+            } => {
+                self.dirty_commands.insert(command.clone());
+                let stats = self.aggregated_stats.entry(command.clone()).or_default();
+
+                stats.last_run_duration = duration;
+                stats.last_run_at = finished_at;
+                if exit_code != 0 {
+                    *stats.exit_codes.entry(exit_code).or_default() += 1;
+                }
+
+                if is_interrupted_exit(exit_code) {
+                    stats.interrupted_count += 1;
+                    stats.interrupted_duration += duration;
+                    stats.interrupted_histogram.record(duration);
+                } else {
+                    stats.total_duration += duration;
+                    stats.duration_histogram.record(duration);
+                    if exit_code == 0 {
+                        stats.success_count += 1;
+                    } else if is_signal_exit(exit_code) {
+                        stats.signal_count += 1;
+                    } else {
+                        stats.fail_count += 1;
+                        stats.last_failure_at = finished_at;
+                    }
+                }
+
+                if let Some(finished_at) = finished_at {
+                    let runs = self.history.entry(command).or_default();
+                    runs.push_back(RunRecord {
+                        finished_at,
+                        duration,
+                        exit_code,
+                        pipe_status,
+                    });
+                    if runs.len() > HISTORY_LENGTH {
+                        runs.pop_front();
+                    }
+                }
             }
+            JournalEntry::Abandoned { command } => {
+                self.dirty_commands.insert(command.clone());
+                self.aggregated_stats
+                    .entry(command)
+                    .or_default()
+                    .abandoned_count += 1;
+            }
+            JournalEntry::Imported { stats, mode } => {
+                self.dirty_commands.extend(stats.keys().cloned());
+                match mode {
+                    ImportMode::Merge => {
+                        for (command, imported) in stats {
+                            self.aggregated_stats
+                                .entry(command)
+                                .or_default()
+                                .merge(&imported);
+                        }
+                    }
+                    ImportMode::Replace => {
+                        self.dirty_commands
+                            .extend(self.aggregated_stats.keys().cloned());
+                        self.aggregated_stats = stats;
+                        self.history.clear();
+                    }
+                }
+            }
+            JournalEntry::Removed { commands } => {
+                for command in commands {
+                    self.aggregated_stats.remove(&command);
+                    self.history.remove(&command);
+                    self.dirty_commands.insert(command);
+                }
+            }
+        }
+    }
+
+    /// Takes a copy of the stats and history of all commands changed since the last call.
+    pub fn take_changes(&mut self) -> StatsChanges {
+        let mut changes = StatsChanges::default();
+        for command in std::mem::take(&mut self.dirty_commands) {
+            match self.aggregated_stats.get(&command) {
+                Some(stats) => {
+                    if let Some(runs) = self.history.get(&command) {
+                        changes.history.insert(command.clone(), runs.clone());
+                    }
+                    changes.updated.insert(command, stats.clone());
+                }
+                None => changes.removed.push(command),
+            }
+        }
+        changes.journal_seq = self.journal.as_ref().map_or(0, Journal::last_seq);
+        changes
+    }
 
-            Some(duration)
-        } else {
-            None
+    /// Drops the journal entries covered by a saved snapshot, up to its `journal_seq`.
+    pub fn discard_journal_through(&self, seq: u64) -> Result<()> {
+        match &self.journal {
+            Some(journal) => journal.discard_through(seq),
+            None => Ok(()),
         }
     }
+
+    fn record(&mut self, entry: JournalEntry) {
+        if let Some(journal) = &mut self.journal
+            && let Err(e) = journal.append(&entry)
+        {
+            tracing::error!("Failed to append to the journal: {}", e);
+        }
+        self.apply(entry);
+    }
 }
 
 #[cfg(test)]
@@ -70,6 +483,15 @@ mod tests {
                 last_run_duration: Duration::from_millis(150),
                 success_count: 4,
                 fail_count: 1,
+                signal_count: 1,
+                interrupted_count: 1,
+                interrupted_duration: Duration::from_millis(80),
+                exit_codes: [(1, 1), (130, 1), (137, 1)].into(),
+                last_failure_at: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_699_000_000)),
+                abandoned_count: 2,
+                last_run_at: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+                duration_histogram: [(7, 3), (8, 2)].into_iter().collect(),
+                interrupted_histogram: [(6, 1)].into_iter().collect(),
             };
 
             let json_string = serde_json::to_string(&original_stats).unwrap();
@@ -83,7 +505,7 @@ mod tests {
             let pid = 1234;
             let cmd_text = "sleep 5".to_string();
 
-            state.handle_start(pid, cmd_text.clone());
+            state.handle_start(pid, cmd_text.clone(), None);
 
             assert_eq!(
                 state.in_flight.len(),
@@ -100,12 +522,27 @@ mod tests {
             assert!(in_flight_cmd.start_time.elapsed() < Duration::from_secs(1));
         }
 
+        #[test]
+        fn running_commands_are_listed_longest_running_first() {
+            let mut state = DaemonState::default();
+            state.handle_start(1, "cargo build".to_string(), None);
+            state.handle_start(2, "vim".to_string(), None);
+            state.in_flight.get_mut(&2).unwrap().start_time -= Duration::from_secs(60);
+
+            let running = state.running_commands();
+            assert_eq!(running.len(), 2);
+            assert_eq!(running[0].pid, 2);
+            assert_eq!(running[0].command, "vim");
+            assert!(running[0].elapsed >= Duration::from_secs(60));
+            assert_eq!(running[1].command, "cargo build");
+        }
+
         #[test]
         fn handle_end_does_nothing_for_unknown_pid() {
             let mut state = DaemonState::default();
             let unknown_pid = 999;
 
-            let result = state.handle_end(unknown_pid, 0);
+            let result = state.handle_end(unknown_pid, 0, Vec::new());
 
             assert!(result.is_none(), "Should return None for an unknown PID");
             assert!(
@@ -129,11 +566,12 @@ mod tests {
                 InFlightCommand {
                     start_time: Instant::now(),
                     command_text: cmd_text.clone(),
+                    cwd: None,
                 },
             );
 
             std::thread::sleep(Duration::from_millis(10));
-            let result = state.handle_end(pid, 0);
+            let result = state.handle_end(pid, 0, Vec::new());
 
             assert!(result.is_some(), "Should return the duration");
             assert!(
@@ -146,6 +584,158 @@ mod tests {
             assert_eq!(stats.fail_count, 0);
             assert!(stats.total_duration >= Duration::from_millis(10));
         }
+
+        #[test]
+        fn ignored_commands_never_become_in_flight() {
+            let mut state = DaemonState {
+                rules: TrackingRules {
+                    ignore_commands: vec!["ls".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            state.handle_start(1, "ls".to_string(), None);
+            state.handle_start(2, " export TOKEN=x".to_string(), None);
+            assert!(state.in_flight.is_empty());
+            assert!(state.handle_end(1, 0, Vec::new()).is_none());
+            assert!(state.aggregated_stats.is_empty());
+        }
+
+        #[test]
+        fn space_prefixed_command_is_stored_without_the_space_when_tracked() {
+            let mut state = DaemonState {
+                rules: TrackingRules {
+                    track_space_prefixed: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            state.handle_start(1, " make".to_string(), None);
+            state.handle_end(1, 0, Vec::new());
+            assert!(state.aggregated_stats.contains_key("make"));
+        }
+
+        #[test]
+        fn commands_shorter_than_min_duration_are_dropped() {
+            let mut state = DaemonState {
+                rules: TrackingRules {
+                    min_duration: Duration::from_secs(60),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            state.handle_start(1, "ls".to_string(), None);
+            assert!(state.handle_end(1, 0, Vec::new()).is_none());
+            assert!(state.in_flight.is_empty());
+            assert!(state.aggregated_stats.is_empty());
+            assert!(state.dirty_commands.is_empty());
+        }
+
+        #[test]
+        fn handle_start_records_overwritten_command_as_abandoned() {
+            let mut state = DaemonState::default();
+            let pid = 1234;
+
+            state.handle_start(pid, "vim notes.txt".to_string(), None);
+            state.handle_start(pid, "ls".to_string(), None);
+
+            assert_eq!(state.in_flight.get(&pid).unwrap().command_text, "ls");
+            let stats = state.aggregated_stats.get("vim notes.txt").unwrap();
+            assert_eq!(stats.abandoned_count, 1);
+            assert_eq!(stats.success_count + stats.fail_count, 0);
+        }
+
+        #[test]
+        fn reap_abandoned_drops_commands_of_dead_shells() {
+            let mut state = DaemonState::default();
+            state.handle_start(1, "sleep 100".to_string(), None);
+            state.handle_start(2, "make".to_string(), None);
+
+            let reaped = state.reap_abandoned(Duration::from_secs(3600), |pid| pid != 1);
+
+            assert_eq!(reaped, 1);
+            assert!(state.in_flight.contains_key(&2));
+            assert!(!state.in_flight.contains_key(&1));
+            assert_eq!(
+                state
+                    .aggregated_stats
+                    .get("sleep 100")
+                    .unwrap()
+                    .abandoned_count,
+                1
+            );
+            assert!(!state.aggregated_stats.contains_key("make"));
+        }
+
+        #[test]
+        fn background_jobs_overlap_with_the_foreground_command() {
+            let mut state = DaemonState::default();
+            state.handle_start(1, "cargo build".to_string(), None);
+            assert!(state.handle_background(1, 500));
+            state.handle_start(1, "vim".to_string(), None);
+            state.handle_start(1, "sleep 60".to_string(), None);
+            assert!(state.handle_background(1, 501));
+            assert!(!state.handle_background(1, 502));
+            assert_eq!(state.in_flight_count(), 2);
+
+            let finished = state.handle_job_end(1, 501, 130).unwrap();
+            assert_eq!(finished.command, "sleep 60");
+            assert!(state.handle_job_end(1, 501, 0).is_none());
+            assert!(state.handle_end(1, 0, Vec::new()).is_none());
+            assert_eq!(
+                state.handle_job_end(1, 500, 0).unwrap().command,
+                "cargo build"
+            );
+
+            assert!(state.background_jobs.is_empty());
+            assert_eq!(state.aggregated_stats["vim"].abandoned_count, 1);
+            assert_eq!(state.aggregated_stats["sleep 60"].interrupted_count, 1);
+            assert_eq!(state.aggregated_stats["cargo build"].success_count, 1);
+            let session = &state.sessions.list()[0];
+            assert_eq!(session.command_count, 2);
+            assert_eq!(session.busy, Duration::ZERO);
+        }
+
+        #[test]
+        fn reap_abandoned_drops_background_jobs_of_dead_shells() {
+            let mut state = DaemonState::default();
+            for (pid, job) in [(1, 10), (1, 11), (2, 20)] {
+                state.handle_start(pid, format!("job {}", job), None);
+                state.handle_background(pid, job);
+            }
+            state.handle_start(2, "make".to_string(), None);
+
+            let reaped = state.reap_abandoned(Duration::from_secs(3600), |pid| pid != 1);
+
+            assert_eq!(reaped, 2);
+            assert!(!state.background_jobs.contains_key(&1));
+            assert_eq!(state.in_flight_count(), 2);
+            assert_eq!(state.aggregated_stats["job 11"].abandoned_count, 1);
+            assert_eq!(state.running_commands().len(), 2);
+        }
+
+        #[test]
+        fn reap_abandoned_drops_commands_older_than_ttl() {
+            let mut state = DaemonState::default();
+            state.handle_start(1, "tail -f log".to_string(), None);
+            std::thread::sleep(Duration::from_millis(20));
+
+            let reaped = state.reap_abandoned(Duration::from_millis(10), |_pid| true);
+
+            assert_eq!(reaped, 1);
+            assert!(state.in_flight.is_empty());
+            assert_eq!(
+                state
+                    .aggregated_stats
+                    .get("tail -f log")
+                    .unwrap()
+                    .abandoned_count,
+                1
+            );
+        }
     }
 
     mod component_tests {
@@ -157,9 +747,9 @@ mod tests {
             let cmd_text = "ls -l".to_string();
             let pid = 1234;
 
-            state.handle_start(pid, cmd_text.clone());
+            state.handle_start(pid, cmd_text.clone(), None);
             std::thread::sleep(Duration::from_millis(50));
-            let duration_opt = state.handle_end(pid, 0);
+            let duration_opt = state.handle_end(pid, 0, Vec::new());
 
             assert!(duration_opt.is_some());
             assert!(state.in_flight.is_empty());
@@ -168,6 +758,106 @@ mod tests {
             assert_eq!(stats.success_count, 1);
         }
 
+        #[test]
+        fn exit_codes_are_counted_and_signal_exits_are_not_failures() {
+            let mut state = DaemonState::default();
+            for (pid, exit_code) in [(1, 0), (2, 2), (3, 137), (4, 2)] {
+                state.handle_start(pid, "make".to_string(), None);
+                state.handle_end(pid, exit_code, Vec::new());
+            }
+
+            let stats = state.aggregated_stats.get("make").unwrap();
+            assert_eq!(stats.success_count, 1);
+            assert_eq!(stats.fail_count, 2);
+            assert_eq!(stats.signal_count, 1);
+            assert_eq!(stats.exit_codes.get(&2), Some(&2));
+            assert_eq!(stats.exit_codes.get(&137), Some(&1));
+            assert!(!stats.exit_codes.contains_key(&0));
+            assert!(stats.last_failure_at.is_some());
+        }
+
+        #[test]
+        fn interrupted_runs_are_kept_out_of_failures_and_total_duration() {
+            let mut state = DaemonState::default();
+            state.handle_start(1, "cargo test".to_string(), None);
+            state.handle_end(1, 0, Vec::new());
+            let total_before = state.aggregated_stats["cargo test"].total_duration;
+            state.handle_start(2, "cargo test".to_string(), None);
+            let interrupted = state.handle_end(2, 130, Vec::new()).unwrap().duration;
+
+            let stats = state.aggregated_stats.get("cargo test").unwrap();
+            assert_eq!(stats.interrupted_count, 1);
+            assert_eq!(stats.interrupted_duration, interrupted);
+            assert_eq!(stats.interrupted_histogram.count(), 1);
+            assert_eq!(stats.total_duration, total_before);
+            assert_eq!(stats.fail_count, 0);
+            assert_eq!(stats.signal_count, 0);
+            assert_eq!(stats.exit_codes.get(&130), Some(&1));
+            assert_eq!(stats.last_run_duration, interrupted);
+            assert_eq!(stats.duration_histogram.count(), 1);
+        }
+
+        #[test]
+        fn history_keeps_only_the_most_recent_runs() {
+            let mut state = DaemonState::default();
+            for pid in 0..HISTORY_LENGTH as u32 + 5 {
+                state.handle_start(pid, "make".to_string(), None);
+                state.handle_end(pid, (pid % 2) as i32, Vec::new());
+            }
+
+            let runs = &state.history["make"];
+            assert_eq!(runs.len(), HISTORY_LENGTH);
+            assert_eq!(runs.back().unwrap().exit_code, 0);
+            assert!(runs.front().unwrap().finished_at <= runs.back().unwrap().finished_at);
+            assert_eq!(state.take_changes().history["make"].len(), HISTORY_LENGTH);
+
+            state.forget("make");
+            assert!(state.history.is_empty());
+        }
+
+        #[test]
+        fn pipeline_stages_are_kept_with_the_run() {
+            let mut state = DaemonState::default();
+            let line = "grep foo log | sort | head".to_string();
+            state.handle_start(1, line.clone(), None);
+            let finished = state.handle_end(1, 0, vec![0, 141, 0]).unwrap();
+            assert_eq!(finished.pipe_status, [0, 141, 0]);
+            state.handle_start(1, line.clone(), None);
+            state.handle_end(1, 1, Vec::new());
+
+            let runs = &state.history[&line];
+            assert_eq!(runs[0].pipe_status, [0, 141, 0]);
+            assert!(runs[1].pipe_status.is_empty());
+            assert_eq!(state.aggregated_stats[&line].success_count, 1);
+        }
+
+        #[test]
+        fn regressions_are_listed_largest_slowdown_first() {
+            let mut state = DaemonState::default();
+            for (command, last_secs) in [("make", 30), ("cargo build", 50), ("ls", 11)] {
+                for secs in [10, 10, 10, 10, 10, last_secs] {
+                    state.apply(JournalEntry::Completed {
+                        command: command.to_string(),
+                        duration: Duration::from_secs(secs),
+                        exit_code: 0,
+                        finished_at: Some(SystemTime::now()),
+                        pipe_status: Vec::new(),
+                    });
+                }
+            }
+
+            let regressions = state.regressions();
+            let commands: Vec<&str> = regressions
+                .iter()
+                .map(|regression| regression.command.as_str())
+                .collect();
+            assert_eq!(commands, vec!["cargo build", "make"]);
+            assert_eq!(regressions[0].baseline, Duration::from_secs(10));
+
+            state.regression_rules.factor = 4.0;
+            assert_eq!(state.regressions().len(), 1);
+        }
+
         #[test]
         fn multiple_commands_are_aggregated_correctly() {
             let mut state = DaemonState::default();
@@ -175,13 +865,13 @@ mod tests {
             let pid1 = 1001;
             let pid2 = 1002;
 
-            state.handle_start(pid1, cmd_text.clone());
+            state.handle_start(pid1, cmd_text.clone(), None);
             std::thread::sleep(Duration::from_millis(20));
-            let duration1 = state.handle_end(pid1, 0).unwrap();
+            let duration1 = state.handle_end(pid1, 0, Vec::new()).unwrap().duration;
 
-            state.handle_start(pid2, cmd_text.clone());
+            state.handle_start(pid2, cmd_text.clone(), None);
             std::thread::sleep(Duration::from_millis(30));
-            let duration2 = state.handle_end(pid2, 1).unwrap();
+            let duration2 = state.handle_end(pid2, 1, Vec::new()).unwrap().duration;
 
             let stats = state.aggregated_stats.get(&cmd_text).unwrap();
             assert_eq!(stats.success_count, 1);
@@ -189,5 +879,186 @@ mod tests {
             assert_eq!(stats.total_duration, duration1 + duration2);
             assert_eq!(stats.last_run_duration, duration2);
         }
+
+        #[test]
+        fn journaled_changes_can_be_replayed_into_fresh_state() {
+            let dir = tempfile::tempdir().unwrap();
+            let journal_path = dir.path().join("journal.log");
+
+            let mut state = DaemonState {
+                journal: Some(Journal::open(&journal_path, 0).unwrap()),
+                ..Default::default()
+            };
+            state.handle_start(1, "cargo build".to_string(), None);
+            state.handle_end(1, 0, Vec::new());
+            state.handle_start(2, "vim".to_string(), None);
+            state.handle_start(2, "cargo test".to_string(), None);
+            state.handle_end(2, 101, Vec::new());
+            assert_eq!(state.dirty_commands.len(), 3);
+
+            state.journal.as_ref().unwrap().flush().unwrap();
+            let mut recovered = DaemonState::default();
+            for entry in Journal::replay_after(&journal_path, 0).unwrap().entries {
+                recovered.apply(entry);
+            }
+            assert_eq!(recovered.aggregated_stats, state.aggregated_stats);
+            assert!(
+                recovered
+                    .aggregated_stats
+                    .get("cargo build")
+                    .unwrap()
+                    .last_run_at
+                    .is_some()
+            );
+
+            let saved_seq = state.take_changes().journal_seq;
+            state.discard_journal_through(saved_seq).unwrap();
+            state.journal.as_ref().unwrap().flush().unwrap();
+            assert!(
+                Journal::replay_after(&journal_path, 0)
+                    .unwrap()
+                    .entries
+                    .is_empty()
+            );
+        }
+
+        #[test]
+        fn import_merges_into_existing_stats() {
+            let mut state = DaemonState::default();
+            state.handle_start(1, "ls".to_string(), None);
+            state.handle_end(1, 0, Vec::new());
+
+            let mut imported = HashMap::new();
+            imported.insert(
+                "ls".to_string(),
+                CommandStats {
+                    success_count: 4,
+                    ..Default::default()
+                },
+            );
+            imported.insert("make".to_string(), CommandStats::default());
+
+            assert_eq!(state.import(imported, ImportMode::Merge), 2);
+            assert_eq!(state.aggregated_stats.len(), 2);
+            assert_eq!(state.aggregated_stats.get("ls").unwrap().success_count, 5);
+        }
+
+        #[test]
+        fn import_replace_drops_commands_missing_from_import() {
+            let mut state = DaemonState::default();
+            state.handle_start(1, "ls".to_string(), None);
+            state.handle_end(1, 0, Vec::new());
+            state.take_changes();
+
+            let mut imported = HashMap::new();
+            imported.insert("make".to_string(), CommandStats::default());
+            state.import(imported, ImportMode::Replace);
+
+            assert_eq!(state.aggregated_stats.len(), 1);
+            assert!(state.aggregated_stats.contains_key("make"));
+            let changes = state.take_changes();
+            assert_eq!(changes.removed, vec!["ls".to_string()]);
+            assert!(changes.updated.contains_key("make"));
+        }
+
+        fn state_with_commands(commands: &[&str]) -> DaemonState {
+            let mut state = DaemonState::default();
+            for (pid, command) in (1..).zip(commands) {
+                state.handle_start(pid, command.to_string(), None);
+                state.handle_end(pid, 0, Vec::new());
+            }
+            state.take_changes();
+            state
+        }
+
+        #[test]
+        fn reset_with_pattern_removes_only_matching_commands() {
+            let mut state = state_with_commands(&["git push", "GIT status", "ls"]);
+
+            assert_eq!(state.reset(Some("git")), 2);
+            assert_eq!(state.aggregated_stats.len(), 1);
+            assert!(state.aggregated_stats.contains_key("ls"));
+
+            let mut removed = state.take_changes().removed;
+            removed.sort();
+            assert_eq!(removed, vec!["GIT status", "git push"]);
+        }
+
+        #[test]
+        fn reset_without_pattern_removes_everything() {
+            let mut state = state_with_commands(&["git push", "ls"]);
+            assert_eq!(state.reset(None), 2);
+            assert!(state.aggregated_stats.is_empty());
+        }
+
+        #[test]
+        fn forget_removes_only_the_exact_command() {
+            let mut state = state_with_commands(&["git psuh", "git push"]);
+
+            assert!(state.forget("git psuh"));
+            assert!(!state.forget("git psuh"));
+            assert_eq!(state.aggregated_stats.len(), 1);
+            assert!(state.aggregated_stats.contains_key("git push"));
+        }
+
+        #[test]
+        fn prune_drops_stale_rarely_used_commands() {
+            let now = SystemTime::now();
+            let day = Duration::from_secs(24 * 60 * 60);
+            let mut state = DaemonState::default();
+            let mut add = |command: &str, runs: u64, last_run_at: Option<SystemTime>| {
+                state.aggregated_stats.insert(
+                    command.to_string(),
+                    CommandStats {
+                        success_count: runs,
+                        last_run_at,
+                        ..Default::default()
+                    },
+                );
+            };
+            add("old one-off", 1, Some(now - 100 * day));
+            add("old favourite", 50, Some(now - 100 * day));
+            add("recent one-off", 1, Some(now - day));
+            add("unknown age", 1, None);
+
+            assert_eq!(state.prune(Some(90 * day), Some(2), now), 1);
+            assert!(!state.aggregated_stats.contains_key("old one-off"));
+            assert_eq!(state.aggregated_stats.len(), 3);
+
+            assert_eq!(state.prune(Some(90 * day), None, now), 1);
+            assert!(!state.aggregated_stats.contains_key("old favourite"));
+
+            assert_eq!(state.prune(None, Some(2), now), 2);
+            assert!(state.aggregated_stats.is_empty());
+        }
+
+        #[test]
+        fn removals_are_replayed_from_the_journal() {
+            let dir = tempfile::tempdir().unwrap();
+            let journal_path = dir.path().join("journal.log");
+
+            let mut state = DaemonState {
+                journal: Some(Journal::open(&journal_path, 0).unwrap()),
+                ..Default::default()
+            };
+            state.handle_start(1, "export TOKEN=secret".to_string(), None);
+            state.handle_end(1, 0, Vec::new());
+            state.handle_start(2, "ls".to_string(), None);
+            state.handle_end(2, 0, Vec::new());
+            state.forget("export TOKEN=secret");
+            state.journal.as_ref().unwrap().flush().unwrap();
+
+            let mut recovered = DaemonState::default();
+            for entry in Journal::replay_after(&journal_path, 0).unwrap().entries {
+                recovered.apply(entry);
+            }
+            assert_eq!(recovered.aggregated_stats, state.aggregated_stats);
+            assert!(
+                recovered
+                    .take_changes()
+                    .removed
+                    .contains(&"export TOKEN=secret".to_string())
+            );
+        }
     }
 }
@@ -0,0 +1,384 @@
+use super::{NewerVersionError, StatsChanges, StorageBackend};
+
+use crate::dto::{CommandStats, RunRecord};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Version of the file format written by `save_stats`.
+///
+/// Version 0 is the original format: a bare map of commands to stats without an envelope.
+const CURRENT_VERSION: u64 = 1;
+
+/// Upgrades the stats payload of version `N` to version `N + 1`, indexed by `N`.
+const MIGRATIONS: [fn(Value) -> Result<Value>; CURRENT_VERSION as usize] = [migrate_v0_to_v1];
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u64,
+    stats: &'a HashMap<String, CommandStats>,
+    /// Recent runs of each command. Optional, so that older builds can still read the file.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    history: &'a HashMap<String, VecDeque<RunRecord>>,
+    /// Optional as well: a file without it predates the numbered journal.
+    #[serde(skip_serializing_if = "is_zero")]
+    journal_seq: u64,
+}
+
+fn is_zero(seq: &u64) -> bool {
+    *seq == 0
+}
+
+/// Stores all stats in a single, human-readable JSON file that is rewritten on every save.
+#[derive(Debug)]
+pub struct JsonStorage {
+    path: PathBuf,
+    stats: HashMap<String, CommandStats>,
+    history: HashMap<String, VecDeque<RunRecord>>,
+    journal_seq: u64,
+}
+
+impl JsonStorage {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            stats: HashMap::new(),
+            history: HashMap::new(),
+            journal_seq: 0,
+        }
+    }
+
+    /// Fails if the file at `path` was written by a newer build. Anything that cannot be read
+    /// passes, to be dealt with when the file is loaded.
+    pub fn check_version(path: &Path) -> Result<()> {
+        let Ok(contents) = std::fs::read(path) else {
+            return Ok(());
+        };
+        let version = serde_json::from_slice::<Value>(&contents)
+            .ok()
+            .and_then(|document| document.get("version").and_then(Value::as_u64));
+        check_supported(version.unwrap_or_default())
+    }
+}
+
+impl StorageBackend for JsonStorage {
+    fn load(&mut self) -> Result<HashMap<String, CommandStats>> {
+        let stored = load_stats(&self.path)?;
+        self.stats = stored.stats;
+        self.history = stored.history;
+        self.journal_seq = stored.journal_seq;
+        Ok(self.stats.clone())
+    }
+
+    fn load_history(&mut self) -> Result<HashMap<String, VecDeque<RunRecord>>> {
+        Ok(self.history.clone())
+    }
+
+    fn load_journal_seq(&mut self) -> Result<u64> {
+        Ok(self.journal_seq)
+    }
+
+    fn save(&mut self, changes: &StatsChanges) -> Result<()> {
+        self.stats.extend(
+            changes
+                .updated
+                .iter()
+                .map(|(command, stats)| (command.clone(), stats.clone())),
+        );
+        for command in changes.updated.keys() {
+            match changes.history.get(command) {
+                Some(runs) => self.history.insert(command.clone(), runs.clone()),
+                None => self.history.remove(command),
+            };
+        }
+        for command in &changes.removed {
+            self.stats.remove(command);
+            self.history.remove(command);
+        }
+        self.journal_seq = self.journal_seq.max(changes.journal_seq);
+        save_stats(
+            &Envelope {
+                version: CURRENT_VERSION,
+                stats: &self.stats,
+                history: &self.history,
+                journal_seq: self.journal_seq,
+            },
+            &self.path,
+        )
+    }
+}
+
+/// Atomically replaces the stats file at `final_path` with the given snapshot.
+fn save_stats(envelope: &Envelope, final_path: &Path) -> Result<()> {
+    let temp_path = final_path.with_extension("json.tmp");
+
+    let temp_file = File::create(&temp_path)
+        .with_context(|| format!("Failed to create temp file: {:?}", &temp_path))?;
+    let mut writer = BufWriter::new(temp_file);
+    serde_json::to_writer_pretty(&mut writer, envelope)
+        .with_context(|| "Failed to serialize state to JSON")?;
+    writer.flush()?;
+    writer
+        .get_ref()
+        .sync_all()
+        .with_context(|| format!("Failed to sync temp file: {:?}", &temp_path))?;
+
+    std::fs::rename(&temp_path, final_path)
+        .with_context(|| "Failed to rename temp file to final path")?;
+    sync_parent_dir(final_path)?;
+
+    tracing::debug!("Successfully saved state to {:?}", final_path);
+    Ok(())
+}
+
+/// Makes a preceding rename durable by syncing the directory entry itself.
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Failed to sync directory: {:?}", dir))?;
+    }
+    Ok(())
+}
+
+/// The contents of a stats file.
+#[derive(Default)]
+struct Stored {
+    stats: HashMap<String, CommandStats>,
+    history: HashMap<String, VecDeque<RunRecord>>,
+    journal_seq: u64,
+}
+
+fn load_stats(path: &Path) -> Result<Stored> {
+    if !path.exists() {
+        tracing::debug!(
+            "No existing state file found at {:?}. Starting fresh.",
+            path
+        );
+        return Ok(Default::default());
+    }
+
+    let file =
+        File::open(path).with_context(|| format!("Failed to open state file: {:?}", path))?;
+    let reader = BufReader::new(file);
+    let mut document: Value =
+        serde_json::from_reader(reader).with_context(|| "Failed to parse state file as JSON")?;
+    // Only an envelope carries the history; a legacy file may well track a command named so.
+    let is_envelope = document.get("version").is_some_and(Value::is_u64);
+    let history = match document.get_mut("history") {
+        Some(history) if is_envelope => serde_json::from_value(history.take())
+            .with_context(|| "Failed to deserialize run history from JSON")?,
+        _ => HashMap::new(),
+    };
+    let journal_seq = match document.get("journal_seq") {
+        Some(seq) if is_envelope => seq.as_u64().unwrap_or_default(),
+        _ => 0,
+    };
+    let stats: HashMap<String, CommandStats> = serde_json::from_value(upgrade(document)?)
+        .with_context(|| "Failed to deserialize state from JSON")?;
+
+    tracing::debug!(
+        "Successfully loaded {} records from state file.",
+        stats.len()
+    );
+    Ok(Stored {
+        stats,
+        history,
+        journal_seq,
+    })
+}
+
+/// Unwraps the stats payload of a state file and runs it through the migration chain.
+fn upgrade(document: Value) -> Result<Value> {
+    let (version, mut stats) = match document {
+        Value::Object(mut envelope) if envelope.get("version").is_some_and(Value::is_u64) => {
+            let version = envelope["version"].as_u64().unwrap_or_default();
+            let stats = envelope.remove("stats").unwrap_or(Value::Null);
+            (version, stats)
+        }
+        legacy => (0, legacy),
+    };
+
+    check_supported(version)?;
+    for migration in &MIGRATIONS[version as usize..] {
+        stats = migration(stats)?;
+    }
+    Ok(stats)
+}
+
+fn check_supported(version: u64) -> Result<()> {
+    if version > CURRENT_VERSION {
+        return Err(NewerVersionError {
+            store: "State file",
+            found: version,
+            supported: CURRENT_VERSION,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Version 1 wrapped the stats into a versioned envelope without changing the stats themselves.
+fn migrate_v0_to_v1(stats: Value) -> Result<Value> {
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::journal::Journal;
+    use crate::daemon::state::DaemonState;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_save_and_load_state() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+
+        let mut storage = JsonStorage::new(stats_path.clone());
+        let initial_stats = storage.load().unwrap();
+        assert!(initial_stats.is_empty());
+
+        let mut state = DaemonState::default();
+        state.handle_start(1, "cmd1".to_string(), None);
+        state.handle_end(1, 0, Vec::new());
+
+        storage.save(&state.take_changes()).unwrap();
+
+        let loaded_stats = JsonStorage::new(stats_path.clone()).load().unwrap();
+        assert_eq!(loaded_stats.len(), 1);
+        assert!(loaded_stats.contains_key("cmd1"));
+        assert_eq!(loaded_stats.get("cmd1").unwrap().success_count, 1);
+        assert!(!stats_path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn journal_entries_saved_before_a_crash_are_not_replayed_twice() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+        let journal_path = dir.path().join("journal.log");
+        let mut state = DaemonState {
+            journal: Some(Journal::open(&journal_path, 0).unwrap()),
+            ..Default::default()
+        };
+        for pid in 1..=3 {
+            state.handle_start(pid, "make".to_string(), None);
+            state.handle_end(pid, 0, Vec::new());
+            if pid == 2 {
+                // The daemon dies after this save, before the journal is cleared.
+                JsonStorage::new(stats_path.clone())
+                    .save(&state.take_changes())
+                    .unwrap();
+            }
+        }
+
+        state.journal.as_ref().unwrap().flush().unwrap();
+
+        let mut storage = JsonStorage::new(stats_path);
+        let mut restarted = DaemonState {
+            aggregated_stats: storage.load().unwrap(),
+            ..Default::default()
+        };
+        let saved_seq = storage.load_journal_seq().unwrap();
+        let replay = Journal::replay_after(&journal_path, saved_seq).unwrap();
+        assert_eq!((saved_seq, replay.last_seq), (2, 3));
+        for entry in replay.entries {
+            restarted.apply(entry);
+        }
+        assert_eq!(restarted.aggregated_stats["make"].success_count, 3);
+    }
+
+    #[test]
+    fn save_keeps_unchanged_commands() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+
+        let mut storage = JsonStorage::new(stats_path.clone());
+        storage.load().unwrap();
+        let mut first = HashMap::new();
+        first.insert("ls".to_string(), CommandStats::default());
+        storage.save(&first.into()).unwrap();
+        let mut second = HashMap::new();
+        second.insert("pwd".to_string(), CommandStats::default());
+        storage.save(&second.into()).unwrap();
+
+        let loaded_stats = JsonStorage::new(stats_path.clone()).load().unwrap();
+        assert_eq!(loaded_stats.len(), 2);
+
+        storage
+            .save(&StatsChanges {
+                updated: HashMap::new(),
+                removed: vec!["ls".to_string()],
+                ..Default::default()
+            })
+            .unwrap();
+        let loaded_stats = JsonStorage::new(stats_path).load().unwrap();
+        assert_eq!(loaded_stats.len(), 1);
+        assert!(loaded_stats.contains_key("pwd"));
+    }
+
+    #[test]
+    fn history_is_saved_and_dropped_with_its_command() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+        let mut state = DaemonState::default();
+        state.handle_start(1, "make".to_string(), None);
+        state.handle_end(1, 2, Vec::new());
+
+        let mut storage = JsonStorage::new(stats_path.clone());
+        storage.save(&state.take_changes()).unwrap();
+        let mut reopened = JsonStorage::new(stats_path.clone());
+        reopened.load().unwrap();
+        let history = reopened.load_history().unwrap();
+        assert_eq!(history["make"], state.history["make"]);
+
+        state.forget("make");
+        storage.save(&state.take_changes()).unwrap();
+        let mut reopened = JsonStorage::new(stats_path);
+        reopened.load().unwrap();
+        assert!(reopened.load_history().unwrap().is_empty());
+    }
+
+    #[test]
+    fn saved_file_carries_current_version() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+        JsonStorage::new(stats_path.clone())
+            .save(&StatsChanges::default())
+            .unwrap();
+
+        let document: Value =
+            serde_json::from_str(&std::fs::read_to_string(&stats_path).unwrap()).unwrap();
+        assert_eq!(document["version"], CURRENT_VERSION);
+    }
+
+    #[test]
+    fn legacy_unversioned_file_is_migrated() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+        std::fs::write(
+            &stats_path,
+            r#"{"ls": {"total_duration": {"secs": 1, "nanos": 0}, "last_run_duration": {"secs": 0, "nanos": 500}, "success_count": 3, "fail_count": 1}}"#,
+        )
+        .unwrap();
+
+        let loaded_stats = JsonStorage::new(stats_path).load().unwrap();
+        let ls = loaded_stats.get("ls").unwrap();
+        assert_eq!(ls.success_count, 3);
+        assert_eq!(ls.abandoned_count, 0);
+    }
+
+    #[test]
+    fn file_from_newer_version_is_rejected() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+        std::fs::write(&stats_path, r#"{"version": 999, "stats": {}}"#).unwrap();
+
+        assert!(JsonStorage::new(stats_path).load().is_err());
+    }
+}
@@ -0,0 +1,347 @@
+mod json;
+mod sqlite;
+
+pub use json::JsonStorage;
+pub use sqlite::SqliteStorage;
+
+use super::options::StorageKind;
+
+use crate::dto::{CommandStats, RunRecord};
+
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// The stats that changed since the previous save.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StatsChanges {
+    /// Commands that were added or updated, with their current stats.
+    pub updated: HashMap<String, CommandStats>,
+    /// Commands that no longer have any stats.
+    pub removed: Vec<String>,
+    /// Recent runs of the updated commands. An updated command missing here has none.
+    pub history: HashMap<String, VecDeque<RunRecord>>,
+    /// Sequence number of the last journal entry these changes include, or zero if they do not
+    /// come from the journal. Stored with the stats so that replay skips what they already hold.
+    pub journal_seq: u64,
+}
+
+impl From<HashMap<String, CommandStats>> for StatsChanges {
+    fn from(updated: HashMap<String, CommandStats>) -> Self {
+        Self {
+            updated,
+            ..Default::default()
+        }
+    }
+}
+
+/// A store written by a newer build, which this one cannot read without losing data.
+#[derive(Debug, Error)]
+#[error("{store} has version {found}, but this build only understands up to version {supported}")]
+pub struct NewerVersionError {
+    pub store: &'static str,
+    pub found: u64,
+    pub supported: u64,
+}
+
+/// A place where the aggregated stats are persisted between daemon runs.
+pub trait StorageBackend: Send {
+    /// Loads all persisted stats.
+    fn load(&mut self) -> Result<HashMap<String, CommandStats>>;
+
+    /// Loads the recent runs of every command. Called after `load`.
+    fn load_history(&mut self) -> Result<HashMap<String, VecDeque<RunRecord>>>;
+
+    /// Sequence number of the last journal entry the persisted stats include. Called after `load`.
+    fn load_journal_seq(&mut self) -> Result<u64>;
+
+    /// Persists the changes made since the previous save.
+    fn save(&mut self, changes: &StatsChanges) -> Result<()>;
+}
+
+/// A freshly opened backend together with the stats it holds.
+pub struct OpenedStorage {
+    pub backend: Box<dyn StorageBackend>,
+    pub stats: HashMap<String, CommandStats>,
+    pub history: HashMap<String, VecDeque<RunRecord>>,
+    /// Sequence number of the last journal entry the stats include.
+    pub journal_seq: u64,
+    /// Problems found while opening, e.g. an unreadable file that was moved aside.
+    pub problems: Vec<String>,
+}
+
+/// Fails if a store in the data directory was written by a newer build, without changing
+/// anything. Lets the CLI report what [`open`] would refuse while there is still a terminal.
+pub fn check_version(data_dir: &Path, kind: StorageKind) -> Result<()> {
+    let json_path = data_dir.join("stats.json");
+    let database_path = data_dir.join("stats.db");
+    let result = JsonStorage::check_version(&json_path).map_err(|e| refuse_newer(&json_path, e));
+    match kind {
+        StorageKind::Json => result,
+        StorageKind::Sqlite => result.and_then(|()| {
+            SqliteStorage::check_version(&database_path)
+                .map_err(|e| refuse_newer(&database_path, e))
+        }),
+    }
+}
+
+pub fn get_journal_file_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("journal.log")
+}
+
+/// Opens the backend of the given kind in the data directory and loads its stats. A store that
+/// cannot be read is moved aside with a timestamp and replaced by an empty one, so that a
+/// damaged file never stops the daemon. A store written by a newer build is left alone and
+/// fails the open instead, since it is not damaged and a downgrade should not lose it.
+pub fn open(data_dir: &Path, kind: StorageKind) -> Result<OpenedStorage> {
+    std::fs::create_dir_all(data_dir)
+        .with_context(|| format!("Failed to create data directory: {:?}", data_dir))?;
+    let json_path = data_dir.join("stats.json");
+    let database_path = data_dir.join("stats.db");
+    let mut problems = Vec::new();
+
+    let (mut backend, stats): (Box<dyn StorageBackend>, _) = match kind {
+        StorageKind::Json => {
+            let mut storage = JsonStorage::new(json_path.clone());
+            let stats = match storage.load() {
+                Ok(stats) => stats,
+                Err(e) => {
+                    back_up_unreadable(&json_path, e, &mut problems)?;
+                    HashMap::new()
+                }
+            };
+            (Box::new(storage), stats)
+        }
+        StorageKind::Sqlite => {
+            let mut storage = match SqliteStorage::open(&database_path) {
+                Ok(storage) => storage,
+                Err(e) => {
+                    back_up_unreadable(&database_path, e, &mut problems)?;
+                    SqliteStorage::open(&database_path)?
+                }
+            };
+            if let Err(e) = migrate_json_to_sqlite(&json_path, &mut storage) {
+                back_up_unreadable(&json_path, e, &mut problems)?;
+            }
+            let stats = storage.load()?;
+            (Box::new(storage), stats)
+        }
+    };
+
+    let history = backend.load_history()?;
+    let journal_seq = backend.load_journal_seq()?;
+
+    Ok(OpenedStorage {
+        backend,
+        stats,
+        history,
+        journal_seq,
+        problems,
+    })
+}
+
+fn back_up_unreadable(path: &Path, error: anyhow::Error, problems: &mut Vec<String>) -> Result<()> {
+    if error.downcast_ref::<NewerVersionError>().is_some() {
+        return Err(refuse_newer(path, error));
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut backup_name = path.file_name().unwrap_or_default().to_os_string();
+    backup_name.push(format!(".corrupt-{}", timestamp));
+    let backup_path = path.with_file_name(backup_name);
+
+    std::fs::rename(path, &backup_path)
+        .with_context(|| format!("Failed to back up unreadable file {:?}", path))?;
+
+    let problem = format!(
+        "Could not read {:?} ({:#}). It was moved to {:?} and the daemon started without it.",
+        path, error, backup_path
+    );
+    tracing::error!("{}", problem);
+    problems.push(problem);
+    Ok(())
+}
+
+fn refuse_newer(path: &Path, error: anyhow::Error) -> anyhow::Error {
+    error.context(format!(
+        "Refusing to start: {:?} was written by a newer version of t_trace",
+        path
+    ))
+}
+
+/// Imports a `stats.json` left behind by the JSON backend into a still empty database,
+/// then moves the JSON file aside so the import happens only once.
+fn migrate_json_to_sqlite(json_path: &Path, storage: &mut SqliteStorage) -> Result<()> {
+    if !json_path.exists() || !storage.is_empty()? {
+        return Ok(());
+    }
+
+    let mut json_storage = JsonStorage::new(json_path.to_path_buf());
+    let stats = json_storage.load()?;
+    let record_count = stats.len();
+    storage.save(&StatsChanges {
+        updated: stats,
+        history: json_storage.load_history()?,
+        journal_seq: json_storage.load_journal_seq()?,
+        ..Default::default()
+    })?;
+
+    let migrated_path = json_path.with_extension("json.migrated");
+    std::fs::rename(json_path, &migrated_path)
+        .with_context(|| format!("Failed to move {:?} aside", json_path))?;
+
+    tracing::info!(
+        "Migrated {} records from {:?} to the SQLite database. The old file was kept as {:?}.",
+        record_count,
+        json_path,
+        migrated_path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn json_stats_are_migrated_to_empty_database_once() {
+        let dir = tempdir().unwrap();
+        let json_path = dir.path().join("stats.json");
+        let mut stats = HashMap::new();
+        stats.insert(
+            "git status".to_string(),
+            CommandStats {
+                success_count: 7,
+                ..Default::default()
+            },
+        );
+        JsonStorage::new(json_path.clone())
+            .save(&StatsChanges {
+                updated: stats.clone(),
+                journal_seq: 42,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut database = SqliteStorage::open(&dir.path().join("stats.db")).unwrap();
+        migrate_json_to_sqlite(&json_path, &mut database).unwrap();
+
+        assert_eq!(database.load().unwrap(), stats);
+        assert_eq!(database.load_journal_seq().unwrap(), 42);
+        assert!(!json_path.exists());
+        assert!(dir.path().join("stats.json.migrated").exists());
+    }
+
+    #[test]
+    fn migration_leaves_populated_database_alone() {
+        let dir = tempdir().unwrap();
+        let json_path = dir.path().join("stats.json");
+        let mut json_stats = HashMap::new();
+        json_stats.insert("ls".to_string(), CommandStats::default());
+        JsonStorage::new(json_path.clone())
+            .save(&json_stats.into())
+            .unwrap();
+
+        let mut database = SqliteStorage::open(&dir.path().join("stats.db")).unwrap();
+        let mut database_stats = HashMap::new();
+        database_stats.insert("pwd".to_string(), CommandStats::default());
+        database.save(&database_stats.clone().into()).unwrap();
+
+        migrate_json_to_sqlite(&json_path, &mut database).unwrap();
+
+        assert_eq!(database.load().unwrap(), database_stats);
+        assert!(json_path.exists());
+    }
+
+    #[test]
+    fn unreadable_json_is_backed_up_and_replaced_by_empty_stats() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("stats.json"), "{ not json").unwrap();
+
+        let opened = open(dir.path(), StorageKind::Json).unwrap();
+
+        assert!(opened.stats.is_empty());
+        assert_eq!(opened.problems.len(), 1);
+        assert!(!dir.path().join("stats.json").exists());
+        let backups: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("stats.json.corrupt-"))
+            .collect();
+        assert_eq!(backups.len(), 1);
+    }
+
+    #[test]
+    fn unreadable_database_is_backed_up_and_recreated() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("stats.db"), "definitely not sqlite").unwrap();
+
+        let opened = open(dir.path(), StorageKind::Sqlite).unwrap();
+
+        assert!(opened.stats.is_empty());
+        assert_eq!(opened.problems.len(), 1);
+        assert!(dir.path().join("stats.db").exists());
+    }
+
+    #[test]
+    fn newer_json_is_refused_and_left_in_place() {
+        let dir = tempdir().unwrap();
+        let json_path = dir.path().join("stats.json");
+        std::fs::write(&json_path, r#"{"version": 999, "stats": {}}"#).unwrap();
+
+        let error = open(dir.path(), StorageKind::Json).err().unwrap();
+
+        assert!(format!("{:#}", error).contains("State file has version 999"));
+        assert!(json_path.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn newer_database_is_refused_and_left_in_place() {
+        let dir = tempdir().unwrap();
+        let database_path = dir.path().join("stats.db");
+        rusqlite::Connection::open(&database_path)
+            .unwrap()
+            .pragma_update(None, "user_version", 999)
+            .unwrap();
+
+        let error = open(dir.path(), StorageKind::Sqlite).err().unwrap();
+
+        assert!(format!("{:#}", error).contains("Database has version 999"));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn version_check_reports_newer_stores_only() {
+        let dir = tempdir().unwrap();
+        assert!(check_version(dir.path(), StorageKind::Sqlite).is_ok());
+
+        std::fs::write(dir.path().join("stats.json"), "{ not json").unwrap();
+        assert!(check_version(dir.path(), StorageKind::Json).is_ok());
+
+        open(dir.path(), StorageKind::Sqlite).unwrap();
+        assert!(check_version(dir.path(), StorageKind::Sqlite).is_ok());
+
+        rusqlite::Connection::open(dir.path().join("stats.db"))
+            .unwrap()
+            .pragma_update(None, "user_version", 999)
+            .unwrap();
+        let error = check_version(dir.path(), StorageKind::Sqlite).unwrap_err();
+        assert!(format!("{:#}", error).contains("Refusing to start"));
+        assert!(format!("{:#}", error).contains("Database has version 999"));
+        assert!(check_version(dir.path(), StorageKind::Json).is_ok());
+    }
+
+    #[test]
+    fn healthy_storage_reports_no_problems() {
+        let dir = tempdir().unwrap();
+        let opened = open(dir.path(), StorageKind::Sqlite).unwrap();
+        assert!(opened.problems.is_empty());
+    }
+}
@@ -0,0 +1,501 @@
+use super::{NewerVersionError, StatsChanges, StorageBackend};
+
+use crate::dto::{CommandStats, RunRecord};
+
+use anyhow::{Context, Result};
+use rusqlite::types::Type;
+use rusqlite::{Connection, OpenFlags, Row, params};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many have run.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE IF NOT EXISTS command_stats (
+        command TEXT PRIMARY KEY NOT NULL,
+        total_duration_ns INTEGER NOT NULL,
+        last_run_duration_ns INTEGER NOT NULL,
+        success_count INTEGER NOT NULL,
+        fail_count INTEGER NOT NULL,
+        abandoned_count INTEGER NOT NULL
+    );
+    ",
+    "
+    ALTER TABLE command_stats ADD COLUMN last_run_at_ms INTEGER;
+    ",
+    "
+    ALTER TABLE command_stats ADD COLUMN signal_count INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE command_stats ADD COLUMN exit_codes TEXT NOT NULL DEFAULT '{}';
+    ALTER TABLE command_stats ADD COLUMN last_failure_at_ms INTEGER;
+    ",
+    "
+    ALTER TABLE command_stats ADD COLUMN interrupted_count INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE command_stats ADD COLUMN interrupted_duration_ns INTEGER NOT NULL DEFAULT 0;
+    ",
+    "
+    ALTER TABLE command_stats ADD COLUMN duration_histogram TEXT NOT NULL DEFAULT '{}';
+    ",
+    "
+    CREATE TABLE command_runs (
+        command TEXT NOT NULL,
+        finished_at_ms INTEGER NOT NULL,
+        duration_ns INTEGER NOT NULL,
+        exit_code INTEGER NOT NULL
+    );
+    CREATE INDEX command_runs_by_command ON command_runs (command);
+    ",
+    "
+    ALTER TABLE command_runs ADD COLUMN pipe_status TEXT NOT NULL DEFAULT '[]';
+    ",
+    "
+    CREATE TABLE journal_state (journal_seq INTEGER NOT NULL);
+    INSERT INTO journal_state (journal_seq) VALUES (0);
+    ",
+    "
+    ALTER TABLE command_stats ADD COLUMN interrupted_histogram TEXT NOT NULL DEFAULT '{}';
+    ",
+];
+
+const UPSERT_STATS: &str = "
+    INSERT INTO command_stats (
+        command, total_duration_ns, last_run_duration_ns, success_count, fail_count, abandoned_count,
+        last_run_at_ms, signal_count, exit_codes, last_failure_at_ms, interrupted_count,
+        interrupted_duration_ns, duration_histogram, interrupted_histogram
+    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+    ON CONFLICT(command) DO UPDATE SET
+        total_duration_ns = excluded.total_duration_ns,
+        last_run_duration_ns = excluded.last_run_duration_ns,
+        success_count = excluded.success_count,
+        fail_count = excluded.fail_count,
+        abandoned_count = excluded.abandoned_count,
+        last_run_at_ms = excluded.last_run_at_ms,
+        signal_count = excluded.signal_count,
+        exit_codes = excluded.exit_codes,
+        last_failure_at_ms = excluded.last_failure_at_ms,
+        interrupted_count = excluded.interrupted_count,
+        interrupted_duration_ns = excluded.interrupted_duration_ns,
+        duration_histogram = excluded.duration_histogram,
+        interrupted_histogram = excluded.interrupted_histogram;
+";
+
+const DELETE_STATS: &str = "DELETE FROM command_stats WHERE command = ?1";
+
+const INSERT_RUN: &str = "
+    INSERT INTO command_runs (command, finished_at_ms, duration_ns, exit_code, pipe_status)
+    VALUES (?1, ?2, ?3, ?4, ?5)
+";
+
+const DELETE_RUNS: &str = "DELETE FROM command_runs WHERE command = ?1";
+
+const UPDATE_JOURNAL_SEQ: &str = "UPDATE journal_state SET journal_seq = MAX(journal_seq, ?1)";
+
+/// Stores stats in a SQLite database, writing only the commands that changed since the last save.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut connection = Connection::open(path)
+            .with_context(|| format!("Failed to open database: {:?}", path))?;
+        migrate(&mut connection)?;
+        Ok(Self { connection })
+    }
+
+    /// Fails if the database at `path` was written by a newer build. Opens it read-only, and
+    /// anything that cannot be read passes, to be dealt with when the database is opened.
+    pub fn check_version(path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let version = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).and_then(
+            |connection| connection.query_row("PRAGMA user_version", [], |row| row.get(0)),
+        );
+        check_supported(version.unwrap_or_default())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        let count: i64 =
+            self.connection
+                .query_row("SELECT COUNT(*) FROM command_stats", [], |row| row.get(0))?;
+        Ok(count == 0)
+    }
+}
+
+impl StorageBackend for SqliteStorage {
+    fn load(&mut self) -> Result<HashMap<String, CommandStats>> {
+        let mut statement = self.connection.prepare(
+            "SELECT command, total_duration_ns, last_run_duration_ns, success_count, fail_count, abandoned_count,
+                    last_run_at_ms, signal_count, exit_codes, last_failure_at_ms, interrupted_count,
+                    interrupted_duration_ns, duration_histogram, interrupted_histogram
+             FROM command_stats",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                CommandStats {
+                    total_duration: nanos_to_duration(row.get(1)?),
+                    last_run_duration: nanos_to_duration(row.get(2)?),
+                    success_count: row.get(3)?,
+                    fail_count: row.get(4)?,
+                    abandoned_count: row.get(5)?,
+                    last_run_at: row.get::<_, Option<i64>>(6)?.map(millis_to_system_time),
+                    signal_count: row.get(7)?,
+                    exit_codes: from_json_column(row, 8)?,
+                    last_failure_at: row.get::<_, Option<i64>>(9)?.map(millis_to_system_time),
+                    interrupted_count: row.get(10)?,
+                    interrupted_duration: nanos_to_duration(row.get(11)?),
+                    duration_histogram: from_json_column(row, 12)?,
+                    interrupted_histogram: from_json_column(row, 13)?,
+                },
+            ))
+        })?;
+
+        let stats = rows
+            .collect::<rusqlite::Result<HashMap<_, _>>>()
+            .with_context(|| "Failed to read stats from database")?;
+        tracing::debug!("Successfully loaded {} records from database.", stats.len());
+        Ok(stats)
+    }
+
+    fn load_history(&mut self) -> Result<HashMap<String, VecDeque<RunRecord>>> {
+        let mut statement = self.connection.prepare(
+            "SELECT command, finished_at_ms, duration_ns, exit_code, pipe_status
+             FROM command_runs ORDER BY rowid",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                RunRecord {
+                    finished_at: millis_to_system_time(row.get(1)?),
+                    duration: nanos_to_duration(row.get(2)?),
+                    exit_code: row.get(3)?,
+                    pipe_status: from_json_column(row, 4)?,
+                },
+            ))
+        })?;
+
+        let mut history: HashMap<String, VecDeque<RunRecord>> = HashMap::new();
+        for row in rows {
+            let (command, run) = row.with_context(|| "Failed to read run history from database")?;
+            history.entry(command).or_default().push_back(run);
+        }
+        Ok(history)
+    }
+
+    fn load_journal_seq(&mut self) -> Result<u64> {
+        self.connection
+            .query_row("SELECT journal_seq FROM journal_state", [], |row| {
+                row.get(0)
+            })
+            .with_context(|| "Failed to read journal position from database")
+    }
+
+    fn save(&mut self, changes: &StatsChanges) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut upsert = transaction.prepare_cached(UPSERT_STATS)?;
+            for (command, stats) in &changes.updated {
+                upsert.execute(params![
+                    command,
+                    duration_to_nanos(stats.total_duration),
+                    duration_to_nanos(stats.last_run_duration),
+                    stats.success_count,
+                    stats.fail_count,
+                    stats.abandoned_count,
+                    stats.last_run_at.map(system_time_to_millis),
+                    stats.signal_count,
+                    serde_json::to_string(&stats.exit_codes)?,
+                    stats.last_failure_at.map(system_time_to_millis),
+                    stats.interrupted_count,
+                    duration_to_nanos(stats.interrupted_duration),
+                    serde_json::to_string(&stats.duration_histogram)?,
+                    serde_json::to_string(&stats.interrupted_histogram)?,
+                ])?;
+            }
+
+            // The few hundred recent runs of a changed command are simply written anew.
+            let mut delete_runs = transaction.prepare_cached(DELETE_RUNS)?;
+            let mut insert_run = transaction.prepare_cached(INSERT_RUN)?;
+            for command in changes.updated.keys().chain(&changes.removed) {
+                delete_runs.execute(params![command])?;
+            }
+            for (command, runs) in &changes.history {
+                for run in runs {
+                    insert_run.execute(params![
+                        command,
+                        system_time_to_millis(run.finished_at),
+                        duration_to_nanos(run.duration),
+                        run.exit_code,
+                        serde_json::to_string(&run.pipe_status)?,
+                    ])?;
+                }
+            }
+
+            let mut delete = transaction.prepare_cached(DELETE_STATS)?;
+            for command in &changes.removed {
+                delete.execute(params![command])?;
+            }
+
+            transaction.execute(UPDATE_JOURNAL_SEQ, params![changes.journal_seq])?;
+        }
+        transaction
+            .commit()
+            .with_context(|| "Failed to commit stats to database")?;
+
+        tracing::debug!(
+            "Successfully saved {} and deleted {} records in database.",
+            changes.updated.len(),
+            changes.removed.len()
+        );
+        Ok(())
+    }
+}
+
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let version: usize = connection
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .with_context(|| "Failed to read database schema version")?;
+    check_supported(version)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction
+            .execute_batch(migration)
+            .with_context(|| format!("Failed to migrate database to version {}", index + 1))?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+        tracing::info!("Migrated database schema to version {}.", index + 1);
+    }
+    Ok(())
+}
+
+fn check_supported(version: usize) -> Result<()> {
+    if version > MIGRATIONS.len() {
+        return Err(NewerVersionError {
+            store: "Database",
+            found: version as u64,
+            supported: MIGRATIONS.len() as u64,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn duration_to_nanos(duration: Duration) -> i64 {
+    i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX)
+}
+
+fn nanos_to_duration(nanos: i64) -> Duration {
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or(0))
+}
+
+fn system_time_to_millis(time: SystemTime) -> i64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    i64::try_from(since_epoch.as_millis()).unwrap_or(i64::MAX)
+}
+
+/// Reads a column holding JSON text, e.g. the exit code counts.
+fn from_json_column<T: DeserializeOwned>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    serde_json::from_str(&row.get::<_, String>(index)?)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn millis_to_system_time(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(u64::try_from(millis).unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn stats(success_count: u64) -> CommandStats {
+        CommandStats {
+            total_duration: Duration::from_millis(1500),
+            last_run_duration: Duration::from_millis(300),
+            success_count,
+            fail_count: 1,
+            signal_count: 1,
+            interrupted_count: 1,
+            interrupted_duration: Duration::from_millis(700),
+            exit_codes: [(2, 1), (130, 1), (137, 1)].into(),
+            last_failure_at: Some(UNIX_EPOCH + Duration::from_millis(1_699_999_000_000)),
+            abandoned_count: 2,
+            last_run_at: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            duration_histogram: [(9, 2), (11, 1)].into_iter().collect(),
+            interrupted_histogram: [(10, 1)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn saved_stats_are_loaded_back() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stats.db");
+
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        assert!(storage.is_empty().unwrap());
+        let mut changed = HashMap::new();
+        changed.insert("cargo build".to_string(), stats(3));
+        storage.save(&changed.clone().into()).unwrap();
+
+        let loaded = SqliteStorage::open(&path).unwrap().load().unwrap();
+        assert_eq!(loaded, changed);
+    }
+
+    #[test]
+    fn save_upserts_only_changed_commands() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stats.db");
+        let mut storage = SqliteStorage::open(&path).unwrap();
+
+        let mut first = HashMap::new();
+        first.insert("ls".to_string(), stats(1));
+        first.insert("pwd".to_string(), stats(1));
+        storage.save(&first.into()).unwrap();
+
+        let mut second = HashMap::new();
+        second.insert("ls".to_string(), stats(5));
+        storage.save(&second.into()).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.get("ls").unwrap().success_count, 5);
+        assert_eq!(loaded.get("pwd").unwrap().success_count, 1);
+    }
+
+    #[test]
+    fn journal_position_only_moves_forward() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stats.db");
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.load_journal_seq().unwrap(), 0);
+
+        for journal_seq in [7, 0] {
+            storage
+                .save(&StatsChanges {
+                    journal_seq,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            SqliteStorage::open(&path)
+                .unwrap()
+                .load_journal_seq()
+                .unwrap(),
+            7
+        );
+    }
+
+    #[test]
+    fn history_replaces_previous_runs_and_goes_with_its_command() {
+        let dir = tempdir().unwrap();
+        let mut storage = SqliteStorage::open(&dir.path().join("stats.db")).unwrap();
+        let run = |millis| RunRecord {
+            finished_at: UNIX_EPOCH + Duration::from_millis(1_700_000_000_000 + millis),
+            duration: Duration::from_millis(millis),
+            exit_code: 0,
+            pipe_status: Vec::new(),
+        };
+
+        let mut changes = StatsChanges::from(HashMap::from([("make".to_string(), stats(1))]));
+        changes
+            .history
+            .insert("make".to_string(), [run(1), run(2)].into());
+        storage.save(&changes).unwrap();
+        let piped = RunRecord {
+            pipe_status: vec![0, 141],
+            ..run(3)
+        };
+        changes
+            .history
+            .insert("make".to_string(), [run(2), piped.clone()].into());
+        storage.save(&changes).unwrap();
+
+        let history = storage.load_history().unwrap();
+        assert_eq!(history["make"], VecDeque::from([run(2), piped]));
+
+        storage
+            .save(&StatsChanges {
+                removed: vec!["make".to_string()],
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(storage.load_history().unwrap().is_empty());
+    }
+
+    #[test]
+    fn save_deletes_removed_commands() {
+        let dir = tempdir().unwrap();
+        let mut storage = SqliteStorage::open(&dir.path().join("stats.db")).unwrap();
+
+        let mut first = HashMap::new();
+        first.insert("ls".to_string(), stats(1));
+        first.insert("pwd".to_string(), stats(1));
+        storage.save(&first.into()).unwrap();
+        storage
+            .save(&StatsChanges {
+                updated: HashMap::new(),
+                removed: vec!["ls".to_string()],
+                ..Default::default()
+            })
+            .unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded.contains_key("pwd"));
+    }
+
+    #[test]
+    fn version_one_database_is_migrated() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stats.db");
+        {
+            let connection = Connection::open(&path).unwrap();
+            connection.execute_batch(MIGRATIONS[0]).unwrap();
+            connection
+                .execute(
+                    "INSERT INTO command_stats VALUES ('ls', 1000, 500, 2, 0, 0)",
+                    [],
+                )
+                .unwrap();
+            connection.pragma_update(None, "user_version", 1).unwrap();
+        }
+
+        let loaded = SqliteStorage::open(&path).unwrap().load().unwrap();
+        let ls = loaded.get("ls").unwrap();
+        assert_eq!(ls.success_count, 2);
+        assert_eq!(ls.last_run_at, None);
+        assert_eq!(ls.signal_count, 0);
+        assert!(ls.exit_codes.is_empty());
+        assert_eq!(ls.interrupted_count, 0);
+        assert!(ls.duration_histogram.is_empty());
+    }
+
+    #[test]
+    fn open_records_schema_version() {
+        let dir = tempdir().unwrap();
+        let storage = SqliteStorage::open(&dir.path().join("stats.db")).unwrap();
+
+        let version: usize = storage
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn database_from_newer_version_is_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stats.db");
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", 999)
+            .unwrap();
+
+        assert!(SqliteStorage::open(&path).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use super::state::SharedDaemonState;
+use super::storage::StorageBackend;
+
+use crate::dto::DaemonEvent;
+
+use anyhow::{Result, anyhow};
+use std::sync::{Arc, PoisonError};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{debug, error};
+
+struct SaveRequest {
+    done: Option<oneshot::Sender<()>>,
+}
+
+/// Handle to the background task that persists the stats.
+///
+/// The task copies the changed stats under the state lock and hands them to the storage
+/// backend on the blocking thread pool, so serialization and disk I/O never stall hook events.
+#[derive(Clone, Debug)]
+pub struct StateWriter {
+    requests: mpsc::Sender<SaveRequest>,
+}
+
+impl StateWriter {
+    /// Starts the task, which announces every save that wrote something on `events`.
+    pub fn spawn(
+        state: SharedDaemonState,
+        backend: Box<dyn StorageBackend>,
+        events: broadcast::Sender<DaemonEvent>,
+    ) -> Self {
+        let (requests, receiver) = mpsc::channel(1);
+        let backend = Arc::new(std::sync::Mutex::new(backend));
+        tokio::spawn(run_writer(state, backend, receiver, events));
+        Self { requests }
+    }
+
+    /// A writer without a backing task, for tests that must not touch the disk.
+    #[cfg(test)]
+    pub fn disconnected() -> Self {
+        let (requests, _receiver) = mpsc::channel(1);
+        Self { requests }
+    }
+
+    /// Asks for a save without waiting for it. Coalesces with a save that is already pending.
+    pub fn request_save(&self) {
+        let _ = self.requests.try_send(SaveRequest { done: None });
+    }
+
+    /// Saves the current stats and waits until they are written.
+    pub async fn save_now(&self) {
+        let (done, saved) = oneshot::channel();
+        if self
+            .requests
+            .send(SaveRequest { done: Some(done) })
+            .await
+            .is_ok()
+        {
+            let _ = saved.await;
+        }
+    }
+}
+
+/// The storage backend, shared with the blocking thread that runs a save. It outlives a save
+/// that panicked, so the next one can be tried.
+type SharedBackend = Arc<std::sync::Mutex<Box<dyn StorageBackend>>>;
+
+async fn run_writer(
+    state: SharedDaemonState,
+    backend: SharedBackend,
+    mut receiver: mpsc::Receiver<SaveRequest>,
+    events: broadcast::Sender<DaemonEvent>,
+) {
+    while let Some(request) = receiver.recv().await {
+        match save_changes(&state, &backend).await {
+            Ok(0) => {}
+            Ok(saved) => {
+                debug!("Saved stats of {} changed command(s).", saved);
+                let _ = events.send(DaemonEvent::StatsSaved { commands: saved });
+            }
+            Err(e) => error!("Failed to save state: {}", e),
+        }
+        if let Some(done) = request.done {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Persists the stats that changed since the last save. Returns how many commands were written.
+/// Changes that could not be written, because the backend failed or panicked, are marked as
+/// changed again, so the next save retries them.
+async fn save_changes(state: &SharedDaemonState, backend: &SharedBackend) -> Result<usize> {
+    let changed = {
+        let mut state_guard = state.lock().await;
+        if state_guard.dirty_commands.is_empty() {
+            return Ok(0);
+        }
+        state_guard.take_changes()
+    };
+    let commands: Vec<String> = changed
+        .updated
+        .keys()
+        .chain(&changed.removed)
+        .cloned()
+        .collect();
+    let journal_seq = changed.journal_seq;
+
+    let backend = Arc::clone(backend);
+    let result = tokio::task::spawn_blocking(move || {
+        let mut backend = backend.lock().unwrap_or_else(PoisonError::into_inner);
+        backend.save(&changed)
+    })
+    .await
+    .unwrap_or_else(|e| Err(anyhow!("The storage backend panicked: {}", e)));
+
+    let mut state_guard = state.lock().await;
+    match result {
+        Ok(()) => state_guard
+            .discard_journal_through(journal_seq)
+            .map(|()| commands.len()),
+        Err(e) => {
+            state_guard.dirty_commands.extend(commands);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::journal::Journal;
+    use crate::daemon::state::DaemonState;
+    use crate::daemon::storage::{JsonStorage, StatsChanges};
+    use crate::dto::{CommandStats, RunRecord};
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn save_now_writes_snapshot_and_clears_journal() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+        let journal_path = dir.path().join("journal.log");
+
+        let state = Arc::new(Mutex::new(DaemonState {
+            journal: Some(Journal::open(&journal_path, 0).unwrap()),
+            ..Default::default()
+        }));
+        {
+            let mut state_guard = state.lock().await;
+            state_guard.handle_start(1, "make".to_string(), None);
+            state_guard.handle_end(1, 0, Vec::new());
+        }
+
+        let (events, mut subscriber) = broadcast::channel(16);
+        let writer = StateWriter::spawn(
+            Arc::clone(&state),
+            Box::new(JsonStorage::new(stats_path.clone())),
+            events,
+        );
+        writer.save_now().await;
+        assert_eq!(
+            subscriber.recv().await.unwrap(),
+            DaemonEvent::StatsSaved { commands: 1 }
+        );
+
+        let saved = JsonStorage::new(stats_path).load().unwrap();
+        assert_eq!(saved.get("make").unwrap().success_count, 1);
+        let state_guard = state.lock().await;
+        state_guard.journal.as_ref().unwrap().flush().unwrap();
+        assert!(
+            Journal::replay_after(&journal_path, 0)
+                .unwrap()
+                .entries
+                .is_empty()
+        );
+        assert!(state_guard.dirty_commands.is_empty());
+    }
+
+    /// Panics on its first save, then keeps the number of commands it was handed.
+    #[derive(Default)]
+    struct FlakyStorage {
+        attempts: usize,
+        saved: Arc<std::sync::Mutex<usize>>,
+    }
+
+    impl StorageBackend for FlakyStorage {
+        fn load(&mut self) -> Result<HashMap<String, CommandStats>> {
+            Ok(HashMap::new())
+        }
+
+        fn load_history(&mut self) -> Result<HashMap<String, VecDeque<RunRecord>>> {
+            Ok(HashMap::new())
+        }
+
+        fn load_journal_seq(&mut self) -> Result<u64> {
+            Ok(0)
+        }
+
+        fn save(&mut self, changes: &StatsChanges) -> Result<()> {
+            self.attempts += 1;
+            if self.attempts == 1 {
+                panic!("storage bug");
+            }
+            *self.saved.lock().unwrap() += changes.updated.len();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_panicking_save_is_retried_by_the_next_one() {
+        let state = Arc::new(Mutex::new(DaemonState::default()));
+        {
+            let mut state_guard = state.lock().await;
+            state_guard.handle_start(1, "make".to_string(), None);
+            state_guard.handle_end(1, 0, Vec::new());
+        }
+        let storage = FlakyStorage::default();
+        let saved = Arc::clone(&storage.saved);
+        let (events, mut subscriber) = broadcast::channel(16);
+        let writer = StateWriter::spawn(Arc::clone(&state), Box::new(storage), events);
+
+        writer.save_now().await;
+        assert!(state.lock().await.dirty_commands.contains("make"));
+
+        writer.save_now().await;
+        assert_eq!(
+            subscriber.recv().await.unwrap(),
+            DaemonEvent::StatsSaved { commands: 1 }
+        );
+        assert_eq!(*saved.lock().unwrap(), 1);
+        assert!(state.lock().await.dirty_commands.is_empty());
+    }
+
+    #[tokio::test]
+    async fn clean_state_is_not_written() {
+        let dir = tempdir().unwrap();
+        let stats_path = dir.path().join("stats.json");
+
+        let state = Arc::new(Mutex::new(DaemonState::default()));
+        let writer = StateWriter::spawn(
+            state,
+            Box::new(JsonStorage::new(stats_path.clone())),
+            broadcast::channel(16).0,
+        );
+        writer.save_now().await;
+
+        assert!(!stats_path.exists());
+    }
+}
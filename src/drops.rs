@@ -0,0 +1,160 @@
+//! A tally of the shell hook messages that never reached the daemon.
+//!
+//! Hooks must not hold up the shell, so they give up on a daemon that does not answer in time
+//! and count it here instead. The file holds a single line, `<timed out> <unreachable> <failed>
+//! <last drop in unix ms>`, so it stays the same size however many messages are dropped. Each
+//! hook updates it under an exclusive lock so that concurrent shells do not lose counts.
+
+use crate::client::ClientError;
+use crate::socket;
+
+use anyhow::Result;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Hook messages dropped so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DropSummary {
+    /// Messages the daemon did not take within the deadline.
+    pub timed_out: usize,
+    /// Messages sent while nothing listened on the socket, e.g. while the daemon was stopped.
+    pub unreachable: usize,
+    /// Messages lost to other errors.
+    pub failed: usize,
+    pub last_dropped_at: Option<SystemTime>,
+}
+
+impl DropSummary {
+    pub fn total(&self) -> usize {
+        self.timed_out + self.unreachable + self.failed
+    }
+
+    /// Parses the tally line. Anything else, e.g. the line-per-drop log of older builds, counts
+    /// as no drops at all.
+    fn parse(tally: &str) -> Self {
+        let fields: Option<Vec<u64>> = tally
+            .split_whitespace()
+            .map(|field| field.parse().ok())
+            .collect();
+        match fields.as_deref() {
+            Some(&[timed_out, unreachable, failed, last_dropped_at]) => Self {
+                timed_out: timed_out as usize,
+                unreachable: unreachable as usize,
+                failed: failed as usize,
+                last_dropped_at: Some(UNIX_EPOCH + Duration::from_millis(last_dropped_at)),
+            },
+            _ => Self::default(),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        let last_dropped_at = self
+            .last_dropped_at
+            .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default()
+            .as_millis();
+        format!(
+            "{} {} {} {}\n",
+            self.timed_out, self.unreachable, self.failed, last_dropped_at
+        )
+    }
+}
+
+/// Counts a message dropped because of `error`. Never fails: a hook has nowhere to report to.
+pub fn record(error: &ClientError) {
+    let _ = record_in(&socket::get_drop_log_path(), error);
+}
+
+pub fn summary() -> Result<DropSummary> {
+    summarize(&socket::get_drop_log_path())
+}
+
+fn record_in(path: &Path, error: &ClientError) -> io::Result<()> {
+    let mut tally = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)?;
+    tally.lock()?;
+    let mut summary = DropSummary::parse(&read_tally(&mut tally)?);
+    match error {
+        ClientError::Timeout(_) => summary.timed_out += 1,
+        ClientError::Connect { .. } => summary.unreachable += 1,
+        _ => summary.failed += 1,
+    }
+    summary.last_dropped_at = Some(SystemTime::now());
+
+    tally.set_len(0)?;
+    tally.seek(SeekFrom::Start(0))?;
+    tally.write_all(summary.to_line().as_bytes())
+}
+
+fn read_tally(tally: &mut File) -> io::Result<String> {
+    let mut contents = Vec::new();
+    tally.read_to_end(&mut contents)?;
+    Ok(String::from_utf8_lossy(&contents).into_owned())
+}
+
+fn summarize(path: &Path) -> Result<DropSummary> {
+    let mut tally = match File::open(path) {
+        Ok(tally) => tally,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(DropSummary::default()),
+        Err(e) => return Err(e.into()),
+    };
+    tally.lock_shared()?;
+    Ok(DropSummary::parse(&read_tally(&mut tally)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn drops_are_counted_by_reason() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("drops");
+        assert_eq!(summarize(&path).unwrap(), DropSummary::default());
+
+        let unreachable = ClientError::Connect {
+            path: "/tmp/t_trace.sock".into(),
+            source: io::Error::from(ErrorKind::NotFound),
+        };
+        record_in(&path, &ClientError::Timeout(Duration::from_millis(50))).unwrap();
+        record_in(&path, &ClientError::Timeout(Duration::from_millis(50))).unwrap();
+        record_in(&path, &unreachable).unwrap();
+        record_in(&path, &ClientError::UnexpectedResponse("?".to_string())).unwrap();
+
+        let summary = summarize(&path).unwrap();
+        assert_eq!(
+            (summary.timed_out, summary.unreachable, summary.failed),
+            (2, 1, 1)
+        );
+        assert_eq!(summary.total(), 4);
+        assert!(summary.last_dropped_at.unwrap().elapsed().unwrap() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn tally_keeps_its_size_under_concurrent_drops() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("drops");
+        std::fs::write(&path, "1700000000000 command-begin timeout\n").unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        record_in(&path, &ClientError::Timeout(Duration::from_millis(50))).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(summarize(&path).unwrap().timed_out, 100);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+}
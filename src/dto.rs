@@ -1,10 +1,576 @@
+use nix::sys::signal::Signal;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Whether an exit code means the command was killed by a signal (128 + signal number).
+pub fn is_signal_exit(exit_code: i32) -> bool {
+    (129..=128 + 64).contains(&exit_code)
+}
+
+/// Whether an exit code means the command was interrupted: SIGINT (130) or SIGTERM (143).
+pub fn is_interrupted_exit(exit_code: i32) -> bool {
+    matches!(exit_code, 130 | 143)
+}
+
+/// An exit code, with the name of the signal for signal exits, e.g. `130 (SIGINT)`.
+pub fn exit_code_label(exit_code: i32) -> String {
+    if is_signal_exit(exit_code)
+        && let Ok(signal) = Signal::try_from(exit_code - 128)
+    {
+        return format!("{} ({})", exit_code, signal.as_str());
+    }
+    exit_code.to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct CommandStats {
     pub total_duration: Duration,
     pub last_run_duration: Duration,
     pub success_count: u64,
+    /// Runs that exited with a non-zero code other than a signal exit.
     pub fail_count: u64,
+    /// Runs killed by a signal other than an interruption, e.g. exit code 137 after SIGKILL.
+    /// Not counted as failures.
+    #[serde(default)]
+    pub signal_count: u64,
+    /// Runs interrupted with SIGINT or SIGTERM, e.g. by Ctrl-C. Neither failures nor part of
+    /// `total_duration`, so that partial runs do not skew the timing.
+    #[serde(default)]
+    pub interrupted_count: u64,
+    /// Time spent in interrupted runs.
+    #[serde(default)]
+    pub interrupted_duration: Duration,
+    /// Number of runs per non-zero exit code, signal exits included.
+    #[serde(default)]
+    pub exit_codes: BTreeMap<i32, u64>,
+    /// When the last failure finished.
+    #[serde(default)]
+    pub last_failure_at: Option<SystemTime>,
+    /// Runs whose end was never reported, e.g. because the shell was killed.
+    #[serde(default)]
+    pub abandoned_count: u64,
+    /// When the last run finished, used to decide which `last_run_duration` is newer when merging.
+    #[serde(default)]
+    pub last_run_at: Option<SystemTime>,
+    /// Durations of the runs counted in `total_duration`.
+    #[serde(default)]
+    pub duration_histogram: DurationHistogram,
+    /// Durations of the interrupted runs, kept apart like `interrupted_duration`.
+    #[serde(default)]
+    pub interrupted_histogram: DurationHistogram,
+}
+
+impl CommandStats {
+    /// Total time spent in the command, optionally including interrupted runs.
+    pub fn total_time(&self, include_interrupted: bool) -> Duration {
+        if include_interrupted {
+            self.total_duration + self.interrupted_duration
+        } else {
+            self.total_duration
+        }
+    }
+
+    /// Mean duration of the runs that finished, successfully or not, optionally including
+    /// interrupted runs.
+    pub fn mean_duration(&self, include_interrupted: bool) -> Duration {
+        let mut timed_runs = self.success_count + self.fail_count + self.signal_count;
+        if include_interrupted {
+            timed_runs += self.interrupted_count;
+        }
+        if timed_runs == 0 {
+            return Duration::ZERO;
+        }
+        let mean_nanos = self.total_time(include_interrupted).as_nanos() / u128::from(timed_runs);
+        Duration::from_nanos(u64::try_from(mean_nanos).unwrap_or(u64::MAX))
+    }
+
+    /// Durations of the runs that finished, optionally including interrupted runs.
+    pub fn histogram(&self, include_interrupted: bool) -> DurationHistogram {
+        let mut histogram = self.duration_histogram.clone();
+        if include_interrupted {
+            histogram.merge(&self.interrupted_histogram);
+        }
+        histogram
+    }
+
+    /// Number of recorded runs, whatever their outcome.
+    pub fn run_count(&self) -> u64 {
+        self.success_count
+            + self.fail_count
+            + self.signal_count
+            + self.interrupted_count
+            + self.abandoned_count
+    }
+
+    /// Share of failures among the runs that were neither interrupted by a signal nor abandoned.
+    pub fn fail_rate(&self) -> Option<f64> {
+        let completed_runs = self.success_count + self.fail_count;
+        (completed_runs > 0).then(|| self.fail_count as f64 / completed_runs as f64)
+    }
+
+    /// The most frequent non-zero exit codes with their counts, most frequent first.
+    pub fn top_exit_codes(&self, limit: usize) -> Vec<(i32, u64)> {
+        let mut exit_codes: Vec<(i32, u64)> = self
+            .exit_codes
+            .iter()
+            .map(|(code, count)| (*code, *count))
+            .collect();
+        exit_codes.sort_by(|first, second| second.1.cmp(&first.1).then(first.0.cmp(&second.0)));
+        exit_codes.truncate(limit);
+        exit_codes
+    }
+
+    /// Combines the stats of the same command recorded elsewhere, e.g. on another machine.
+    /// Counts and total durations add up, and the more recent last run wins.
+    pub fn merge(&mut self, other: &CommandStats) {
+        self.total_duration += other.total_duration;
+        self.success_count += other.success_count;
+        self.fail_count += other.fail_count;
+        self.signal_count += other.signal_count;
+        self.interrupted_count += other.interrupted_count;
+        self.interrupted_duration += other.interrupted_duration;
+        self.abandoned_count += other.abandoned_count;
+        for (code, count) in &other.exit_codes {
+            *self.exit_codes.entry(*code).or_default() += count;
+        }
+        self.last_failure_at = self.last_failure_at.max(other.last_failure_at);
+        self.duration_histogram.merge(&other.duration_histogram);
+        self.interrupted_histogram
+            .merge(&other.interrupted_histogram);
+
+        if other.last_run_at > self.last_run_at {
+            self.last_run_duration = other.last_run_duration;
+            self.last_run_at = other.last_run_at;
+        }
+    }
+}
+
+/// Run durations counted in logarithmic buckets.
+///
+/// Bucket `i` holds the runs shorter than `2^i` milliseconds that do not fit in bucket `i - 1`.
+/// The last bucket also holds all longer runs. Only non-empty buckets are stored.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct DurationHistogram(BTreeMap<u8, u64>);
+
+impl DurationHistogram {
+    /// Number of buckets. The last bounded one ends at `2^31` ms, almost 25 days.
+    pub const BUCKETS: u8 = 32;
+
+    pub fn bucket_of(duration: Duration) -> u8 {
+        let millis = duration.as_millis();
+        let bits = u128::BITS - millis.leading_zeros();
+        bits.min(u32::from(Self::BUCKETS - 1)) as u8
+    }
+
+    /// Exclusive upper bound of the durations in `bucket`.
+    pub fn upper_bound(bucket: u8) -> Duration {
+        Duration::from_millis(1 << bucket.min(Self::BUCKETS - 1))
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        *self.0.entry(Self::bucket_of(duration)).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &DurationHistogram) {
+        for (bucket, count) in &other.0 {
+            *self.0.entry(*bucket).or_default() += count;
+        }
+    }
+
+    /// Non-empty buckets with their counts, shortest durations first.
+    pub fn buckets(&self) -> impl Iterator<Item = (u8, u64)> + '_ {
+        self.0.iter().map(|(bucket, count)| (*bucket, *count))
+    }
+
+    /// Buckets from the shortest to the longest recorded duration, with the empty ones in between.
+    pub fn span(&self) -> Vec<(u8, u64)> {
+        let (Some(first), Some(last)) = (self.0.keys().next(), self.0.keys().next_back()) else {
+            return Vec::new();
+        };
+        (*first..=*last)
+            .map(|bucket| (bucket, self.0.get(&bucket).copied().unwrap_or(0)))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0.values().sum()
+    }
+
+    /// Estimates a quantile, e.g. `0.5` for the median, as the upper bound of its bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let rank = (quantile.clamp(0.0, 1.0) * self.count() as f64)
+            .ceil()
+            .max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets() {
+            seen += count;
+            if seen >= rank {
+                return Some(Self::upper_bound(bucket));
+            }
+        }
+        None
+    }
+}
+
+impl FromIterator<(u8, u64)> for DurationHistogram {
+    fn from_iter<I: IntoIterator<Item = (u8, u64)>>(buckets: I) -> Self {
+        Self(
+            buckets
+                .into_iter()
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+        )
+    }
+}
+
+/// A single finished run, kept in the recent history of its command.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunRecord {
+    pub finished_at: SystemTime,
+    pub duration: Duration,
+    pub exit_code: i32,
+    /// Exit codes of the stages of the last pipeline of the command line, from Bash's
+    /// `PIPESTATUS`. Empty unless it had more than one stage.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipe_status: Vec<i32>,
+}
+
+/// Everything the daemon knows about one command, as shown by `t_trace show`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandHistory {
+    /// The command as tracked, i.e. after redaction.
+    pub command: String,
+    pub stats: Option<CommandStats>,
+    /// The most recent runs, oldest first.
+    pub runs: Vec<RunRecord>,
+}
+
+/// A command whose last run took much longer than its recent runs usually do.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub command: String,
+    pub last_run: RunRecord,
+    /// Median duration of the runs before the last one.
+    pub baseline: Duration,
+    /// Number of runs the baseline was taken from.
+    pub baseline_runs: usize,
+}
+
+impl Regression {
+    /// How many times longer the last run took than the baseline.
+    pub fn slowdown(&self) -> f64 {
+        self.last_run.duration.as_secs_f64() / self.baseline.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// A command that started but has not finished yet, as shown by `t_trace top`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunningCommand {
+    pub pid: u32,
+    /// PID of the background job running the command, if the shell sent it to the background.
+    #[serde(default)]
+    pub job: Option<u32>,
+    pub command: String,
+    pub elapsed: Duration,
+}
+
+/// A shell from the moment it started until it exited, as shown by `t_trace sessions`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    /// PID of the shell.
+    pub pid: u32,
+    pub started_at: SystemTime,
+    /// `None` while the shell is still running.
+    pub ended_at: Option<SystemTime>,
+    /// Number of tracked commands that finished in the shell.
+    pub command_count: u64,
+    /// Time spent running tracked commands.
+    pub busy: Duration,
+}
+
+impl Session {
+    /// How long the shell ran, or has been running until `now`.
+    pub fn duration(&self, now: SystemTime) -> Duration {
+        self.ended_at
+            .unwrap_or(now)
+            .duration_since(self.started_at)
+            .unwrap_or_default()
+    }
+
+    /// Time the shell spent waiting at the prompt rather than running tracked commands.
+    pub fn idle(&self, now: SystemTime) -> Duration {
+        self.duration(now).saturating_sub(self.busy)
+    }
+}
+
+/// How imported stats are combined with the ones the daemon already has.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Add the imported stats to the existing ones.
+    Merge,
+    /// Discard the existing stats and keep only the imported ones.
+    Replace,
+}
+
+/// How a long-running command that finished is announced in the terminal it ran in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalAlert {
+    /// Nothing is printed.
+    #[default]
+    None,
+    /// The terminal bell.
+    Bell,
+    /// An OSC 9 notification, as supported by iTerm2, Windows Terminal, kitty and others.
+    Osc9,
+    /// An OSC 777 notification, as supported by rxvt-unicode, foot, Ghostty and VTE terminals.
+    Osc777,
+}
+
+/// The daemon's reply to the end of a long-running command, printed by the shell hook.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TerminalNotification {
+    pub alert: TerminalAlert,
+    pub title: String,
+    pub body: String,
+}
+
+impl TerminalNotification {
+    /// The bytes that make the terminal show the notification. Control characters are dropped
+    /// from the text, so that a command cannot end the sequence early and inject its own.
+    pub fn escape_sequence(&self) -> String {
+        let printable = |text: &str| text.chars().filter(|c| !c.is_control()).collect::<String>();
+        match self.alert {
+            TerminalAlert::None => String::new(),
+            TerminalAlert::Bell => "\x07".to_string(),
+            TerminalAlert::Osc9 => {
+                format!(
+                    "\x1b]9;{}: {}\x07",
+                    printable(&self.title),
+                    printable(&self.body)
+                )
+            }
+            TerminalAlert::Osc777 => format!(
+                "\x1b]777;notify;{};{}\x07",
+                printable(&self.title).replace(';', ","),
+                printable(&self.body)
+            ),
+        }
+    }
+}
+
+/// Something that happened in the daemon, streamed to `SUBSCRIBE` connections as NDJSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DaemonEvent {
+    /// A tracked command started.
+    CommandStarted {
+        pid: u32,
+        command: String,
+        cwd: Option<PathBuf>,
+    },
+    /// A tracked command finished and was recorded.
+    CommandFinished {
+        pid: u32,
+        command: String,
+        exit_code: i32,
+        duration_ms: u64,
+        cwd: Option<PathBuf>,
+        finished_at_unix_ms: u64,
+        /// Exit codes of the pipeline stages, if the command line ended with a pipeline.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pipe_status: Vec<i32>,
+    },
+    /// Changed stats were written to storage.
+    StatsSaved { commands: usize },
+    /// The subscriber fell behind and this many events were dropped for it.
+    Lagged { missed: u64 },
+    /// The daemon is stopping; no more events follow.
+    ShuttingDown,
+}
+
+/// A snapshot of the daemon's health, as reported by `t_trace daemon status`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub uptime: Duration,
+    pub storage: String,
+    pub tracked_commands: usize,
+    pub in_flight_commands: usize,
+    /// Problems the daemon recovered from, e.g. an unreadable stats file it moved aside.
+    pub problems: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_of_signal_exits_are_named() {
+        assert_eq!(exit_code_label(1), "1");
+        assert_eq!(exit_code_label(130), "130 (SIGINT)");
+        assert_eq!(exit_code_label(137), "137 (SIGKILL)");
+        assert_eq!(exit_code_label(255), "255");
+    }
+
+    fn stats_finished_at(secs: u64, last_run_duration: Duration) -> CommandStats {
+        CommandStats {
+            total_duration: Duration::from_secs(10),
+            last_run_duration,
+            success_count: 2,
+            fail_count: 1,
+            signal_count: 1,
+            interrupted_count: 1,
+            interrupted_duration: Duration::from_secs(3),
+            exit_codes: BTreeMap::from([(1, 1), (130, 1), (137, 1)]),
+            last_failure_at: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            abandoned_count: 1,
+            last_run_at: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            duration_histogram: [(10, 2), (12, 1)].into_iter().collect(),
+            interrupted_histogram: [(11, 1)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn merge_adds_counts_and_durations() {
+        let mut local = stats_finished_at(100, Duration::from_secs(1));
+        local.merge(&stats_finished_at(50, Duration::from_secs(2)));
+
+        assert_eq!(local.total_duration, Duration::from_secs(20));
+        assert_eq!(local.success_count, 4);
+        assert_eq!(local.fail_count, 2);
+        assert_eq!(local.signal_count, 2);
+        assert_eq!(local.interrupted_count, 2);
+        assert_eq!(local.interrupted_duration, Duration::from_secs(6));
+        assert_eq!(local.abandoned_count, 2);
+        assert_eq!(
+            local.duration_histogram,
+            [(10, 4), (12, 2)].into_iter().collect()
+        );
+        assert_eq!(
+            local.exit_codes,
+            BTreeMap::from([(1, 2), (130, 2), (137, 2)])
+        );
+        assert_eq!(
+            local.last_failure_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(100))
+        );
+    }
+
+    #[test]
+    fn durations_fall_into_power_of_two_buckets() {
+        let bucket = |millis| DurationHistogram::bucket_of(Duration::from_millis(millis));
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 1);
+        assert_eq!(bucket(3), 2);
+        assert_eq!(bucket(1023), 10);
+        assert_eq!(bucket(1024), 11);
+        assert_eq!(bucket(u64::MAX), DurationHistogram::BUCKETS - 1);
+        assert_eq!(
+            DurationHistogram::upper_bound(10),
+            Duration::from_millis(1024)
+        );
+    }
+
+    #[test]
+    fn histogram_quantiles_are_bucket_bounds() {
+        let mut histogram = DurationHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+
+        for millis in [100, 110, 120, 900, 5_000] {
+            histogram.record(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(128)));
+        assert_eq!(histogram.quantile(0.8), Some(Duration::from_millis(1024)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::from_millis(8192)));
+        assert_eq!(
+            histogram.span(),
+            vec![(7, 3), (8, 0), (9, 0), (10, 1), (11, 0), (12, 0), (13, 1)]
+        );
+    }
+
+    #[test]
+    fn signal_exits_are_told_apart_from_failures() {
+        assert!(!is_signal_exit(1));
+        assert!(!is_signal_exit(128));
+        assert!(is_signal_exit(130));
+        assert!(is_signal_exit(143));
+        assert!(!is_signal_exit(255));
+    }
+
+    #[test]
+    fn interrupted_runs_are_excluded_from_timing_unless_requested() {
+        let stats = CommandStats {
+            total_duration: Duration::from_secs(30),
+            success_count: 3,
+            interrupted_count: 1,
+            interrupted_duration: Duration::from_secs(2),
+            ..Default::default()
+        };
+
+        assert_eq!(stats.mean_duration(false), Duration::from_secs(10));
+        assert_eq!(stats.total_time(true), Duration::from_secs(32));
+        assert_eq!(stats.mean_duration(true), Duration::from_secs(8));
+        assert_eq!(stats.run_count(), 4);
+        assert!(is_interrupted_exit(130) && is_interrupted_exit(143));
+        assert!(!is_interrupted_exit(137));
+    }
+
+    #[test]
+    fn fail_rate_ignores_signal_exits_and_top_exit_codes_are_ordered() {
+        let stats = CommandStats {
+            success_count: 3,
+            fail_count: 1,
+            signal_count: 4,
+            exit_codes: BTreeMap::from([(1, 1), (2, 1), (130, 4)]),
+            ..Default::default()
+        };
+
+        assert_eq!(stats.fail_rate(), Some(0.25));
+        assert_eq!(stats.top_exit_codes(2), vec![(130, 4), (1, 1)]);
+        assert_eq!(CommandStats::default().fail_rate(), None);
+    }
+
+    #[test]
+    fn merge_keeps_most_recent_last_run() {
+        let mut older = stats_finished_at(100, Duration::from_secs(1));
+        older.merge(&stats_finished_at(200, Duration::from_secs(2)));
+        assert_eq!(older.last_run_duration, Duration::from_secs(2));
+        assert_eq!(
+            older.last_run_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(200))
+        );
+
+        let mut newer = stats_finished_at(300, Duration::from_secs(3));
+        newer.merge(&stats_finished_at(200, Duration::from_secs(2)));
+        assert_eq!(newer.last_run_duration, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn terminal_notifications_cannot_smuggle_escape_sequences() {
+        let notification = |alert| TerminalNotification {
+            alert,
+            title: "Command failed; exit 1".to_string(),
+            body: "echo \x07\x1b]0;owned\x07 (1.0s)".to_string(),
+        };
+
+        assert_eq!(notification(TerminalAlert::None).escape_sequence(), "");
+        assert_eq!(notification(TerminalAlert::Bell).escape_sequence(), "\x07");
+        assert_eq!(
+            notification(TerminalAlert::Osc9).escape_sequence(),
+            "\x1b]9;Command failed; exit 1: echo ]0;owned (1.0s)\x07"
+        );
+        assert_eq!(
+            notification(TerminalAlert::Osc777).escape_sequence(),
+            "\x1b]777;notify;Command failed, exit 1;echo ]0;owned (1.0s)\x07"
+        );
+    }
 }
@@ -0,0 +1,296 @@
+use crate::dto::CommandStats;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File formats understood by `t_trace export` and `t_trace import`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A pretty-printed JSON array of records.
+    #[default]
+    Json,
+    /// Comma-separated values with a header row.
+    Csv,
+    /// One JSON record per line.
+    Ndjson,
+}
+
+impl ExportFormat {
+    /// Guesses the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        Self::from_str(extension, true).ok()
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Json => write!(f, "json"),
+            ExportFormat::Csv => write!(f, "csv"),
+            ExportFormat::Ndjson => write!(f, "ndjson"),
+        }
+    }
+}
+
+/// The stats of a single command in the documented export format.
+///
+/// Durations are stored in nanoseconds and timestamps in milliseconds since the Unix epoch,
+/// so that every format round-trips without loss.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportRecord {
+    pub command: String,
+    pub success_count: u64,
+    pub fail_count: u64,
+    pub abandoned_count: u64,
+    pub total_duration_ns: u64,
+    pub last_run_duration_ns: u64,
+    pub last_run_at_unix_ms: Option<u64>,
+    #[serde(default)]
+    pub signal_count: u64,
+    /// Non-zero exit codes with their counts, e.g. `1:3 130:1`.
+    #[serde(default)]
+    pub exit_codes: String,
+    #[serde(default)]
+    pub last_failure_at_unix_ms: Option<u64>,
+    #[serde(default)]
+    pub interrupted_count: u64,
+    #[serde(default)]
+    pub interrupted_duration_ns: u64,
+    /// Runs per duration bucket, e.g. `10:4 11:1`, where bucket `i` counts runs shorter than
+    /// `2^i` milliseconds.
+    #[serde(default)]
+    pub duration_histogram: String,
+    /// Interrupted runs per duration bucket, like `duration_histogram`.
+    #[serde(default)]
+    pub interrupted_histogram: String,
+}
+
+impl ExportRecord {
+    fn new(command: &str, stats: &CommandStats) -> Self {
+        Self {
+            command: command.to_string(),
+            success_count: stats.success_count,
+            fail_count: stats.fail_count,
+            abandoned_count: stats.abandoned_count,
+            total_duration_ns: duration_to_nanos(stats.total_duration),
+            last_run_duration_ns: duration_to_nanos(stats.last_run_duration),
+            last_run_at_unix_ms: stats.last_run_at.map(system_time_to_millis),
+            signal_count: stats.signal_count,
+            exit_codes: format_counts(stats.exit_codes.iter().map(|(code, count)| (*code, *count))),
+            last_failure_at_unix_ms: stats.last_failure_at.map(system_time_to_millis),
+            interrupted_count: stats.interrupted_count,
+            interrupted_duration_ns: duration_to_nanos(stats.interrupted_duration),
+            duration_histogram: format_counts(stats.duration_histogram.buckets()),
+            interrupted_histogram: format_counts(stats.interrupted_histogram.buckets()),
+        }
+    }
+
+    fn into_stats(self) -> Result<(String, CommandStats)> {
+        let exit_codes = parse_counts(&self.exit_codes)?.into_iter().collect();
+        let duration_histogram = parse_counts(&self.duration_histogram)?
+            .into_iter()
+            .collect();
+        let interrupted_histogram = parse_counts(&self.interrupted_histogram)?
+            .into_iter()
+            .collect();
+        let stats = CommandStats {
+            total_duration: Duration::from_nanos(self.total_duration_ns),
+            last_run_duration: Duration::from_nanos(self.last_run_duration_ns),
+            success_count: self.success_count,
+            fail_count: self.fail_count,
+            abandoned_count: self.abandoned_count,
+            last_run_at: self.last_run_at_unix_ms.map(millis_to_system_time),
+            signal_count: self.signal_count,
+            exit_codes,
+            last_failure_at: self.last_failure_at_unix_ms.map(millis_to_system_time),
+            interrupted_count: self.interrupted_count,
+            interrupted_duration: Duration::from_nanos(self.interrupted_duration_ns),
+            duration_histogram,
+            interrupted_histogram,
+        };
+        Ok((self.command, stats))
+    }
+}
+
+/// Formats counts as space-separated `key:count` pairs.
+fn format_counts<K: fmt::Display>(counts: impl Iterator<Item = (K, u64)>) -> String {
+    counts
+        .map(|(key, count)| format!("{}:{}", key, count))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_counts<K: FromStr>(input: &str) -> Result<Vec<(K, u64)>>
+where
+    K::Err: std::error::Error + Send + Sync + 'static,
+{
+    input
+        .split_whitespace()
+        .map(|entry| {
+            let (key, count) = entry
+                .split_once(':')
+                .with_context(|| format!("Invalid count entry: '{}'", entry))?;
+            Ok((key.parse()?, count.parse()?))
+        })
+        .collect()
+}
+
+fn system_time_to_millis(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+}
+
+fn millis_to_system_time(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+fn duration_to_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Renders the stats in the given format, ordered by command.
+pub fn render(stats: &HashMap<String, CommandStats>, format: ExportFormat) -> Result<String> {
+    let mut records: Vec<ExportRecord> = stats
+        .iter()
+        .map(|(command, stats)| ExportRecord::new(command, stats))
+        .collect();
+    records.sort_by(|first, second| first.command.cmp(&second.command));
+
+    match format {
+        ExportFormat::Json => {
+            let mut output = serde_json::to_string_pretty(&records)?;
+            output.push('\n');
+            Ok(output)
+        }
+        ExportFormat::Ndjson => {
+            let mut output = String::new();
+            for record in &records {
+                output.push_str(&serde_json::to_string(record)?);
+                output.push('\n');
+            }
+            Ok(output)
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for record in &records {
+                writer.serialize(record)?;
+            }
+            let bytes = writer.into_inner().context("Failed to flush CSV output")?;
+            Ok(String::from_utf8(bytes)?)
+        }
+    }
+}
+
+/// Parses stats rendered by `render`. Records for the same command are merged.
+pub fn parse(input: &str, format: ExportFormat) -> Result<HashMap<String, CommandStats>> {
+    let records: Vec<ExportRecord> = match format {
+        ExportFormat::Json => serde_json::from_str(input).context("Invalid JSON export")?,
+        ExportFormat::Ndjson => input
+            .lines()
+            .enumerate()
+            .filter(|(_index, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Invalid NDJSON record on line {}", index + 1))
+            })
+            .collect::<Result<_>>()?,
+        ExportFormat::Csv => csv::Reader::from_reader(input.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .context("Invalid CSV export")?,
+    };
+
+    let mut stats: HashMap<String, CommandStats> = HashMap::new();
+    for record in records {
+        let (command, record_stats) = record.into_stats()?;
+        stats.entry(command).or_default().merge(&record_stats);
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_stats() -> HashMap<String, CommandStats> {
+        let mut stats = HashMap::new();
+        stats.insert(
+            "git commit -m \"a, b\"".to_string(),
+            CommandStats {
+                total_duration: Duration::from_nanos(1_234_567_890),
+                last_run_duration: Duration::from_millis(250),
+                success_count: 3,
+                fail_count: 1,
+                signal_count: 2,
+                interrupted_count: 1,
+                interrupted_duration: Duration::from_millis(40),
+                duration_histogram: [(8, 3), (12, 1)].into_iter().collect(),
+                interrupted_histogram: [(5, 1)].into_iter().collect(),
+                exit_codes: [(1, 1), (130, 1), (137, 2)].into(),
+                last_failure_at: Some(UNIX_EPOCH + Duration::from_millis(1_699_000_000_000)),
+                abandoned_count: 0,
+                last_run_at: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            },
+        );
+        stats.insert(
+            "ls".to_string(),
+            CommandStats {
+                success_count: 7,
+                ..Default::default()
+            },
+        );
+        stats
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        for format in [ExportFormat::Json, ExportFormat::Csv, ExportFormat::Ndjson] {
+            let rendered = render(&sample_stats(), format).unwrap();
+            let parsed = parse(&rendered, format).unwrap();
+            assert_eq!(parsed, sample_stats(), "{} did not round-trip", format);
+        }
+    }
+
+    #[test]
+    fn csv_has_header_and_one_row_per_command() {
+        let rendered = render(&sample_stats(), ExportFormat::Csv).unwrap();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("command,success_count"));
+    }
+
+    #[test]
+    fn duplicate_records_are_merged() {
+        let input = concat!(
+            r#"{"command":"ls","success_count":1,"fail_count":0,"abandoned_count":0,"total_duration_ns":10,"last_run_duration_ns":10,"last_run_at_unix_ms":null}"#,
+            "\n",
+            r#"{"command":"ls","success_count":2,"fail_count":0,"abandoned_count":0,"total_duration_ns":20,"last_run_duration_ns":10,"last_run_at_unix_ms":null}"#,
+        );
+        let parsed = parse(input, ExportFormat::Ndjson).unwrap();
+        assert_eq!(parsed.get("ls").unwrap().success_count, 3);
+        assert_eq!(
+            parsed.get("ls").unwrap().total_duration,
+            Duration::from_nanos(30)
+        );
+    }
+
+    #[test]
+    fn format_is_guessed_from_extension() {
+        assert_eq!(
+            ExportFormat::from_path(Path::new("backup.CSV")),
+            Some(ExportFormat::Csv)
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("stats.ndjson")),
+            Some(ExportFormat::Ndjson)
+        );
+        assert_eq!(ExportFormat::from_path(Path::new("stats.txt")), None);
+    }
+}
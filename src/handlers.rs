@@ -1,42 +1,177 @@
-use crate::client::Client;
-use crate::dto::CommandStats;
+use crate::charts;
+use crate::client::{
+    Client, ClientError, ClientOptions, ClientRequest, CommandBackground, CommandBegin, CommandEnd,
+    Export, Forget, GetHistory, GetRegressions, GetSessions, GetStats, GetStatus, HealthCheck,
+    Import, Prune, ResetStats, SessionBegin, SessionEnd, Stop,
+};
+use crate::config::{Config, SortKey};
+use crate::drops::{self, DropSummary};
+use crate::dto::{
+    CommandHistory, CommandStats, DaemonEvent, DurationHistogram, ImportMode, Regression, Session,
+    exit_code_label, is_interrupted_exit,
+};
+use crate::export::{self, ExportFormat};
+use crate::tui::{self, App};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use comfy_table::{Attribute, Cell, Color, ContentArrangement, Table, presets::UTF8_FULL};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Width of the longest bar in the histogram of `t_trace show`.
+const HISTOGRAM_WIDTH: usize = 40;
 
 pub async fn handle_daemon_health_check() -> Result<()> {
-    let mut client = Client::connect().await?;
-    let response = client.send_health_check().await?;
-    if response == "Daemon alive" {
-        println!("Daemon is responsive.");
-        Ok(())
+    match Client::new().send(HealthCheck).await {
+        Ok(()) => {
+            println!("Daemon is responsive.");
+            Ok(())
+        }
+        Err(ClientError::UnexpectedResponse(response)) => {
+            anyhow::bail!("Daemon responded with an unexpected message: {}", response);
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn handle_daemon_status() -> Result<()> {
+    let status = Client::new().send(GetStatus).await?;
+
+    println!("Daemon is running (PID {}).", status.pid);
+    println!("Uptime:             {:.0?}", status.uptime);
+    println!("Storage backend:    {}", status.storage);
+    println!("Tracked commands:   {}", status.tracked_commands);
+    println!("In-flight commands: {}", status.in_flight_commands);
+    println!("Dropped by hooks:   {}", format_drops(&drops::summary()?));
+
+    if status.problems.is_empty() {
+        println!("No problems detected.");
     } else {
-        anyhow::bail!("Daemon responded with an unexpected message: {}", response);
+        println!("Problems:");
+        for problem in &status.problems {
+            println!("  - {}", problem);
+        }
     }
+
+    Ok(())
 }
 
-pub async fn handle_daemon_command_begin(pid: u32, command: String) -> Result<()> {
-    Client::connect()
-        .await?
-        .send_command_begin(pid, command)
-        .await
+/// Reports the start of a command for the shell hook. Never fails and never takes longer than
+/// `timeout`: a message the daemon does not take in time is dropped and counted as such.
+pub async fn handle_daemon_command_begin(
+    pid: u32,
+    command: String,
+    cwd: Option<PathBuf>,
+    timeout: Duration,
+) -> Result<()> {
+    // The hook sends the whole command line, which spans several lines for e.g. a loop typed
+    // over several lines. It is tracked as one line, so that it lists like any other command.
+    let command = command.replace('\n', " ");
+    send_from_hook(
+        &hook_client(timeout),
+        CommandBegin { pid, command, cwd },
+        drops::record,
+    )
+    .await;
+    Ok(())
 }
 
-pub async fn handle_daemon_command_end(pid: u32, exit_code: i32) -> Result<()> {
-    Client::connect()
-        .await?
-        .send_end_command(pid, exit_code)
-        .await
+/// Reports the end of a command or background job for the shell hook, with the same
+/// guarantees as [`handle_daemon_command_begin`].
+pub async fn handle_daemon_command_end(
+    pid: u32,
+    exit_code: i32,
+    job: Option<u32>,
+    pipe_status: Vec<i32>,
+    timeout: Duration,
+) -> Result<()> {
+    let notification = send_from_hook(
+        &hook_client(timeout),
+        CommandEnd {
+            pid,
+            exit_code,
+            job,
+            pipe_status,
+        },
+        drops::record,
+    )
+    .await
+    .flatten();
+    if let Some(notification) = notification {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(notification.escape_sequence().as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// Reports that a command went to the background for the shell hook, with the same guarantees
+/// as [`handle_daemon_command_begin`].
+pub async fn handle_daemon_command_background(pid: u32, job: u32, timeout: Duration) -> Result<()> {
+    send_from_hook(
+        &hook_client(timeout),
+        CommandBackground { pid, job },
+        drops::record,
+    )
+    .await;
+    Ok(())
+}
+
+/// Reports the start of a shell for the shell hook, with the same guarantees as
+/// [`handle_daemon_command_begin`].
+pub async fn handle_daemon_session_begin(pid: u32, timeout: Duration) -> Result<()> {
+    send_from_hook(&hook_client(timeout), SessionBegin { pid }, drops::record).await;
+    Ok(())
+}
+
+/// Reports the exit of a shell for the shell hook, with the same guarantees as
+/// [`handle_daemon_command_begin`].
+pub async fn handle_daemon_session_end(pid: u32, timeout: Duration) -> Result<()> {
+    send_from_hook(&hook_client(timeout), SessionEnd { pid }, drops::record).await;
+    Ok(())
+}
+
+fn hook_client(timeout: Duration) -> Client {
+    Client::with_options(ClientOptions::default().timeout(timeout))
+}
+
+/// Sends a request of a shell hook, handing any failure to `on_drop` instead of returning it.
+async fn send_from_hook<R: ClientRequest>(
+    client: &Client,
+    request: R,
+    on_drop: impl FnOnce(&ClientError),
+) -> Option<R::Response> {
+    match client.send(request).await {
+        Ok(response) => Some(response),
+        Err(e) => {
+            on_drop(&e);
+            None
+        }
+    }
 }
 
 pub async fn handle_daemon_stop() -> Result<()> {
-    Client::connect().await?.send_stop().await?;
+    Client::new().send(Stop).await?;
     Ok(())
 }
 
-pub async fn handle_stats(filter: Option<String>) -> Result<()> {
-    let all_stats = Client::connect().await?.send_get_stats().await?;
+pub async fn handle_stats(
+    filter: Option<String>,
+    sort: Option<SortKey>,
+    limit: Option<usize>,
+    failures: bool,
+    include_interrupted: bool,
+    config_path: &Path,
+) -> Result<()> {
+    let display = Config::load(config_path)?.display;
+    let sort = sort.unwrap_or(display.sort);
+    let limit = limit.or(display.limit);
+    let include_interrupted = include_interrupted || display.include_interrupted;
+
+    let all_stats = Client::new().send(GetStats).await?;
 
     if all_stats.is_empty() {
         println!("No commands tracked yet. Run a few commands and try again!");
@@ -51,14 +186,217 @@ pub async fn handle_stats(filter: Option<String>) -> Result<()> {
         return Ok(());
     }
 
-    filtered_stats.sort_by(|first, second| first.1.total_duration.cmp(&second.1.total_duration));
+    if failures {
+        filtered_stats.retain(|(_command, stats)| !stats.exit_codes.is_empty());
+        if filtered_stats.is_empty() {
+            println!("No failures recorded.");
+            return Ok(());
+        }
+        filtered_stats.sort_by_key(|(_command, stats)| (stats.fail_count, stats.signal_count));
+    } else {
+        sort_stats(&mut filtered_stats, sort, include_interrupted);
+    }
+    if let Some(limit) = limit {
+        let skipped = filtered_stats.len().saturating_sub(limit);
+        filtered_stats.drain(..skipped);
+    }
+
+    if failures {
+        println!(
+            "{}",
+            build_failures_table(filtered_stats, SystemTime::now())
+        );
+    } else {
+        let regressions = Client::new()
+            .send(GetRegressions)
+            .await?
+            .into_iter()
+            .map(|regression| (regression.command.clone(), regression))
+            .collect();
+        println!(
+            "{}",
+            build_stats_table(filtered_stats, &regressions, include_interrupted)
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn handle_regressions() -> Result<()> {
+    let regressions = Client::new().send(GetRegressions).await?;
+    if regressions.is_empty() {
+        println!("No regressions: every command ran about as fast as usual.");
+        return Ok(());
+    }
+
+    println!(
+        "{}",
+        build_regressions_table(regressions, SystemTime::now())
+    );
+    Ok(())
+}
+
+pub async fn handle_sessions() -> Result<()> {
+    let sessions = Client::new().send(GetSessions).await?;
+    if sessions.is_empty() {
+        println!("No sessions yet. Sessions start with the shells sourcing `t_trace init bash`.");
+        return Ok(());
+    }
+
+    println!("{}", build_sessions_table(&sessions, SystemTime::now()));
+    Ok(())
+}
+
+pub async fn handle_show(
+    command: String,
+    runs: usize,
+    include_interrupted: bool,
+    config_path: &Path,
+) -> Result<()> {
+    let include_interrupted =
+        include_interrupted || Config::load(config_path)?.display.include_interrupted;
+    let history = Client::new().send(GetHistory { command }).await?;
+    if history.stats.is_none() {
+        println!("No stats recorded for \"{}\".", history.command);
+        return Ok(());
+    }
+
+    print!(
+        "{}",
+        render_history(&history, runs, include_interrupted, SystemTime::now())
+    );
+    Ok(())
+}
+
+pub async fn handle_top(interval: Duration, config_path: &Path) -> Result<()> {
+    let display = Config::load(config_path)?.display;
+    // Fail with the usual message before taking over the terminal if the daemon is not running.
+    Client::new().send(HealthCheck).await?;
+    tui::run(
+        App::new(display.sort, display.include_interrupted),
+        interval,
+    )
+    .await
+}
+
+pub async fn handle_watch(json: bool) -> Result<()> {
+    let mut subscription = Client::new().subscribe().await?;
+    while let Some(event) = subscription.next_event().await? {
+        if json {
+            println!("{}", serde_json::to_string(&event)?);
+        } else {
+            println!("{}", format_event(&event));
+        }
+    }
+    Ok(())
+}
+
+pub async fn handle_export(format: ExportFormat) -> Result<()> {
+    let document = Client::new().send(Export { format }).await?;
+    std::io::stdout().write_all(document.as_bytes())?;
+    Ok(())
+}
+
+pub async fn handle_import(
+    file: &Path,
+    format: Option<ExportFormat>,
+    mode: ImportMode,
+) -> Result<()> {
+    let format = format
+        .or_else(|| ExportFormat::from_path(file))
+        .unwrap_or_default();
+    let input = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read import file: {:?}", file))?;
+    let stats = export::parse(&input, format)
+        .with_context(|| format!("Failed to parse {:?} as {}", file, format))?;
+
+    let imported = Client::new().send(Import { stats, mode }).await?;
+    match mode {
+        ImportMode::Merge => println!("Merged stats of {} command(s).", imported),
+        ImportMode::Replace => println!("Replaced all stats with {} command(s).", imported),
+    }
+    Ok(())
+}
+
+pub async fn handle_stats_reset(pattern: Option<String>) -> Result<()> {
+    let removed = Client::new()
+        .send(ResetStats {
+            pattern: pattern.clone(),
+        })
+        .await?;
+    match pattern {
+        Some(pattern) => println!(
+            "Deleted stats of {} command(s) matching \"{}\".",
+            removed, pattern
+        ),
+        None => println!("Deleted stats of all {} command(s).", removed),
+    }
+    Ok(())
+}
+
+pub async fn handle_forget(command: String) -> Result<()> {
+    if Client::new()
+        .send(Forget {
+            command: command.clone(),
+        })
+        .await?
+    {
+        println!("Forgot \"{}\".", command);
+    } else {
+        println!("No stats recorded for \"{}\".", command);
+    }
+    Ok(())
+}
+
+pub async fn handle_prune(older_than: Option<Duration>, min_count: Option<u64>) -> Result<()> {
+    let pruned = Client::new()
+        .send(Prune {
+            older_than,
+            min_count,
+        })
+        .await?;
+    println!("Pruned stats of {} command(s).", pruned);
+    Ok(())
+}
 
-    println!("{}", build_stats_table(filtered_stats));
+pub fn handle_config_show(config_path: &Path) -> Result<()> {
+    print!("{}", Config::load(config_path)?.to_toml()?);
+    Ok(())
+}
 
+pub fn handle_config_validate(config_path: &Path) -> Result<()> {
+    if !config_path.exists() {
+        println!(
+            "No config file at {}; the defaults are used.",
+            config_path.display()
+        );
+        return Ok(());
+    }
+    Config::load(config_path)?;
+    println!("{} is valid.", config_path.display());
     Ok(())
 }
 
-fn filter_stats(
+/// Sorts in ascending order, so that the largest values end up next to the prompt.
+pub(crate) fn sort_stats(
+    stats: &mut [(String, CommandStats)],
+    sort: SortKey,
+    include_interrupted: bool,
+) {
+    match sort {
+        SortKey::Total => {
+            stats.sort_by_key(|(_command, stats)| stats.total_time(include_interrupted))
+        }
+        SortKey::Mean => {
+            stats.sort_by_key(|(_command, stats)| stats.mean_duration(include_interrupted))
+        }
+        SortKey::Count => stats.sort_by_key(|(_command, stats)| stats.run_count()),
+        SortKey::Last => stats.sort_by_key(|(_command, stats)| stats.last_run_duration),
+        SortKey::Command => stats.sort_by(|(first, _), (second, _)| first.cmp(second)),
+    }
+}
+
+pub(crate) fn filter_stats(
     stats: Vec<(String, CommandStats)>,
     filter: &Option<String>,
 ) -> Vec<(String, CommandStats)> {
@@ -73,19 +411,90 @@ fn filter_stats(
         .collect()
 }
 
-fn build_stats_table(stats_to_display: Vec<(String, CommandStats)>) -> String {
+/// Interrupted runs are left out of the total and mean times unless `include_interrupted` is set.
+/// The last time of commands in `regressions` is marked with their slowdown.
+fn build_stats_table(
+    stats_to_display: Vec<(String, CommandStats)>,
+    regressions: &HashMap<String, Regression>,
+    include_interrupted: bool,
+) -> String {
+    let show_interrupted = stats_to_display
+        .iter()
+        .any(|(_command, data)| data.interrupted_count > 0);
+    let show_abandoned = stats_to_display
+        .iter()
+        .any(|(_command, data)| data.abandoned_count > 0);
+
+    let mut header = vec![
+        "Command",
+        "Success Count",
+        "Fail Count",
+        "Total Time",
+        "Mean Time",
+        "Last Time",
+    ];
+    if show_interrupted {
+        header.push("Interrupted");
+    }
+    if show_abandoned {
+        header.push("Abandoned");
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+
+    table
+        .set_header(
+            header
+                .into_iter()
+                .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+        )
+        .set_content_arrangement(ContentArrangement::Dynamic);
+
+    for (command, data) in stats_to_display {
+        let last_time = match regressions.get(&command) {
+            Some(regression) => Cell::new(format!(
+                "{:.3?} ▲ {:.1}×",
+                data.last_run_duration,
+                regression.slowdown()
+            ))
+            .fg(Color::Red),
+            None => Cell::new(format!("{:.3?}", data.last_run_duration)),
+        };
+        let mut row = vec![
+            Cell::new(command).fg(Color::Yellow),
+            Cell::new(data.success_count.to_string()).fg(Color::Green),
+            Cell::new(data.fail_count.to_string()).fg(Color::Red),
+            Cell::new(format!("{:.3?}", data.total_time(include_interrupted))),
+            Cell::new(format!("{:.3?}", data.mean_duration(include_interrupted))),
+            last_time,
+        ];
+        if show_interrupted {
+            row.push(Cell::new(data.interrupted_count.to_string()).fg(Color::DarkGrey));
+        }
+        if show_abandoned {
+            row.push(Cell::new(data.abandoned_count.to_string()).fg(Color::DarkGrey));
+        }
+        table.add_row(row);
+    }
+
+    table.to_string()
+}
+
+fn build_failures_table(stats_to_display: Vec<(String, CommandStats)>, now: SystemTime) -> String {
     let mut table = Table::new();
     table.load_preset(UTF8_FULL);
 
     table
         .set_header(
-            vec![
+            [
                 "Command",
-                "Success Count",
                 "Fail Count",
-                "Total Time",
-                "Mean Time",
-                "Last Time",
+                "Fail Rate",
+                "Signal Count",
+                "Interrupted",
+                "Top Exit Codes",
+                "Last Failure",
             ]
             .into_iter()
             .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
@@ -93,32 +502,380 @@ fn build_stats_table(stats_to_display: Vec<(String, CommandStats)>) -> String {
         .set_content_arrangement(ContentArrangement::Dynamic);
 
     for (command, data) in stats_to_display {
-        let mean_duration = if data.success_count > 0 || data.fail_count > 0 {
-            data.total_duration.as_nanos() / (data.success_count as u128 + data.fail_count as u128)
-        } else {
-            0
+        let fail_rate = match data.fail_rate() {
+            Some(rate) => format!("{:.1}%", rate * 100.0),
+            None => "-".to_string(),
+        };
+        let top_exit_codes = data
+            .top_exit_codes(3)
+            .into_iter()
+            .map(|(code, count)| format!("{} ×{}", exit_code_label(code), count))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let last_failure = match data.last_failure_at {
+            Some(time) => format_time_ago(time, now),
+            None => "-".to_string(),
         };
 
         table.add_row(vec![
             Cell::new(command).fg(Color::Yellow),
-            Cell::new(data.success_count.to_string()).fg(Color::Green),
             Cell::new(data.fail_count.to_string()).fg(Color::Red),
-            Cell::new(format!("{:.3?}", data.total_duration)),
+            Cell::new(fail_rate),
+            Cell::new(data.signal_count.to_string()).fg(Color::DarkGrey),
+            Cell::new(data.interrupted_count.to_string()).fg(Color::DarkGrey),
+            Cell::new(top_exit_codes),
+            Cell::new(last_failure),
+        ]);
+    }
+
+    table.to_string()
+}
+
+fn build_regressions_table(regressions: Vec<Regression>, now: SystemTime) -> String {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+
+    table
+        .set_header(
+            [
+                "Command",
+                "Last Time",
+                "Baseline (p50)",
+                "Slowdown",
+                "Last Run",
+            ]
+            .into_iter()
+            .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+        )
+        .set_content_arrangement(ContentArrangement::Dynamic);
+
+    for regression in regressions {
+        table.add_row(vec![
+            Cell::new(&regression.command).fg(Color::Yellow),
+            Cell::new(format!("{:.3?}", regression.last_run.duration)).fg(Color::Red),
             Cell::new(format!(
-                "{:.3?}",
-                Duration::from_nanos(mean_duration as u64)
+                "{:.3?} over {} runs",
+                regression.baseline, regression.baseline_runs
             )),
-            Cell::new(format!("{:.3?}", data.last_run_duration)),
+            Cell::new(format!("{:.1}×", regression.slowdown())).fg(Color::Red),
+            Cell::new(format_time_ago(regression.last_run.finished_at, now)),
+        ]);
+    }
+
+    table.to_string()
+}
+
+fn build_sessions_table(sessions: &[Session], now: SystemTime) -> String {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+
+    table
+        .set_header(
+            [
+                "Shell PID",
+                "Started",
+                "Ended",
+                "Duration",
+                "Commands",
+                "Busy",
+                "Idle",
+                "Busy %",
+            ]
+            .into_iter()
+            .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+        )
+        .set_content_arrangement(ContentArrangement::Dynamic);
+
+    for session in sessions {
+        let duration = session.duration(now);
+        let busy_share = session.busy.as_secs_f64() / duration.as_secs_f64().max(f64::EPSILON);
+        let ended = match session.ended_at {
+            Some(ended_at) => Cell::new(format_time_ago(ended_at, now)),
+            None => Cell::new("running").fg(Color::Green),
+        };
+        table.add_row(vec![
+            Cell::new(session.pid),
+            Cell::new(format_time_ago(session.started_at, now)),
+            ended,
+            Cell::new(format_span(duration)),
+            Cell::new(session.command_count),
+            Cell::new(format_span(session.busy)).fg(Color::Yellow),
+            Cell::new(format_span(session.idle(now))),
+            Cell::new(format!("{:.0}%", busy_share.min(1.0) * 100.0)),
         ]);
     }
 
     table.to_string()
 }
 
+/// The summary, recent-run sparkline and duration histogram of a single command.
+fn render_history(
+    history: &CommandHistory,
+    runs: usize,
+    include_interrupted: bool,
+    now: SystemTime,
+) -> String {
+    let stats = history.stats.clone().unwrap_or_default();
+    let mut output = String::new();
+
+    let outcomes: Vec<String> = [
+        (stats.success_count, "succeeded"),
+        (stats.fail_count, "failed"),
+        (stats.signal_count, "killed by a signal"),
+        (stats.interrupted_count, "interrupted"),
+        (stats.abandoned_count, "abandoned"),
+    ]
+    .into_iter()
+    .filter(|(count, _outcome)| *count > 0)
+    .map(|(count, outcome)| format!("{} {}", count, outcome))
+    .collect();
+    let histogram = stats.histogram(include_interrupted);
+    let percentile = |quantile| match histogram.quantile(quantile) {
+        Some(bound) => format!("< {}", charts::format_bound(bound)),
+        None => "-".to_string(),
+    };
+    let last_run = match stats.last_run_at {
+        Some(time) => format!(
+            "{:.3?}, {}",
+            stats.last_run_duration,
+            format_time_ago(time, now)
+        ),
+        None => format!("{:.3?}", stats.last_run_duration),
+    };
+
+    let _ = writeln!(output, "{}", history.command);
+    let _ = writeln!(
+        output,
+        "  Runs:      {} ({})",
+        stats.run_count(),
+        outcomes.join(", ")
+    );
+    let _ = writeln!(
+        output,
+        "  Mean:      {:.3?}  p50 {}  p90 {}",
+        stats.mean_duration(include_interrupted),
+        percentile(0.5),
+        percentile(0.9)
+    );
+    let _ = writeln!(output, "  Last run:  {}", last_run);
+    if let Some(run) = history.runs.last()
+        && !run.pipe_status.is_empty()
+    {
+        let stages: Vec<String> = run
+            .pipe_status
+            .iter()
+            .map(|exit_code| exit_code_label(*exit_code))
+            .collect();
+        let _ = writeln!(output, "  Stages:    {}", stages.join(" | "));
+    }
+
+    let recent: Vec<Duration> = history
+        .runs
+        .iter()
+        .filter(|run| include_interrupted || !is_interrupted_exit(run.exit_code))
+        .map(|run| run.duration)
+        .collect();
+    let recent = &recent[recent.len().saturating_sub(runs)..];
+    if let (Some(shortest), Some(longest)) = (recent.iter().min(), recent.iter().max()) {
+        let _ = writeln!(
+            output,
+            "\nLast {} runs, oldest first ({:.3?} to {:.3?}):",
+            recent.len(),
+            shortest,
+            longest
+        );
+        let _ = writeln!(output, "  {}", charts::sparkline(recent));
+    }
+
+    let rows: Vec<(String, u64)> = histogram
+        .span()
+        .into_iter()
+        .map(|(bucket, count)| {
+            let bound = DurationHistogram::upper_bound(bucket);
+            (format!("<{}", charts::format_bound(bound)), count)
+        })
+        .collect();
+    if !rows.is_empty() {
+        let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        let max_count = rows.iter().map(|(_, count)| *count).max().unwrap_or(0);
+
+        let _ = writeln!(
+            output,
+            "\nDurations of {} {}:",
+            histogram.count(),
+            if include_interrupted {
+                "runs"
+            } else {
+                "completed runs"
+            }
+        );
+        for (label, count) in rows {
+            let bar = charts::bar(count, max_count, HISTOGRAM_WIDTH);
+            let _ = writeln!(
+                output,
+                "  {:>label_width$} │{:<HISTOGRAM_WIDTH$} {}",
+                label, bar, count
+            );
+        }
+    }
+
+    output
+}
+
+fn format_event(event: &DaemonEvent) -> String {
+    match event {
+        DaemonEvent::CommandStarted { pid, command, .. } => {
+            format!("▶ [{}] {}", pid, command)
+        }
+        DaemonEvent::CommandFinished {
+            pid,
+            command,
+            exit_code: 0,
+            duration_ms,
+            ..
+        } => format!(
+            "✔ [{}] {} ({:.1?})",
+            pid,
+            command,
+            Duration::from_millis(*duration_ms)
+        ),
+        DaemonEvent::CommandFinished {
+            pid,
+            command,
+            exit_code,
+            duration_ms,
+            ..
+        } => format!(
+            "✘ [{}] {} (exit code {} after {:.1?})",
+            pid,
+            command,
+            exit_code_label(*exit_code),
+            Duration::from_millis(*duration_ms)
+        ),
+        DaemonEvent::StatsSaved { commands } => {
+            format!("· Saved stats of {} command(s).", commands)
+        }
+        DaemonEvent::Lagged { missed } => {
+            format!("· Fell behind and missed {} event(s).", missed)
+        }
+        DaemonEvent::ShuttingDown => "· The daemon is shutting down.".to_string(),
+    }
+}
+
+/// The hook messages that never reached the daemon, e.g. `3 (2 timed out, 1 unreachable), last 5m ago`.
+fn format_drops(summary: &DropSummary) -> String {
+    let Some(last_dropped_at) = summary.last_dropped_at else {
+        return "none".to_string();
+    };
+    let reasons: Vec<String> = [
+        (summary.timed_out, "timed out"),
+        (summary.unreachable, "unreachable"),
+        (summary.failed, "failed"),
+    ]
+    .into_iter()
+    .filter(|(count, _reason)| *count > 0)
+    .map(|(count, reason)| format!("{} {}", count, reason))
+    .collect();
+    format!(
+        "{} ({}), last {}",
+        summary.total(),
+        reasons.join(", "),
+        format_time_ago(last_dropped_at, SystemTime::now())
+    )
+}
+
+/// A span of time in its two largest units, e.g. `2h 05m`, `4m 10s` or `12s`.
+fn format_span(span: Duration) -> String {
+    let seconds = span.as_secs();
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3_600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3_600, seconds % 3_600 / 60),
+    }
+}
+
+/// How long ago `time` was, in the largest whole unit, e.g. `3h ago`.
+pub(crate) fn format_time_ago(time: SystemTime, now: SystemTime) -> String {
+    let seconds = now.duration_since(time).unwrap_or_default().as_secs();
+    let (amount, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3_600 => (seconds / 60, "m"),
+        3_600..86_400 => (seconds / 3_600, "h"),
+        _ => (seconds / 86_400, "d"),
+    };
+    format!("{}{} ago", amount, unit)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
+    use crate::dto::RunRecord;
+    use std::time::{Duration, Instant};
+    use tempfile::tempdir;
+    use tokio::net::UnixListener;
+
+    #[tokio::test]
+    async fn hooks_give_up_on_a_wedged_daemon_and_note_the_drop() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("daemon.sock");
+        // Accepts connections into the backlog but never reads or answers, like a daemon stuck
+        // on its state lock.
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+        let client = Client::with_options(
+            ClientOptions::default()
+                .socket_path(&socket_path)
+                .timeout(Duration::from_millis(50)),
+        );
+        let mut dropped = Vec::new();
+
+        let begin = CommandBegin {
+            pid: 1,
+            command: "make".to_string(),
+            cwd: None,
+        };
+        let sent = send_from_hook(&client, begin, |e| dropped.push(e.to_string())).await;
+        assert_eq!(sent, Some(()));
+
+        let started = Instant::now();
+        let end = CommandEnd {
+            pid: 1,
+            exit_code: 0,
+            job: None,
+            pipe_status: Vec::new(),
+        };
+        let answer = send_from_hook(&client, end, |e| dropped.push(e.to_string())).await;
+        assert!(answer.is_none());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(dropped, vec!["Daemon did not answer within 50ms"]);
+
+        std::fs::remove_file(&socket_path).unwrap();
+        let begin = CommandBegin {
+            pid: 2,
+            command: "make".to_string(),
+            cwd: None,
+        };
+        assert!(
+            send_from_hook(&client, begin, |e| dropped.push(e.to_string()))
+                .await
+                .is_none()
+        );
+        assert_eq!(dropped.len(), 2);
+    }
+
+    #[test]
+    fn drops_are_summed_up_by_reason() {
+        assert_eq!(format_drops(&DropSummary::default()), "none");
+        let summary = DropSummary {
+            timed_out: 2,
+            unreachable: 1,
+            failed: 0,
+            last_dropped_at: Some(SystemTime::now() - Duration::from_secs(300)),
+        };
+        assert_eq!(
+            format_drops(&summary),
+            "3 (2 timed out, 1 unreachable), last 5m ago"
+        );
+    }
 
     fn dummy_command_stats(count: u64) -> CommandStats {
         CommandStats {
@@ -126,7 +883,220 @@ mod tests {
             last_run_duration: Duration::from_secs(1),
             success_count: count,
             fail_count: count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sort_stats_puts_largest_values_last() {
+        let mut stats = vec![
+            ("b".to_string(), dummy_command_stats(10)),
+            ("a".to_string(), dummy_command_stats(30)),
+            ("c".to_string(), dummy_command_stats(20)),
+        ];
+
+        sort_stats(&mut stats, SortKey::Count, false);
+        let commands: Vec<&str> = stats.iter().map(|(command, _)| command.as_str()).collect();
+        assert_eq!(commands, vec!["b", "c", "a"]);
+
+        sort_stats(&mut stats, SortKey::Command, false);
+        let commands: Vec<&str> = stats.iter().map(|(command, _)| command.as_str()).collect();
+        assert_eq!(commands, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn sessions_show_busy_and_idle_time() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let sessions = [
+            Session {
+                pid: 4242,
+                started_at: now - Duration::from_secs(7_200),
+                ended_at: Some(now - Duration::from_secs(3_600)),
+                command_count: 12,
+                busy: Duration::from_secs(900),
+            },
+            Session {
+                pid: 4343,
+                started_at: now - Duration::from_secs(100),
+                ended_at: None,
+                command_count: 1,
+                busy: Duration::from_secs(75),
+            },
+        ];
+        let table = build_sessions_table(&sessions, now);
+        let rows: Vec<&str> = table
+            .lines()
+            .filter(|line| line.contains("4242") || line.contains("4343"))
+            .collect();
+
+        assert!(rows[0].contains("2h ago") && rows[0].contains("1h ago"));
+        assert!(rows[0].contains("1h 00m") && rows[0].contains("15m 00s"));
+        assert!(rows[0].contains("45m 00s") && rows[0].contains("25%"));
+        assert!(rows[1].contains("running") && rows[1].contains("1m 15s"));
+        assert!(rows[1].contains("25s") && rows[1].contains("75%"));
+    }
+
+    #[test]
+    fn time_ago_uses_largest_whole_unit() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let ago = |seconds| format_time_ago(now - Duration::from_secs(seconds), now);
+        assert_eq!(ago(5), "just now");
+        assert_eq!(ago(150), "2m ago");
+        assert_eq!(ago(7_200), "2h ago");
+        assert_eq!(ago(3 * 86_400 + 5), "3d ago");
+    }
+
+    #[test]
+    fn history_shows_sparkline_of_recent_runs_and_histogram() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let run = |secs, exit_code| RunRecord {
+            finished_at: now - Duration::from_secs(60),
+            duration: Duration::from_secs(secs),
+            exit_code,
+            pipe_status: Vec::new(),
+        };
+        let mut histogram = DurationHistogram::default();
+        for secs in [1, 2, 4] {
+            histogram.record(Duration::from_secs(secs));
         }
+        let history = CommandHistory {
+            command: "cargo test".to_string(),
+            stats: Some(CommandStats {
+                total_duration: Duration::from_secs(7),
+                last_run_duration: Duration::from_secs(4),
+                success_count: 2,
+                fail_count: 1,
+                interrupted_count: 1,
+                last_run_at: Some(now - Duration::from_secs(60)),
+                duration_histogram: histogram,
+                interrupted_histogram: [(DurationHistogram::bucket_of(Duration::from_secs(30)), 1)]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            }),
+            runs: vec![run(9, 0), run(1, 0), run(2, 1), run(30, 130), run(4, 0)],
+        };
+        let mut piped = history.clone();
+        piped.runs.last_mut().unwrap().pipe_status = vec![141, 0];
+
+        let output = render_history(&history, 3, false, now);
+        assert!(output.contains("Runs:      4 (2 succeeded, 1 failed, 1 interrupted)"));
+        assert!(output.contains("Last run:  4.000s, 1m ago"));
+        assert!(output.contains("p90 < 4s"));
+        assert!(output.contains("Last 3 runs, oldest first (1.000s to 4.000s):\n  ▁▃█"));
+        assert!(output.contains("Durations of 3 completed runs:"));
+        assert!(output.contains("<2s │██████████"));
+        assert_eq!(output.matches(" │").count(), 3);
+
+        let output = render_history(&history, 3, true, now);
+        assert!(output.contains("p90 < 32s"));
+        assert!(output.contains("Durations of 4 runs:"));
+        assert!(output.contains("  <32s │"));
+        assert!(output.contains("▁█▂"));
+        assert!(!output.contains("Stages:"));
+        let output = render_history(&piped, 3, false, now);
+        assert!(output.contains("Stages:    141 (SIGPIPE) | 0"));
+    }
+
+    #[test]
+    fn events_are_printed_one_per_line() {
+        let finished = |exit_code| DaemonEvent::CommandFinished {
+            pid: 42,
+            command: "make".to_string(),
+            exit_code,
+            duration_ms: 1_500,
+            cwd: None,
+            finished_at_unix_ms: 1_700_000_000_000,
+            pipe_status: Vec::new(),
+        };
+
+        assert_eq!(format_event(&finished(0)), "✔ [42] make (1.5s)");
+        assert_eq!(
+            format_event(&finished(130)),
+            "✘ [42] make (exit code 130 (SIGINT) after 1.5s)"
+        );
+        assert_eq!(
+            format_event(&DaemonEvent::CommandStarted {
+                pid: 42,
+                command: "make".to_string(),
+                cwd: None,
+            }),
+            "▶ [42] make"
+        );
+    }
+
+    #[test]
+    fn failures_table_shows_rate_and_exit_codes() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let stats = CommandStats {
+            success_count: 3,
+            fail_count: 1,
+            interrupted_count: 2,
+            exit_codes: [(2, 1), (130, 2)].into(),
+            last_failure_at: Some(now - Duration::from_secs(7_200)),
+            ..Default::default()
+        };
+
+        let table = build_failures_table(vec![("make".to_string(), stats)], now);
+        assert!(table.contains("25.0%"));
+        assert!(table.contains("130 (SIGINT) ×2"));
+        assert!(table.contains("2h ago"));
+    }
+
+    #[test]
+    fn interrupted_runs_are_left_out_of_timing_by_default() {
+        let stats = CommandStats {
+            total_duration: Duration::from_secs(20),
+            success_count: 2,
+            interrupted_count: 1,
+            interrupted_duration: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let rows = || vec![("cargo test".to_string(), stats.clone())];
+
+        let table = build_stats_table(rows(), &HashMap::new(), false);
+        assert!(table.contains("Interrupted"));
+        assert!(table.contains("20.000s"));
+        assert!(table.contains("10.000s"));
+
+        let table = build_stats_table(rows(), &HashMap::new(), true);
+        assert!(table.contains("21.000s"));
+        assert!(table.contains("7.000s"));
+    }
+
+    #[test]
+    fn regressions_are_marked_and_listed_with_their_baseline() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let regression = Regression {
+            command: "cargo build".to_string(),
+            last_run: RunRecord {
+                finished_at: now - Duration::from_secs(300),
+                duration: Duration::from_secs(25),
+                exit_code: 0,
+                pipe_status: Vec::new(),
+            },
+            baseline: Duration::from_secs(10),
+            baseline_runs: 20,
+        };
+        let stats = CommandStats {
+            last_run_duration: Duration::from_secs(25),
+            success_count: 21,
+            ..Default::default()
+        };
+        let rows = vec![
+            ("cargo build".to_string(), stats),
+            ("ls".to_string(), dummy_command_stats(3)),
+        ];
+
+        let regressions = HashMap::from([(regression.command.clone(), regression.clone())]);
+        let table = build_stats_table(rows, &regressions, false);
+        assert!(table.contains("25.000s ▲ 2.5×"));
+        assert_eq!(table.matches('▲').count(), 1);
+
+        let table = build_regressions_table(vec![regression], now);
+        assert!(table.contains("10.000s over 20 runs"));
+        assert!(table.contains("2.5×"));
+        assert!(table.contains("5m ago"));
     }
 
     #[test]
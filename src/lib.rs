@@ -1,8 +1,13 @@
+pub mod charts;
 pub mod cli;
 pub mod client;
+pub mod config;
 pub mod daemon;
+pub mod drops;
 pub mod dto;
+pub mod export;
 pub mod handlers;
 pub mod init;
 pub mod protocol;
 pub mod socket;
+pub mod tui;
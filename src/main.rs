@@ -1,8 +1,12 @@
 use anyhow::Result;
 use clap::Parser;
 use daemonize::Daemonize;
-use t_trace::cli::{Cli, Commands, DaemonArgs, DaemonCommands, InitArgs};
-use t_trace::{daemon, handlers, init};
+use t_trace::cli::{
+    Cli, Commands, ConfigArgs, ConfigCommands, DaemonArgs, DaemonCommands, InitArgs, StatsArgs,
+    StatsCommands,
+};
+use t_trace::dto::ImportMode;
+use t_trace::{config, daemon, handlers, init};
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -13,10 +17,44 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    let config_path = match cli.config {
+        Some(path) => path,
+        None => config::path()?,
+    };
+
     if let Commands::Daemon(DaemonArgs {
-        command: DaemonCommands::Run,
+        command:
+            DaemonCommands::Run {
+                in_flight_ttl,
+                autosave_interval,
+                storage,
+                redact_patterns,
+                ignore_commands,
+                ignore_prefixes,
+                ignore_patterns,
+                only_patterns,
+                track_space_prefixed,
+                min_duration,
+            },
     }) = cli.command
     {
+        let options = daemon::DaemonOptions {
+            config_path,
+            in_flight_ttl,
+            autosave_interval,
+            storage,
+            redact_patterns,
+            ignore_commands,
+            ignore_prefixes,
+            ignore_patterns,
+            only_patterns,
+            track_space_prefixed,
+            min_duration,
+        };
+        // Report an invalid config file, or stats written by a newer version, here, while there
+        // is still a terminal to report it to.
+        daemon::check_storage(&options.resolve()?)?;
+
         println!("Starting t_trace daemon in the background...");
         let daemonize = Daemonize::new().pid_file(pid_file).working_directory("/");
 
@@ -24,7 +62,7 @@ fn main() -> Result<()> {
             Ok(_) => {
                 let daemon_rt = tokio::runtime::Runtime::new()
                     .expect("Failed to create Tokio runtime for daemon");
-                if let Err(e) = daemon_rt.block_on(daemon::run()) {
+                if let Err(e) = daemon_rt.block_on(daemon::run(options)) {
                     eprintln!("Daemon failed: {}", e);
                 }
             }
@@ -40,19 +78,94 @@ fn main() -> Result<()> {
     rt.block_on(async {
         match cli.command {
             Commands::Daemon(DaemonArgs { command }) => match command {
-                DaemonCommands::Run => unreachable!(),
+                DaemonCommands::Run { .. } => unreachable!(),
                 DaemonCommands::Stop => {
                     handlers::handle_daemon_stop().await?;
                 }
                 DaemonCommands::HealthCheck => handlers::handle_daemon_health_check().await?,
-                DaemonCommands::CommandBegin { pid, command } => {
-                    handlers::handle_daemon_command_begin(pid, command).await?
+                DaemonCommands::Status => handlers::handle_daemon_status().await?,
+                DaemonCommands::CommandBegin {
+                    pid,
+                    command,
+                    cwd,
+                    timeout,
+                } => handlers::handle_daemon_command_begin(pid, command, cwd, timeout).await?,
+                DaemonCommands::CommandEnd {
+                    pid,
+                    exit_code,
+                    job,
+                    pipe_status,
+                    timeout,
+                } => {
+                    handlers::handle_daemon_command_end(pid, exit_code, job, pipe_status, timeout)
+                        .await?
+                }
+                DaemonCommands::CommandBackground { pid, job, timeout } => {
+                    handlers::handle_daemon_command_background(pid, job, timeout).await?
+                }
+                DaemonCommands::SessionBegin { pid, timeout } => {
+                    handlers::handle_daemon_session_begin(pid, timeout).await?
                 }
-                DaemonCommands::CommandEnd { pid, exit_code } => {
-                    handlers::handle_daemon_command_end(pid, exit_code).await?
+                DaemonCommands::SessionEnd { pid, timeout } => {
+                    handlers::handle_daemon_session_end(pid, timeout).await?
                 }
             },
-            Commands::Stats { filter } => handlers::handle_stats(filter).await?,
+            Commands::Stats(StatsArgs {
+                command,
+                filter,
+                sort,
+                limit,
+                failures,
+                include_interrupted,
+            }) => match command {
+                None => {
+                    handlers::handle_stats(
+                        filter,
+                        sort,
+                        limit,
+                        failures,
+                        include_interrupted,
+                        &config_path,
+                    )
+                    .await?
+                }
+                Some(StatsCommands::Reset { all: _, pattern }) => {
+                    handlers::handle_stats_reset(pattern).await?
+                }
+            },
+            Commands::Config(ConfigArgs { command }) => match command {
+                ConfigCommands::Show => handlers::handle_config_show(&config_path)?,
+                ConfigCommands::Path => println!("{}", config_path.display()),
+                ConfigCommands::Validate => handlers::handle_config_validate(&config_path)?,
+            },
+            Commands::Show {
+                command,
+                runs,
+                include_interrupted,
+            } => handlers::handle_show(command, runs, include_interrupted, &config_path).await?,
+            Commands::Regressions => handlers::handle_regressions().await?,
+            Commands::Sessions => handlers::handle_sessions().await?,
+            Commands::Watch { json } => handlers::handle_watch(json).await?,
+            Commands::Top { interval } => handlers::handle_top(interval, &config_path).await?,
+            Commands::Forget { command } => handlers::handle_forget(command).await?,
+            Commands::Prune {
+                older_than,
+                min_count,
+            } => handlers::handle_prune(older_than, min_count).await?,
+            Commands::Export { format } => handlers::handle_export(format).await?,
+            Commands::Import {
+                file,
+                format,
+                merge: _,
+                replace,
+            } => {
+                let mode = if replace {
+                    ImportMode::Replace
+                } else {
+                    ImportMode::Merge
+                };
+                handlers::handle_import(&file, format, mode).await?
+            }
             Commands::Init(_) => unreachable!(),
         }
 
@@ -1,17 +1,116 @@
+use crate::dto::{CommandStats, ImportMode};
+use crate::export::ExportFormat;
+
 use anyhow::{Result, anyhow, bail};
+use clap::ValueEnum;
+use std::collections::HashMap;
 use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Separates the command of `COMMAND_BEGIN` from the optional `key=value` fields after it.
+/// Unknown fields are ignored, so that newer shell hooks work with older daemons.
+const FIELD_SEPARATOR: char = '\x1f';
+
+/// Escapes backslashes and line breaks in free text, so that e.g. a command line with a heredoc
+/// stays on the single line of its request.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverses [`escape`]. Any other backslash is kept as it is, as sent by older clients.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let replacement = match (c, chars.peek()) {
+            ('\\', Some('\\')) => '\\',
+            ('\\', Some('n')) => '\n',
+            ('\\', Some('r')) => '\r',
+            _ => {
+                unescaped.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        unescaped.push(replacement);
+    }
+    unescaped
+}
 
 #[derive(Debug, PartialEq)]
 pub enum Request {
     Stop,
     HealthCheck,
-    CommandBegin { pid: u32, command: String },
-    CommandEnd { pid: u32, exit_code: i32 },
+    CommandBegin {
+        pid: u32,
+        command: String,
+        /// Working directory of the shell when the command started.
+        cwd: Option<PathBuf>,
+    },
+    CommandEnd {
+        pid: u32,
+        exit_code: i32,
+        /// The background job that ended, rather than the command in the foreground.
+        job: Option<u32>,
+        /// Exit codes of the stages of the pipeline the command line ended with, if any.
+        pipe_status: Vec<i32>,
+    },
+    /// The command in flight for `pid` was sent to the background as `job`.
+    CommandBackground {
+        pid: u32,
+        job: u32,
+    },
+    /// A shell started, or re-initialized with `exec`.
+    SessionBegin {
+        pid: u32,
+    },
+    /// A shell is exiting.
+    SessionEnd {
+        pid: u32,
+    },
+    GetSessions,
     GetStats,
+    GetInFlight,
+    GetHistory {
+        command: String,
+    },
+    GetRegressions,
+    Subscribe,
+    Status,
+    Export {
+        format: ExportFormat,
+    },
+    Import {
+        mode: ImportMode,
+        stats: HashMap<String, CommandStats>,
+    },
+    ResetStats {
+        pattern: Option<String>,
+    },
+    Forget {
+        command: String,
+    },
+    Prune {
+        older_than: Option<Duration>,
+        min_count: Option<u64>,
+    },
 }
 
-impl Request {
-    pub fn from_str(s: &str) -> Result<Self> {
+impl FromStr for Request {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
         let s = s.trim();
 
         if s == "STOP" {
@@ -23,6 +122,75 @@ impl Request {
         if s == "GET_STATS" {
             return Ok(Request::GetStats);
         }
+        if s == "GET_IN_FLIGHT" {
+            return Ok(Request::GetInFlight);
+        }
+        if s == "GET_SESSIONS" {
+            return Ok(Request::GetSessions);
+        }
+        if let Some(command) = s.strip_prefix("GET_HISTORY ") {
+            return Ok(Request::GetHistory {
+                command: unescape(command),
+            });
+        }
+        if s == "GET_REGRESSIONS" {
+            return Ok(Request::GetRegressions);
+        }
+        if s == "SUBSCRIBE" {
+            return Ok(Request::Subscribe);
+        }
+        if s == "STATUS" {
+            return Ok(Request::Status);
+        }
+        if let Some(format) = s.strip_prefix("EXPORT ") {
+            let format = ExportFormat::from_str(format.trim(), true).map_err(|e| anyhow!(e))?;
+            return Ok(Request::Export { format });
+        }
+        if let Some(rest) = s.strip_prefix("IMPORT ") {
+            let (mode, payload) = rest
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Missing import payload"))?;
+            let mode = match mode {
+                "MERGE" => ImportMode::Merge,
+                "REPLACE" => ImportMode::Replace,
+                _ => bail!("Unknown import mode: {}", mode),
+            };
+            let stats = serde_json::from_str(payload)?;
+            return Ok(Request::Import { mode, stats });
+        }
+        if s == "RESET_STATS ALL" {
+            return Ok(Request::ResetStats { pattern: None });
+        }
+        if let Some(pattern) = s.strip_prefix("RESET_STATS MATCH ") {
+            return Ok(Request::ResetStats {
+                pattern: Some(unescape(pattern)),
+            });
+        }
+        if let Some(command) = s.strip_prefix("FORGET ") {
+            return Ok(Request::Forget {
+                command: unescape(command),
+            });
+        }
+        if let Some(criteria) = s.strip_prefix("PRUNE") {
+            let mut older_than = None;
+            let mut min_count = None;
+            for criterion in criteria.split_whitespace() {
+                match criterion.split_once('=') {
+                    Some(("older_than_secs", secs)) => {
+                        older_than = Some(Duration::from_secs(secs.parse()?))
+                    }
+                    Some(("min_count", count)) => min_count = Some(count.parse()?),
+                    _ => bail!("Unknown prune criterion: {}", criterion),
+                }
+            }
+            if older_than.is_none() && min_count.is_none() {
+                bail!("Prune requires at least one criterion");
+            }
+            return Ok(Request::Prune {
+                older_than,
+                min_count,
+            });
+        }
 
         let mut parts = s.splitn(3, ' ');
         let verb = parts.next().ok_or_else(|| anyhow!("Missing verb"))?;
@@ -31,17 +199,51 @@ impl Request {
 
         match verb {
             "COMMAND_BEGIN" => {
-                let command = parts
+                let mut fields = parts
                     .next()
                     .ok_or_else(|| anyhow!("Missing command string"))?
-                    .to_string();
-                Ok(Request::CommandBegin { pid, command })
+                    .split(FIELD_SEPARATOR);
+                let command = unescape(fields.next().unwrap_or_default());
+                let mut cwd = None;
+                for field in fields {
+                    if let Some(("cwd", value)) = field.split_once('=') {
+                        cwd = Some(PathBuf::from(unescape(value)));
+                    }
+                }
+                Ok(Request::CommandBegin { pid, command, cwd })
             }
             "COMMAND_END" => {
-                let exit_code_str = parts.next().ok_or_else(|| anyhow!("Missing exit code"))?;
-                let exit_code = exit_code_str.parse::<i32>()?;
-                Ok(Request::CommandEnd { pid, exit_code })
+                let mut fields = parts
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing exit code"))?
+                    .split(FIELD_SEPARATOR);
+                let exit_code = fields.next().unwrap_or_default().parse::<i32>()?;
+                let mut job = None;
+                let mut pipe_status = Vec::new();
+                for field in fields {
+                    match field.split_once('=') {
+                        Some(("job", value)) => job = Some(value.parse()?),
+                        Some(("pipestatus", value)) => {
+                            pipe_status =
+                                value.split(',').map(str::parse).collect::<Result<_, _>>()?;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Request::CommandEnd {
+                    pid,
+                    exit_code,
+                    job,
+                    pipe_status,
+                })
             }
+            "COMMAND_BACKGROUND" => {
+                let job_str = parts.next().ok_or_else(|| anyhow!("Missing job"))?;
+                let job = job_str.parse::<u32>()?;
+                Ok(Request::CommandBackground { pid, job })
+            }
+            "SESSION_BEGIN" => Ok(Request::SessionBegin { pid }),
+            "SESSION_END" => Ok(Request::SessionEnd { pid }),
             _ => bail!("Unknown verb: {}", verb),
         }
     }
@@ -52,13 +254,73 @@ impl fmt::Display for Request {
         match self {
             Request::Stop => write!(f, "STOP"),
             Request::HealthCheck => write!(f, "HEALTH_CHECK"),
-            Request::CommandBegin { pid, command } => {
-                write!(f, "COMMAND_BEGIN {} {}", pid, command)
+            Request::CommandBegin { pid, command, cwd } => {
+                write!(f, "COMMAND_BEGIN {} {}", pid, escape(command))?;
+                if let Some(cwd) = cwd {
+                    write!(
+                        f,
+                        "{}cwd={}",
+                        FIELD_SEPARATOR,
+                        escape(&cwd.display().to_string())
+                    )?;
+                }
+                Ok(())
+            }
+            Request::CommandEnd {
+                pid,
+                exit_code,
+                job,
+                pipe_status,
+            } => {
+                write!(f, "COMMAND_END {} {}", pid, exit_code)?;
+                if let Some(job) = job {
+                    write!(f, "{}job={}", FIELD_SEPARATOR, job)?;
+                }
+                if !pipe_status.is_empty() {
+                    let stages: Vec<String> = pipe_status.iter().map(i32::to_string).collect();
+                    write!(f, "{}pipestatus={}", FIELD_SEPARATOR, stages.join(","))?;
+                }
+                Ok(())
             }
-            Request::CommandEnd { pid, exit_code } => {
-                write!(f, "COMMAND_END {} {}", pid, exit_code)
+            Request::CommandBackground { pid, job } => {
+                write!(f, "COMMAND_BACKGROUND {} {}", pid, job)
             }
+            Request::SessionBegin { pid } => write!(f, "SESSION_BEGIN {}", pid),
+            Request::SessionEnd { pid } => write!(f, "SESSION_END {}", pid),
+            Request::GetSessions => write!(f, "GET_SESSIONS"),
             Request::GetStats => write!(f, "GET_STATS"),
+            Request::GetInFlight => write!(f, "GET_IN_FLIGHT"),
+            Request::GetHistory { command } => write!(f, "GET_HISTORY {}", escape(command)),
+            Request::GetRegressions => write!(f, "GET_REGRESSIONS"),
+            Request::Subscribe => write!(f, "SUBSCRIBE"),
+            Request::Status => write!(f, "STATUS"),
+            Request::Export { format } => write!(f, "EXPORT {}", format),
+            Request::Import { mode, stats } => {
+                let mode = match mode {
+                    ImportMode::Merge => "MERGE",
+                    ImportMode::Replace => "REPLACE",
+                };
+                let payload = serde_json::to_string(stats).map_err(|_| fmt::Error)?;
+                write!(f, "IMPORT {} {}", mode, payload)
+            }
+            Request::ResetStats { pattern: None } => write!(f, "RESET_STATS ALL"),
+            Request::ResetStats {
+                pattern: Some(pattern),
+            } => write!(f, "RESET_STATS MATCH {}", escape(pattern)),
+            Request::Forget { command } => write!(f, "FORGET {}", escape(command)),
+            Request::Prune {
+                older_than,
+                min_count,
+            } => {
+                write!(f, "PRUNE")?;
+                if let Some(older_than) = older_than {
+                    write!(f, " older_than_secs={}", older_than.as_secs())?;
+                }
+                if let Some(min_count) = min_count {
+                    write!(f, " min_count={}", min_count)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -73,16 +335,71 @@ mod tests {
         let expected = Request::CommandBegin {
             pid: 1234,
             command: "ls -l /home/user".to_string(),
+            cwd: None,
         };
         assert_eq!(Request::from_str(input).unwrap(), expected);
     }
 
+    #[test]
+    fn test_command_begin_request_with_fields() {
+        let request = Request::CommandBegin {
+            pid: 1234,
+            command: "make test".to_string(),
+            cwd: Some(PathBuf::from("/home/user/my project")),
+        };
+        let line = request.to_string();
+        assert_eq!(
+            line,
+            "COMMAND_BEGIN 1234 make test\x1fcwd=/home/user/my project"
+        );
+        assert_eq!(Request::from_str(&line).unwrap(), request);
+
+        let with_unknown_field = "COMMAND_BEGIN 1 ls\x1fjob=3\x1fcwd=/tmp";
+        assert_eq!(
+            Request::from_str(with_unknown_field).unwrap(),
+            Request::CommandBegin {
+                pid: 1,
+                command: "ls".to_string(),
+                cwd: Some(PathBuf::from("/tmp")),
+            }
+        );
+    }
+
+    #[test]
+    fn multi_line_commands_stay_on_one_line() {
+        let request = Request::CommandBegin {
+            pid: 1234,
+            command: "cat <<EOF >/dev/null\nhello C:\\temp\\n\r\nEOF".to_string(),
+            cwd: Some(PathBuf::from("/tmp/two\nlines")),
+        };
+        let line = request.to_string();
+        assert_eq!(line.lines().count(), 1);
+        assert_eq!(Request::from_str(&line).unwrap(), request);
+
+        let forget = Request::Forget {
+            command: "git commit -m \"first\nsecond\"".to_string(),
+        };
+        assert_eq!(Request::from_str(&forget.to_string()).unwrap(), forget);
+
+        // A lone backslash, as older clients send it, is kept.
+        assert_eq!(
+            Request::from_str("COMMAND_BEGIN 1 grep a\\|b").unwrap(),
+            Request::CommandBegin {
+                pid: 1,
+                command: "grep a\\|b".to_string(),
+                cwd: None,
+            }
+        );
+    }
+
     #[test]
     fn test_command_end_request_parsing() {
         let input = "COMMAND_END 5678 0";
         let expected = Request::CommandEnd {
             pid: 5678,
             exit_code: 0,
+            job: None,
+            pipe_status: Vec::new(),
         };
         assert_eq!(Request::from_str(input).unwrap(), expected);
     }
@@ -93,6 +410,8 @@ mod tests {
         let expected = Request::CommandEnd {
             pid: 5678,
             exit_code: -1,
+            job: None,
+            pipe_status: Vec::new(),
         };
         assert_eq!(Request::from_str(input).unwrap(), expected);
     }
@@ -102,10 +421,13 @@ mod tests {
         let begin_req = Request::CommandBegin {
             pid: 1234,
             command: "git commit -m \"a message\"".to_string(),
+            cwd: None,
         };
         let end_req = Request::CommandEnd {
             pid: 5678,
             exit_code: 0,
+            job: None,
+            pipe_status: Vec::new(),
         };
 
         let expected_begin = "COMMAND_BEGIN 1234 git commit -m \"a message\"";
@@ -113,6 +435,100 @@ mod tests {
 
         assert_eq!(begin_req.to_string(), expected_begin);
         assert_eq!(end_req.to_string(), expected_end);
+        for request in [
+            Request::GetInFlight,
+            Request::GetHistory {
+                command: "cargo test --release".to_string(),
+            },
+            Request::GetRegressions,
+            Request::Subscribe,
+            Request::CommandEnd {
+                pid: 4321,
+                exit_code: 143,
+                job: Some(4400),
+                pipe_status: Vec::new(),
+            },
+            Request::CommandEnd {
+                pid: 4321,
+                exit_code: 1,
+                job: None,
+                pipe_status: vec![0, 1, 0],
+            },
+            Request::CommandBackground {
+                pid: 4321,
+                job: 4400,
+            },
+            Request::SessionBegin { pid: 4321 },
+            Request::SessionEnd { pid: 4321 },
+            Request::GetSessions,
+        ] {
+            assert_eq!(Request::from_str(&request.to_string()).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn test_export_request_round_trip() {
+        let request = Request::Export {
+            format: ExportFormat::Ndjson,
+        };
+        assert_eq!(request.to_string(), "EXPORT ndjson");
+        assert_eq!(Request::from_str("EXPORT ndjson").unwrap(), request);
+        assert!(Request::from_str("EXPORT xml").is_err());
+    }
+
+    #[test]
+    fn test_import_request_round_trip() {
+        let mut stats = HashMap::new();
+        stats.insert(
+            "echo 'multi\nline'".to_string(),
+            CommandStats {
+                success_count: 2,
+                ..Default::default()
+            },
+        );
+        let request = Request::Import {
+            mode: ImportMode::Replace,
+            stats,
+        };
+
+        let line = request.to_string();
+        assert!(!line.contains('\n'));
+        assert_eq!(Request::from_str(&line).unwrap(), request);
+        assert!(Request::from_str("IMPORT UPSERT {}").is_err());
+    }
+
+    #[test]
+    fn test_removal_requests_round_trip() {
+        let requests = [
+            Request::ResetStats { pattern: None },
+            Request::ResetStats {
+                pattern: Some("git push".to_string()),
+            },
+            Request::Forget {
+                command: "curl -H 'Authorization: x'".to_string(),
+            },
+            Request::Prune {
+                older_than: Some(Duration::from_secs(90 * 24 * 60 * 60)),
+                min_count: Some(2),
+            },
+            Request::Prune {
+                older_than: None,
+                min_count: Some(3),
+            },
+        ];
+        for request in requests {
+            assert_eq!(Request::from_str(&request.to_string()).unwrap(), request);
+        }
+        assert_eq!(
+            Request::Prune {
+                older_than: Some(Duration::from_secs(60)),
+                min_count: Some(2)
+            }
+            .to_string(),
+            "PRUNE older_than_secs=60 min_count=2"
+        );
+        assert!(Request::from_str("PRUNE max_count=2").is_err());
+        assert!(Request::from_str("PRUNE").is_err());
     }
 
     #[test]
@@ -151,6 +567,46 @@ mod tests {
         assert!(Request::from_str(input).is_err());
     }
 
+    #[test]
+    fn test_job_requests_name_the_job() {
+        assert_eq!(
+            Request::from_str("COMMAND_END 1 0\x1fjob=7\x1ftty=/dev/pts/3").unwrap(),
+            Request::CommandEnd {
+                pid: 1,
+                exit_code: 0,
+                job: Some(7),
+                pipe_status: Vec::new(),
+            }
+        );
+        assert!(Request::from_str("COMMAND_END 1 0\x1fjob=%1").is_err());
+        assert!(Request::from_str("COMMAND_BACKGROUND 1").is_err());
+        assert!(Request::from_str("COMMAND_END 1 141\x1fpipestatus=0,x").is_err());
+        assert_eq!(
+            Request::CommandEnd {
+                pid: 1,
+                exit_code: 141,
+                job: None,
+                pipe_status: vec![141, 0],
+            }
+            .to_string(),
+            "COMMAND_END 1 141\x1fpipestatus=141,0"
+        );
+        assert_eq!(
+            Request::CommandBackground { pid: 1, job: 7 }.to_string(),
+            "COMMAND_BACKGROUND 1 7"
+        );
+    }
+
+    #[test]
+    fn test_session_requests_need_a_pid() {
+        assert_eq!(
+            Request::from_str("SESSION_END 77").unwrap(),
+            Request::SessionEnd { pid: 77 }
+        );
+        assert!(Request::from_str("SESSION_BEGIN").is_err());
+        assert!(Request::from_str("SESSION_BEGIN bash").is_err());
+    }
+
     #[test]
     fn test_parsing_fails_on_invalid_exit_code() {
         let input = "COMMAND_END 1234 not_an_int";
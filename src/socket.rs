@@ -1,8 +1,12 @@
-use anyhow::Result;
 use std::path::PathBuf;
 
-pub fn get_socket_path() -> Result<PathBuf> {
+pub fn get_socket_path() -> PathBuf {
     let uid = unsafe { libc::getuid() };
-    let path = PathBuf::from(format!("/tmp/t_trace.{}.sock", uid));
-    Ok(path)
+    PathBuf::from(format!("/tmp/t_trace.{}.sock", uid))
+}
+
+/// Where the shell hooks count the messages they gave up on, next to the socket.
+pub fn get_drop_log_path() -> PathBuf {
+    let uid = unsafe { libc::getuid() };
+    PathBuf::from(format!("/tmp/t_trace.{}.drops", uid))
 }
@@ -0,0 +1,303 @@
+use crate::config::SortKey;
+use crate::dto::{CommandStats, RunningCommand};
+use crate::handlers::{filter_stats, sort_stats};
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::widgets::TableState;
+use std::collections::HashMap;
+
+/// Order in which `s` cycles through the sortable columns.
+const SORT_KEYS: [SortKey; 5] = [
+    SortKey::Total,
+    SortKey::Mean,
+    SortKey::Count,
+    SortKey::Last,
+    SortKey::Command,
+];
+
+/// Rows moved by Page Up and Page Down.
+const PAGE_SIZE: isize = 10;
+
+/// What the main pane shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Table,
+    /// Details of the selected command.
+    Detail,
+}
+
+/// What the event loop should do after a key press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Refresh,
+    Quit,
+}
+
+/// State of the dashboard: the latest data from the daemon and how the user is looking at it.
+#[derive(Debug)]
+pub struct App {
+    stats: HashMap<String, CommandStats>,
+    /// Commands matching the filter, in display order.
+    pub(super) rows: Vec<(String, CommandStats)>,
+    pub(super) running: Vec<RunningCommand>,
+    pub(super) sort: SortKey,
+    pub(super) descending: bool,
+    pub(super) include_interrupted: bool,
+    pub(super) filter: String,
+    pub(super) editing_filter: bool,
+    pub(super) view: View,
+    pub(super) table_state: TableState,
+    /// Why the last refresh failed, shown until the next one succeeds.
+    pub(super) error: Option<String>,
+}
+
+impl App {
+    pub fn new(sort: SortKey, include_interrupted: bool) -> Self {
+        Self {
+            stats: HashMap::new(),
+            rows: Vec::new(),
+            running: Vec::new(),
+            sort,
+            descending: sort != SortKey::Command,
+            include_interrupted,
+            filter: String::new(),
+            editing_filter: false,
+            view: View::Table,
+            table_state: TableState::default(),
+            error: None,
+        }
+    }
+
+    /// Replaces the data with a fresh snapshot from the daemon, keeping the selected command.
+    pub fn update(&mut self, stats: HashMap<String, CommandStats>, running: Vec<RunningCommand>) {
+        self.stats = stats;
+        self.running = running;
+        self.error = None;
+        self.rebuild_rows();
+    }
+
+    /// Keeps showing the last snapshot, e.g. while the daemon restarts.
+    pub fn set_error(&mut self, error: &anyhow::Error) {
+        self.error = Some(format!("{:#}", error));
+    }
+
+    pub fn selected(&self) -> Option<&(String, CommandStats)> {
+        self.table_state
+            .selected()
+            .and_then(|index| self.rows.get(index))
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Action {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        if self.editing_filter {
+            self.edit_filter(key.code);
+            return Action::None;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Esc if self.view == View::Detail => self.view = View::Table,
+            KeyCode::Esc if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.rebuild_rows();
+            }
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Enter if self.view == View::Detail => self.view = View::Table,
+            KeyCode::Enter if self.selected().is_some() => self.view = View::Detail,
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::PageDown => self.move_selection(PAGE_SIZE),
+            KeyCode::PageUp => self.move_selection(-PAGE_SIZE),
+            KeyCode::Home | KeyCode::Char('g') => self.move_selection(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.move_selection(isize::MAX),
+            KeyCode::Char('/') => self.editing_filter = true,
+            KeyCode::Char('s') => {
+                let position = SORT_KEYS.iter().position(|key| *key == self.sort);
+                self.sort =
+                    SORT_KEYS[position.map_or(0, |position| position + 1) % SORT_KEYS.len()];
+                self.descending = self.sort != SortKey::Command;
+                self.rebuild_rows();
+            }
+            KeyCode::Char('o') => {
+                self.descending = !self.descending;
+                self.rebuild_rows();
+            }
+            KeyCode::Char('i') => {
+                self.include_interrupted = !self.include_interrupted;
+                self.rebuild_rows();
+            }
+            KeyCode::Char('r') => return Action::Refresh,
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn edit_filter(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(c) => self.filter.push(c),
+            KeyCode::Backspace => {
+                self.filter.pop();
+            }
+            KeyCode::Enter => self.editing_filter = false,
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.editing_filter = false;
+            }
+            _ => return,
+        }
+        self.rebuild_rows();
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let current = self.table_state.selected().unwrap_or(0);
+        let last = self.rows.len() - 1;
+        let target = current.saturating_add_signed(offset).min(last);
+        self.table_state.select(Some(target));
+    }
+
+    fn rebuild_rows(&mut self) {
+        let selected = self.selected().map(|(command, _stats)| command.clone());
+        let filter = (!self.filter.is_empty()).then(|| self.filter.clone());
+
+        let stats = self
+            .stats
+            .iter()
+            .map(|(command, stats)| (command.clone(), stats.clone()))
+            .collect();
+        self.rows = filter_stats(stats, &filter);
+        sort_stats(&mut self.rows, self.sort, self.include_interrupted);
+        if self.descending {
+            self.rows.reverse();
+        }
+
+        let index = selected
+            .and_then(|selected| {
+                self.rows
+                    .iter()
+                    .position(|(command, _)| *command == selected)
+            })
+            .or_else(|| {
+                let previous = self.table_state.selected().unwrap_or(0);
+                (!self.rows.is_empty()).then(|| previous.min(self.rows.len() - 1))
+            });
+        self.table_state.select(index);
+        if index.is_none() {
+            self.view = View::Table;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn press(app: &mut App, code: KeyCode) -> Action {
+        app.handle_key(KeyEvent::from(code))
+    }
+
+    fn commands(app: &App) -> Vec<&str> {
+        app.rows
+            .iter()
+            .map(|(command, _)| command.as_str())
+            .collect()
+    }
+
+    fn app_with(commands: &[(&str, u64)]) -> App {
+        let stats = commands
+            .iter()
+            .map(|(command, seconds)| {
+                let stats = CommandStats {
+                    total_duration: Duration::from_secs(*seconds),
+                    success_count: 1,
+                    ..Default::default()
+                };
+                (command.to_string(), stats)
+            })
+            .collect();
+        let mut app = App::new(SortKey::Total, false);
+        app.update(stats, Vec::new());
+        app
+    }
+
+    #[test]
+    fn largest_values_come_first_and_order_can_be_reversed() {
+        let mut app = app_with(&[("ls", 1), ("cargo build", 30), ("make", 10)]);
+        assert_eq!(commands(&app), vec!["cargo build", "make", "ls"]);
+
+        press(&mut app, KeyCode::Char('o'));
+        assert_eq!(commands(&app), vec!["ls", "make", "cargo build"]);
+    }
+
+    #[test]
+    fn sort_key_cycles_through_the_columns() {
+        let mut app = app_with(&[("b", 1), ("a", 2)]);
+        for expected in [
+            SortKey::Mean,
+            SortKey::Count,
+            SortKey::Last,
+            SortKey::Command,
+        ] {
+            press(&mut app, KeyCode::Char('s'));
+            assert_eq!(app.sort, expected);
+        }
+        assert_eq!(commands(&app), vec!["a", "b"]);
+
+        press(&mut app, KeyCode::Char('s'));
+        assert_eq!(app.sort, SortKey::Total);
+    }
+
+    #[test]
+    fn typing_a_filter_narrows_the_rows_live() {
+        let mut app = app_with(&[("git push", 1), ("git status", 2), ("ls", 3)]);
+
+        press(&mut app, KeyCode::Char('/'));
+        for c in "GIT P".chars() {
+            press(&mut app, KeyCode::Char(c));
+            assert!(
+                app.rows
+                    .iter()
+                    .all(|(command, _)| command.starts_with("git"))
+            );
+        }
+        assert_eq!(commands(&app), vec!["git push"]);
+
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(press(&mut app, KeyCode::Char('q')), Action::Quit);
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(app.rows.len(), 3);
+        assert_eq!(press(&mut app, KeyCode::Esc), Action::Quit);
+    }
+
+    #[test]
+    fn selection_follows_its_command_across_refreshes() {
+        let mut app = app_with(&[("a", 1), ("b", 2), ("c", 3)]);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.selected().unwrap().0, "b");
+
+        let mut stats = app.stats.clone();
+        stats.get_mut("a").unwrap().total_duration = Duration::from_secs(60);
+        app.update(stats, Vec::new());
+        assert_eq!(app.selected().unwrap().0, "b");
+        assert_eq!(app.table_state.selected(), Some(2));
+    }
+
+    #[test]
+    fn details_open_for_the_selected_command() {
+        let mut app = app_with(&[("a", 1)]);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.view, View::Detail);
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(app.view, View::Table);
+
+        let mut empty = app_with(&[]);
+        press(&mut empty, KeyCode::Enter);
+        assert_eq!(empty.view, View::Table);
+    }
+}
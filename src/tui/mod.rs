@@ -0,0 +1,90 @@
+//! `t_trace top`: an interactive dashboard that refreshes itself from the daemon.
+
+mod app;
+mod ui;
+
+pub use app::App;
+
+use app::Action;
+
+use crate::client::{Client, GetInFlight, GetStats};
+use crate::dto::{CommandStats, RunningCommand};
+
+use anyhow::Result;
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+/// How long the event reader waits for input before checking whether the dashboard closed.
+const EVENT_POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Takes over the terminal until the user quits, refreshing the data every `interval`.
+pub async fn run(mut app: App, interval: Duration) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app, interval).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    interval: Duration,
+) -> Result<()> {
+    let mut events = spawn_event_reader();
+    let mut refresh = tokio::time::interval(interval);
+    refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        terminal.draw(|frame| ui::draw(frame, app))?;
+
+        tokio::select! {
+            _ = refresh.tick() => match fetch().await {
+                Ok((stats, running)) => app.update(stats, running),
+                Err(e) => app.set_error(&e),
+            },
+            event = events.recv() => match event {
+                Some(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    match app.handle_key(key) {
+                        Action::Quit => return Ok(()),
+                        Action::Refresh => refresh.reset_immediately(),
+                        Action::None => {}
+                    }
+                }
+                Some(_) => {}
+                None => anyhow::bail!("Stopped receiving terminal events"),
+            },
+        }
+    }
+}
+
+async fn fetch() -> Result<(HashMap<String, CommandStats>, Vec<RunningCommand>)> {
+    let client = Client::new();
+    let stats = client.send(GetStats).await?.commands;
+    let running = client.send(GetInFlight).await?;
+    Ok((stats, running))
+}
+
+/// Forwards terminal events from a separate thread, as crossterm only offers blocking reads
+/// without its optional async support.
+fn spawn_event_reader() -> mpsc::UnboundedReceiver<Event> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while !sender.is_closed() {
+            match event::poll(EVENT_POLL_TIMEOUT) {
+                Ok(true) => match event::read() {
+                    Ok(event) => {
+                        let _ = sender.send(event);
+                    }
+                    Err(_) => break,
+                },
+                Ok(false) => {}
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
@@ -0,0 +1,372 @@
+use super::app::{App, View};
+
+use crate::charts::format_bound;
+use crate::config::SortKey;
+use crate::dto::{CommandStats, DurationHistogram, exit_code_label};
+use crate::handlers::format_time_ago;
+
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Bar, BarChart, BarGroup, Block, Cell, Paragraph, Row, Table};
+use std::time::SystemTime;
+
+/// Most running commands listed before the pane stops growing.
+const MAX_RUNNING_ROWS: usize = 5;
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let running_rows = app.running.len().clamp(1, MAX_RUNNING_ROWS);
+    let [header, main, running, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(5),
+        Constraint::Length(running_rows as u16 + 3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_header(frame, app, header);
+    match app.view {
+        View::Table => draw_table(frame, app, main),
+        View::Detail => draw_detail(frame, app, main),
+    }
+    draw_running(frame, app, running);
+    draw_footer(frame, app, footer);
+}
+
+fn draw_header(frame: &mut Frame, app: &App, area: Rect) {
+    let order = if app.descending { "↓" } else { "↑" };
+    let mut spans = vec![
+        Span::from(" t_trace top ").bold().reversed(),
+        Span::from(format!(
+            "  {} commands · {} running · sorted by {} {}",
+            app.rows.len(),
+            app.running.len(),
+            sort_label(app.sort),
+            order
+        )),
+    ];
+    if app.include_interrupted {
+        spans.push(Span::from(" · interrupted runs included"));
+    }
+    if let Some(error) = &app.error {
+        spans.push(Span::from(format!("  {}", error)).fg(Color::Red));
+    }
+    frame.render_widget(Line::from(spans), area);
+}
+
+fn draw_table(frame: &mut Frame, app: &mut App, area: Rect) {
+    let now = SystemTime::now();
+    let columns = [
+        (Some(SortKey::Command), "Command"),
+        (Some(SortKey::Count), "Runs"),
+        (None, "Fail"),
+        (Some(SortKey::Total), "Total"),
+        (Some(SortKey::Mean), "Mean"),
+        (Some(SortKey::Last), "Last"),
+        (None, "Last Run"),
+    ];
+    let header = Row::new(columns.map(|(key, title)| {
+        let cell = Cell::from(title).bold();
+        if key == Some(app.sort) {
+            cell.underlined()
+        } else {
+            cell
+        }
+    }));
+
+    let include_interrupted = app.include_interrupted;
+    let rows = app.rows.iter().map(|(command, stats)| {
+        let fail_style = if stats.fail_count > 0 {
+            Style::new().fg(Color::Red)
+        } else {
+            Style::new()
+        };
+        Row::new([
+            Cell::from(command.as_str()).fg(Color::Yellow),
+            Cell::from(stats.run_count().to_string()),
+            Cell::from(stats.fail_count.to_string()).style(fail_style),
+            Cell::from(format!("{:.1?}", stats.total_time(include_interrupted))),
+            Cell::from(format!("{:.1?}", stats.mean_duration(include_interrupted))),
+            Cell::from(format!("{:.1?}", stats.last_run_duration)),
+            Cell::from(
+                stats
+                    .last_run_at
+                    .map_or("-".to_string(), |time| format_time_ago(time, now)),
+            ),
+        ])
+    });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(1),
+            Constraint::Length(7),
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(9),
+        ],
+    )
+    .header(header)
+    .block(Block::bordered().title(" Commands "))
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, area, &mut app.table_state);
+}
+
+fn draw_detail(frame: &mut Frame, app: &App, area: Rect) {
+    let Some((command, stats)) = app.selected() else {
+        return;
+    };
+    let block = Block::bordered().title(format!(" {} ", command));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let summary = detail_lines(stats, app.include_interrupted, SystemTime::now());
+    let [summary_area, histogram_area] = Layout::vertical([
+        Constraint::Length(summary.len() as u16 + 1),
+        Constraint::Min(3),
+    ])
+    .areas(inner);
+    frame.render_widget(Paragraph::new(summary), summary_area);
+
+    let bars = histogram_bars(&stats.duration_histogram);
+    if bars.is_empty() {
+        frame.render_widget(
+            Paragraph::new("No completed runs to draw a histogram of.").italic(),
+            histogram_area,
+        );
+        return;
+    }
+    let chart = BarChart::default()
+        .block(Block::bordered().title(" Run durations "))
+        .data(BarGroup::default().bars(&bars))
+        .bar_width(6)
+        .bar_gap(1)
+        .bar_style(Style::new().fg(Color::Cyan));
+    frame.render_widget(chart, histogram_area);
+}
+
+fn detail_lines(
+    stats: &CommandStats,
+    include_interrupted: bool,
+    now: SystemTime,
+) -> Vec<Line<'static>> {
+    let percentile = |quantile| {
+        stats
+            .duration_histogram
+            .quantile(quantile)
+            .map_or("-".to_string(), |bound| {
+                format!("< {}", format_bound(bound))
+            })
+    };
+    let fail_rate = stats
+        .fail_rate()
+        .map_or("-".to_string(), |rate| format!("{:.1}%", rate * 100.0));
+    let exit_codes = stats
+        .top_exit_codes(3)
+        .into_iter()
+        .map(|(code, count)| format!("{} ×{}", exit_code_label(code), count))
+        .collect::<Vec<_>>();
+    let ago =
+        |time: Option<SystemTime>| time.map_or("-".to_string(), |time| format_time_ago(time, now));
+
+    vec![
+        Line::from(format!(
+            "Runs: {}  (success {}, failed {}, signal {}, interrupted {}, abandoned {})",
+            stats.run_count(),
+            stats.success_count,
+            stats.fail_count,
+            stats.signal_count,
+            stats.interrupted_count,
+            stats.abandoned_count
+        )),
+        Line::from(format!(
+            "Total: {:.3?}  Mean: {:.3?}  Last: {:.3?}",
+            stats.total_time(include_interrupted),
+            stats.mean_duration(include_interrupted),
+            stats.last_run_duration
+        )),
+        Line::from(format!(
+            "p50: {}  p90: {}  p99: {}",
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99)
+        )),
+        Line::from(format!(
+            "Fail rate: {}  Top exit codes: {}",
+            fail_rate,
+            if exit_codes.is_empty() {
+                "-".to_string()
+            } else {
+                exit_codes.join(", ")
+            }
+        )),
+        Line::from(format!(
+            "Last run: {}  Last failure: {}",
+            ago(stats.last_run_at),
+            ago(stats.last_failure_at)
+        )),
+    ]
+}
+
+/// One bar per bucket from the shortest to the longest recorded duration, empty ones included.
+fn histogram_bars(histogram: &DurationHistogram) -> Vec<Bar<'static>> {
+    histogram
+        .span()
+        .into_iter()
+        .map(|(bucket, count)| {
+            Bar::default().value(count).label(Line::from(format!(
+                "<{}",
+                format_bound(DurationHistogram::upper_bound(bucket))
+            )))
+        })
+        .collect()
+}
+
+fn draw_running(frame: &mut Frame, app: &App, area: Rect) {
+    let block = Block::bordered().title(" Running ");
+    if app.running.is_empty() {
+        frame.render_widget(
+            Paragraph::new("Nothing running.").italic().block(block),
+            area,
+        );
+        return;
+    }
+
+    let rows = app.running.iter().take(MAX_RUNNING_ROWS).map(|running| {
+        Row::new([
+            Cell::from(running.pid.to_string()),
+            Cell::from(format!("{:.0?}", running.elapsed)),
+            Cell::from(match running.job {
+                Some(_job) => format!("{} &", running.command),
+                None => running.command.clone(),
+            })
+            .fg(Color::Yellow),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(Row::new(["Shell PID", "Elapsed", "Command"]).bold())
+    .block(block);
+    frame.render_widget(table, area);
+}
+
+fn draw_footer(frame: &mut Frame, app: &App, area: Rect) {
+    let line = if app.editing_filter {
+        Line::from(vec![
+            Span::from(format!(" /{}", app.filter)),
+            Span::from("█").slow_blink(),
+            Span::from("  Enter keep · Esc clear").dark_gray(),
+        ])
+    } else {
+        let mut spans = Vec::new();
+        if !app.filter.is_empty() {
+            spans.push(Span::from(format!(" filter: {} ", app.filter)).reversed());
+        }
+        spans.push(
+            Span::from(
+                " ↑↓ select · Enter details · / filter · s sort · o order · i interrupted · r refresh · q quit",
+            )
+            .dark_gray(),
+        );
+        Line::from(spans)
+    };
+    frame.render_widget(line, area);
+}
+
+fn sort_label(sort: SortKey) -> &'static str {
+    match sort {
+        SortKey::Total => "total",
+        SortKey::Mean => "mean",
+        SortKey::Count => "count",
+        SortKey::Last => "last",
+        SortKey::Command => "command",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::RunningCommand;
+    use ratatui::Terminal;
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn render(app: &mut App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| draw(frame, app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn sample_app() -> App {
+        let mut histogram = DurationHistogram::default();
+        for millis in [300, 400, 2_500] {
+            histogram.record(Duration::from_millis(millis));
+        }
+        let stats = CommandStats {
+            total_duration: Duration::from_millis(3_200),
+            success_count: 2,
+            fail_count: 1,
+            exit_codes: [(101, 1)].into(),
+            duration_histogram: histogram,
+            ..Default::default()
+        };
+        let running = vec![RunningCommand {
+            pid: 4242,
+            job: None,
+            command: "cargo watch".to_string(),
+            elapsed: Duration::from_secs(90),
+        }];
+
+        let mut app = App::new(SortKey::Total, false);
+        app.update(HashMap::from([("cargo test".to_string(), stats)]), running);
+        app
+    }
+
+    #[test]
+    fn table_view_lists_commands_and_running_commands() {
+        let screen = render(&mut sample_app());
+        assert!(screen.contains("1 commands · 1 running · sorted by total ↓"));
+        assert!(screen.contains("cargo test"));
+        assert!(screen.contains("3.2s"));
+        assert!(screen.contains("4242"));
+        assert!(screen.contains("cargo watch"));
+    }
+
+    #[test]
+    fn detail_view_shows_percentiles_and_histogram() {
+        let mut app = sample_app();
+        app.handle_key(KeyEvent::from(KeyCode::Enter));
+        let screen = render(&mut app);
+
+        assert!(screen.contains("Runs: 3"));
+        assert!(screen.contains("p50: < 512ms"));
+        assert!(screen.contains("101 ×1"));
+        assert!(screen.contains("Run durations"));
+        assert!(screen.contains("<512ms"));
+        assert!(screen.contains("<4s"));
+    }
+
+    #[test]
+    fn histogram_has_a_bar_per_bucket_between_shortest_and_longest() {
+        let histogram: DurationHistogram = [(3, 1), (6, 2)].into_iter().collect();
+        assert_eq!(histogram_bars(&histogram).len(), 4);
+        assert!(histogram_bars(&DurationHistogram::default()).is_empty());
+    }
+}